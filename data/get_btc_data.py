@@ -31,12 +31,15 @@ def fetch_data(symbol, timeframe='1d', since='2010-01-01T00:00:00Z'):
     df.set_index('datetime', inplace=True)
     return df
 
-def save_to_json(df, filename):
-    # format: list of {"YYYYMMDD": close}
+def save_to_json(df, filename, ohlcv=False):
+    # format: list of {"YYYYMMDD": close}, or {"YYYYMMDD": {"open": .., "high": .., "low": .., "close": .., "volume": ..}}
     records = []
     for dt, row in df.iterrows():
         key = dt.strftime('%Y%m%d')
-        value = float(row['close'])
+        if ohlcv:
+            value = {col: float(row[col]) for col in ['open', 'high', 'low', 'close', 'volume']}
+        else:
+            value = float(row['close'])
         records.append({key: value})
     with open(filename, 'w') as f:
         json.dump(records, f, indent=2)
@@ -0,0 +1,33 @@
+use sistra::{
+    cex::{CexClient, binance::BinanceClient},
+    config::Config,
+    logger::setup_logger,
+};
+
+//  cargo r --example binance -- --config ./examples/config.toml
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cfg = Config::load_from_args()?;
+    setup_logger(cfg.log_level);
+    let pair = cfg.pair();
+
+    let binance = BinanceClient::new(cfg.cex_api_key, cfg.cex_api_secret, cfg.cex_demo);
+
+    let price = binance.get_price(&pair).await?;
+    println!("Binance actual price: {:.8} {}", price, pair);
+
+    let historical = binance.get_historical(&pair, cfg.timeframe, 365).await?;
+    print_historical(&historical);
+
+    println!("Done");
+    Ok(())
+}
+
+fn print_historical(historical: &[f64]) {
+    let today = chrono::Utc::now().date_naive();
+    for (i, price) in historical.iter().enumerate() {
+        let date = today - chrono::Duration::days((historical.len() - i) as i64);
+        println!("  Dátum: {date}, Cena: {price:.2}");
+    }
+}
@@ -6,13 +6,14 @@ use sistra::{cex::{kucoin::KucoinClient, CexClient}, config::Config, logger::set
 async fn main() -> anyhow::Result<()> {
     let cfg = Config::load_from_args()?;
     setup_logger(cfg.log_level);
+    let pair = cfg.pair();
 
     let kucoin = KucoinClient::new("api".to_string(), "sec".to_string(), "pass".to_string());
 
-    let price = kucoin.get_price(&cfg.coin).await?;
-    println!("OKX actual price: {:.8} {}", price, cfg.coin.symbol());
+    let price = kucoin.get_price(&pair).await?;
+    println!("OKX actual price: {:.8} {}", price, pair);
 
-    let historical = kucoin.get_historical(&cfg.coin, 365).await?;
+    let historical = kucoin.get_historical(&pair, cfg.timeframe, 365).await?;
     print_historical(&historical);
 
     println!("Done");    
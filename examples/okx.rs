@@ -2,7 +2,7 @@ use sistra::{
     cex::{CexClient, okx::OkxClient},
     config::Config,
     logger::setup_logger,
-    order::Side,
+    order::{OrderType, Side},
 };
 
 //  cargo r --example okx -- --config ./examples/config.toml
@@ -11,17 +11,23 @@ use sistra::{
 async fn main() -> anyhow::Result<()> {
     let cfg = Config::load_from_args()?;
     setup_logger(cfg.log_level);
+    let pair = cfg.pair();
 
-    let okx = OkxClient::new(cfg.cex_api_key, cfg.cex_api_secret, cfg.cex_api_passphrase, true);
+    let okx = OkxClient::new(
+        cfg.cex_api_key,
+        cfg.cex_api_secret,
+        cfg.cex_api_passphrase,
+        cfg.cex_demo,
+    );
 
-    let price = okx.get_price(&cfg.coin).await?;
-    println!("OKX actual price: {:.8} {}", price, cfg.coin.symbol());
+    let price = okx.get_price(&pair).await?;
+    println!("OKX actual price: {:.8} {}", price, pair);
 
-    let historical = okx.get_historical(&cfg.coin, 365).await?;
+    let historical = okx.get_historical(&pair, cfg.timeframe, 365).await?;
     print_historical(&historical);
 
-    //let res = okx.put_order(&cfg.coin, Side::Buy, 0.01, None).await?;
-    let res = okx.put_order(&cfg.coin, Side::Sell, 0.002, None).await?;
+    //let res = okx.put_order(&pair, Side::Buy, 0.01, OrderType::Market, None).await?;
+    let res = okx.put_order(&pair, Side::Sell, 0.002, OrderType::Market, None).await?;
     println!("Put order result: {res:?}");
 
     println!("Done");
@@ -0,0 +1,49 @@
+use anyhow::Result;
+use chrono::NaiveDate;
+use log::warn;
+
+use crate::{candle::Timeframe, cex::simulate::SimulateClient, config::Config, engine::Engine, metrics::Metrics};
+
+/// Backtest of the configured strategy over the days between `from` and `to` of `simulate_file`. The simulated
+/// exchange is the clock: every cycle fetches the price of the next day, there is no timer and nothing depends on
+/// the current date.
+pub async fn run(cfg: Config, from: NaiveDate, to: NaiveDate) -> Result<()> {
+    let (cfg, client) = prepare(cfg, from, to)?;
+    let (first, last) = client.date_range();
+    println!("Backtest {first} - {last}");
+    let cycles = cycles(&client);
+
+    let mut engine = Engine::new(cfg, Box::new(client)).await?;
+    engine.run_backtest(cycles).await?;
+    println!();
+    Ok(())
+}
+
+/// Same backtest without any output, returns only the metrics of the run
+pub async fn evaluate(cfg: Config, from: NaiveDate, to: NaiveDate) -> Result<Metrics> {
+    let (cfg, client) = prepare(cfg, from, to)?;
+    let cycles = cycles(&client);
+
+    let mut engine = Engine::new(cfg, Box::new(client)).await?;
+    engine.run_silent(cycles).await
+}
+
+fn prepare(mut cfg: Config, from: NaiveDate, to: NaiveDate) -> Result<(Config, SimulateClient)> {
+    if cfg.timeframe != Timeframe::Day1 {
+        anyhow::bail!("Backtest supports only the 1d timeframe, not {}", cfg.timeframe);
+    }
+    if cfg.use_fear_index {
+        warn!("Fear & Greed index has no history, it is not used in the backtest");
+        cfg.use_fear_index = false;
+    }
+
+    let client = SimulateClient::with_range(cfg.simulate_file.clone(), from, to, cfg.initial_btc, cfg.initial_usd)?;
+    Ok((cfg, client))
+}
+
+/// The first day gives the starting price, every other one is a cycle
+fn cycles(client: &SimulateClient) -> u32 {
+    client.remaining_days().saturating_sub(1)
+}
+
+// eof
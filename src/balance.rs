@@ -57,6 +57,13 @@ impl Balance {
     }
 }
 
+/// Available balances of the traded coin and USDT on the exchange account
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExchangeBalance {
+    pub btc_balance: f64,
+    pub usd_balance: f64,
+}
+
 // impl std::fmt::Display for Balance {
 //     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 //         write!(f, "BTC: {:.8}, USD: {:.2}", self.btc_balance, self.usd_balance)
@@ -0,0 +1,217 @@
+use serde::Serialize;
+
+use crate::{
+    balance::Balance,
+    candle::Timeframe,
+    metrics::{Metrics, max_drawdown},
+    report::EquityPoint,
+};
+
+// the rebalanced portfolio is brought back to 50/50 this often
+const REBALANCE_DAYS: f64 = 30.0;
+const DCA_DAYS: f64 = 7.0;
+
+/// Passive portfolio which the strategy is compared with. All of them start with the value of the initial balance at
+/// the initial price and trade without fees at the prices of the cycles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BenchmarkKind {
+    /// Everything in the coin from the start
+    BuyAndHold,
+    /// Half in the coin and half in USD, never traded
+    Static5050,
+    /// Half in the coin and half in USD, rebalanced every 30 days
+    Rebalance,
+    /// Starts in USD and buys the coin for an equal part of it every 7 days
+    WeeklyDca,
+}
+
+impl BenchmarkKind {
+    pub const ALL: [BenchmarkKind; 4] = [
+        BenchmarkKind::BuyAndHold,
+        BenchmarkKind::Static5050,
+        BenchmarkKind::Rebalance,
+        BenchmarkKind::WeeklyDca,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BenchmarkKind::BuyAndHold => "Buy & hold",
+            BenchmarkKind::Static5050 => "50/50 static",
+            BenchmarkKind::Rebalance => "50/50 rebalanced",
+            BenchmarkKind::WeeklyDca => "Weekly DCA",
+        }
+    }
+
+    /// Values of the portfolio in USD, the initial one and one per price
+    fn values(&self, capital: f64, initial_price: f64, prices: &[f64], timeframe: Timeframe) -> Vec<f64> {
+        let bar_days = timeframe.minutes() as f64 / (24.0 * 60.0);
+        let every = |days: f64| ((days / bar_days).round() as usize).max(1);
+        let (mut coin, mut usd) = match self {
+            BenchmarkKind::BuyAndHold => (capital / initial_price, 0.0),
+            BenchmarkKind::Static5050 | BenchmarkKind::Rebalance => (capital / 2.0 / initial_price, capital / 2.0),
+            BenchmarkKind::WeeklyDca => (0.0, capital),
+        };
+        // DCA buys at the start and then every week, so that all is invested by the last purchase
+        let dca_step = every(DCA_DAYS);
+        let dca_part = capital / (prices.len() / dca_step + 1) as f64;
+        if *self == BenchmarkKind::WeeklyDca {
+            coin += dca_part / initial_price;
+            usd -= dca_part;
+        }
+
+        let mut values = Vec::with_capacity(prices.len() + 1);
+        values.push(capital);
+        for (i, &price) in prices.iter().enumerate() {
+            let bar = i + 1;
+            match self {
+                BenchmarkKind::Rebalance if bar.is_multiple_of(every(REBALANCE_DAYS)) => {
+                    let value = coin * price + usd;
+                    coin = value / 2.0 / price;
+                    usd = value / 2.0;
+                }
+                BenchmarkKind::WeeklyDca if bar.is_multiple_of(dca_step) && usd > 0.0 => {
+                    let part = dca_part.min(usd);
+                    coin += part / price;
+                    usd -= part;
+                }
+                _ => {}
+            }
+            values.push(coin * price + usd);
+        }
+        values
+    }
+}
+
+/// Result of the benchmark over the window of the run and the strategy against it
+#[derive(Debug, Clone, Serialize)]
+pub struct Benchmark {
+    pub name: &'static str,
+    pub return_pct: f64,
+    pub max_drawdown_pct: f64,
+    /// Return of the strategy minus the return of the benchmark, in percentage points
+    pub excess_return_pct: f64,
+    /// Max drawdown of the strategy minus the one of the benchmark, negative is better
+    pub drawdown_diff_pct: f64,
+}
+
+impl Benchmark {
+    pub fn line(&self) -> String {
+        format!(
+            "{}: {:.2}%, max drawdown {:.2}% => excess return {:+.2}%, drawdown {:+.2}%",
+            self.name, self.return_pct, self.max_drawdown_pct, self.excess_return_pct, self.drawdown_diff_pct
+        )
+    }
+}
+
+/// Benchmarks over the prices of the equity curve compared with the metrics of the strategy
+pub fn compare(initial: &Balance, equity: &[EquityPoint], timeframe: Timeframe, metrics: &Metrics) -> Vec<Benchmark> {
+    let initial_price = initial._btc_price;
+    let capital = initial.btc_balance * initial_price + initial.usd_balance;
+    if initial_price <= 0.0 || capital <= 0.0 {
+        return Vec::new();
+    }
+    let prices: Vec<f64> = equity.iter().map(|point| point.price).collect();
+
+    BenchmarkKind::ALL
+        .iter()
+        .map(|kind| {
+            let values = kind.values(capital, initial_price, &prices, timeframe);
+            let return_pct = (values.last().copied().unwrap_or(capital) / capital - 1.0) * 100.0;
+            let max_drawdown_pct = max_drawdown(&values).0 * 100.0;
+            Benchmark {
+                name: kind.name(),
+                return_pct,
+                max_drawdown_pct,
+                excess_return_pct: metrics.total_return_pct - return_pct,
+                drawdown_diff_pct: metrics.max_drawdown_pct - max_drawdown_pct,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_all_close(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len(), "{actual:?}");
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-9, "{actual:?} != {expected:?}");
+        }
+    }
+
+    /// Daily values of 1000 USD of capital starting at the price 100
+    fn values(kind: BenchmarkKind, prices: &[f64]) -> Vec<f64> {
+        kind.values(1000.0, 100.0, prices, Timeframe::Day1)
+    }
+
+    #[test]
+    fn buy_and_hold() {
+        let values = values(BenchmarkKind::BuyAndHold, &[200.0, 50.0, 100.0]);
+        assert_all_close(&values, &[1000.0, 2000.0, 500.0, 1000.0]);
+        assert!((max_drawdown(&values).0 - 0.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn static_half_and_half() {
+        let values = values(BenchmarkKind::Static5050, &[200.0, 50.0, 100.0]);
+        assert_all_close(&values, &[1000.0, 1500.0, 750.0, 1000.0]);
+        assert!((max_drawdown(&values).0 - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rebalanced_on_the_30th_day() {
+        // 5 coins and 500 USD are worth 1500 at 200 on day 30 and become 3.75 coins and 750 USD
+        let mut prices = vec![100.0; 29];
+        prices.extend([200.0, 100.0]);
+        let values = values(BenchmarkKind::Rebalance, &prices);
+        assert_all_close(&values[29..], &[1000.0, 1500.0, 1125.0]);
+        assert!((max_drawdown(&values).0 - 0.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn weekly_dca() {
+        // a third is bought at the start (100), on day 7 (50) and on day 14 (200)
+        let mut prices = vec![100.0; 14];
+        prices[6] = 50.0;
+        prices[13] = 200.0;
+        let values = values(BenchmarkKind::WeeklyDca, &prices);
+        let third = 1000.0 / 3.0;
+        assert_all_close(
+            &[values[0], values[6], values[7], values[8], values[14]],
+            &[
+                1000.0,
+                1000.0,
+                10.0 * 50.0 + third,
+                10.0 * 100.0 + third,
+                35.0 / 3.0 * 200.0,
+            ],
+        );
+        assert!((max_drawdown(&values).0 - 1.0 / 6.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn compare_with_the_strategy() {
+        let initial = Balance::new(10.0, 0.0, 100.0);
+        let equity: Vec<EquityPoint> = [200.0, 50.0, 100.0]
+            .iter()
+            .enumerate()
+            .map(|(i, &price)| EquityPoint::new(String::new(), i as u32 + 1, price, 10.0, 0.0, (0.0, 0.0), (0.0, 0.0)))
+            .collect();
+        let metrics = Metrics {
+            total_return_pct: 10.0,
+            max_drawdown_pct: 20.0,
+            ..Default::default()
+        };
+        let benchmarks = compare(&initial, &equity, Timeframe::Day1, &metrics);
+        assert_eq!(benchmarks.len(), BenchmarkKind::ALL.len());
+        let hold = &benchmarks[0];
+        assert_eq!(hold.name, "Buy & hold");
+        assert!(hold.return_pct.abs() < 1e-9 && (hold.max_drawdown_pct - 75.0).abs() < 1e-9);
+        assert!((hold.excess_return_pct - 10.0).abs() < 1e-9);
+        assert!((hold.drawdown_diff_pct + 55.0).abs() < 1e-9);
+        assert!(compare(&Balance::new(0.0, 0.0, 100.0), &equity, Timeframe::Day1, &metrics).is_empty());
+    }
+}
+
+// eof
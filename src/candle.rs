@@ -0,0 +1,224 @@
+use chrono::{DateTime, Datelike, Duration, Utc};
+use clap::ValueEnum;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// One bar of the price history (OHLCV)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Candle {
+    /// Start of the bar, milliseconds since the epoch
+    pub ts: i64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    /// Traded amount in the base asset
+    pub volume: f64,
+}
+
+/// Price of the candle which the strategies consume
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, ValueEnum)]
+pub enum PriceField {
+    Open,
+    High,
+    Low,
+    Close,
+    /// Average of the high and low price
+    #[default]
+    Mid,
+    /// Average of the high, low and close price
+    Typical,
+}
+
+impl Candle {
+    /// Candle of the data which has a single price per bar
+    pub fn flat(ts: i64, price: f64) -> Self {
+        Candle {
+            ts,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume: 0.0,
+        }
+    }
+
+    pub fn mid(&self) -> f64 {
+        (self.high + self.low) / 2.0
+    }
+
+    pub fn price(&self, field: PriceField) -> f64 {
+        match field {
+            PriceField::Open => self.open,
+            PriceField::High => self.high,
+            PriceField::Low => self.low,
+            PriceField::Close => self.close,
+            PriceField::Mid => self.mid(),
+            PriceField::Typical => (self.high + self.low + self.close) / 3.0,
+        }
+    }
+}
+
+/// Length of one candle, which is also the time between the trading cycles
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, ValueEnum)]
+pub enum Timeframe {
+    #[serde(rename = "1m")]
+    #[value(name = "1m")]
+    Min1,
+    #[serde(rename = "5m")]
+    #[value(name = "5m")]
+    Min5,
+    #[serde(rename = "1h")]
+    #[value(name = "1h")]
+    Hour1,
+    #[serde(rename = "4h")]
+    #[value(name = "4h")]
+    Hour4,
+    #[default]
+    #[serde(rename = "1d")]
+    #[value(name = "1d")]
+    Day1,
+    #[serde(rename = "1w")]
+    #[value(name = "1w")]
+    Week1,
+}
+
+impl Timeframe {
+    pub fn minutes(&self) -> i64 {
+        match self {
+            Timeframe::Min1 => 1,
+            Timeframe::Min5 => 5,
+            Timeframe::Hour1 => 60,
+            Timeframe::Hour4 => 4 * 60,
+            Timeframe::Day1 => 24 * 60,
+            Timeframe::Week1 => 7 * 24 * 60,
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::minutes(self.minutes())
+    }
+
+    /// Start of the bar which contains `time`. Weekly bars start on Monday, like on the exchanges.
+    pub fn bar_start(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        let day_start = time.date_naive().and_hms_opt(0, 0, 0).expect("valid time").and_utc();
+        match self {
+            Timeframe::Week1 => day_start - Duration::days(time.weekday().num_days_from_monday() as i64),
+            _ => {
+                let bar_ms = self.duration().num_milliseconds();
+                let since_day_start = (time - day_start).num_milliseconds();
+                day_start + Duration::milliseconds(since_day_start / bar_ms * bar_ms)
+            }
+        }
+    }
+
+    /// Time window of the last `bars` finished bars before `now`, as (start of the first bar, end of the last bar)
+    pub fn window(&self, now: DateTime<Utc>, bars: u32) -> (DateTime<Utc>, DateTime<Utc>) {
+        let end = self.bar_start(now);
+        (end - self.duration() * bars as i32, end)
+    }
+}
+
+impl fmt::Display for Timeframe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Timeframe::Min1 => "1m",
+            Timeframe::Min5 => "5m",
+            Timeframe::Hour1 => "1h",
+            Timeframe::Hour4 => "4h",
+            Timeframe::Day1 => "1d",
+            Timeframe::Week1 => "1w",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Series of the chosen price of the candles
+pub fn prices(candles: &[Candle], field: PriceField) -> Vec<f64> {
+    candles.iter().map(|candle| candle.price(field)).collect()
+}
+
+/// Sorts the candles assembled from several pages oldest first and drops the duplicates. Missing bars (the
+/// exchange skips bars without trades) are only reported.
+pub fn merge_pages(mut candles: Vec<Candle>, timeframe: Timeframe) -> Vec<Candle> {
+    candles.sort_by_key(|candle| candle.ts);
+    candles.dedup_by_key(|candle| candle.ts);
+
+    let bar_ms = timeframe.duration().num_milliseconds();
+    let gaps: Vec<(i64, i64)> = candles
+        .windows(2)
+        .map(|pair| (pair[0].ts, (pair[1].ts - pair[0].ts) / bar_ms - 1))
+        .filter(|(_, missing)| *missing > 0)
+        .collect();
+    if let Some((after_ts, _)) = gaps.first() {
+        let missing: i64 = gaps.iter().map(|(_, missing)| missing).sum();
+        warn!(
+            "{missing} {timeframe} candles missing in {} gaps, the first one after {}",
+            gaps.len(),
+            DateTime::from_timestamp_millis(*after_ts).unwrap_or_default()
+        );
+    }
+    candles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(day: u32, hour: u32, min: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, day, hour, min, 0).unwrap()
+    }
+
+    fn candles(ts: &[i64]) -> Vec<Candle> {
+        ts.iter().map(|&ts| Candle::flat(ts, ts as f64)).collect()
+    }
+
+    #[test]
+    fn window_ends_at_the_last_finished_bar() {
+        // 2024-01-10 is a Wednesday
+        assert_eq!(
+            Timeframe::Hour1.window(at(10, 13, 30), 3),
+            (at(10, 10, 0), at(10, 13, 0))
+        );
+        // the bar starting right now is not finished yet
+        assert_eq!(
+            Timeframe::Hour1.window(at(10, 13, 0), 3),
+            (at(10, 10, 0), at(10, 13, 0))
+        );
+        assert_eq!(
+            Timeframe::Hour4.window(at(10, 13, 30), 2),
+            (at(10, 4, 0), at(10, 12, 0))
+        );
+        assert_eq!(Timeframe::Day1.window(at(10, 13, 30), 2), (at(8, 0, 0), at(10, 0, 0)));
+        assert_eq!(Timeframe::Week1.window(at(10, 13, 30), 1), (at(1, 0, 0), at(8, 0, 0)));
+        assert_eq!(
+            Timeframe::Min5.window(at(10, 13, 34), 0),
+            (at(10, 13, 30), at(10, 13, 30))
+        );
+    }
+
+    #[test]
+    fn merge_overlapping_pages() {
+        // newest first pages which share their boundary candles, like the OKX paging returns them
+        let mut pages = candles(&[5, 4, 3]);
+        pages.extend(candles(&[3, 2, 1]));
+        pages.extend(candles(&[2, 1, 0]));
+        let merged = merge_pages(pages, Timeframe::Min1);
+        assert_eq!(merged.iter().map(|c| c.ts).collect::<Vec<_>>(), vec![0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn merge_keeps_gaps_and_single_pages() {
+        let minute = 60_000;
+        let merged = merge_pages(candles(&[3 * minute, 0, minute, minute]), Timeframe::Min1);
+        assert_eq!(
+            merged.iter().map(|c| c.ts).collect::<Vec<_>>(),
+            vec![0, minute, 3 * minute]
+        );
+        assert!(merge_pages(Vec::new(), Timeframe::Day1).is_empty());
+    }
+}
+
+// eof
@@ -0,0 +1,470 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use hmac::{Hmac, Mac};
+use log::debug;
+use reqwest::Method;
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::Value;
+use sha2::Sha256;
+
+use crate::{
+    balance::ExchangeBalance,
+    candle::{self, Candle, Timeframe},
+    cex::{
+        CexClient,
+        error::CexError,
+        http::{HttpClient, RetryPolicy},
+    },
+    instrument::{InstrumentInfo, format_decimal},
+    order::{Order, OrderResponse, OrderStatus, OrderType, Side},
+    pair::Pair,
+};
+
+const BINANCE_LIVE: &str = "https://api.binance.com";
+const BINANCE_TESTNET: &str = "https://testnet.binance.vision";
+const RECV_WINDOW: u64 = 5000;
+// most klines returned by one request
+const CANDLES_LIMIT: usize = 1000;
+// -1003 = too many requests, -1015 = too many new orders
+const RATE_LIMIT_CODES: &[&str] = &["-1003", "-1015"];
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub struct BinanceClient {
+    api_key: String,
+    secret: String,
+    base: String,
+    http: HttpClient,
+}
+
+impl BinanceClient {
+    /// `is_demo` trades on the spot testnet, which has its own API keys
+    pub fn new(api_key: String, secret: String, is_demo: bool) -> Self {
+        BinanceClient {
+            api_key,
+            secret,
+            base: if is_demo { BINANCE_TESTNET } else { BINANCE_LIVE }.to_string(),
+            http: HttpClient::new("binance-rust-client/0.1", RATE_LIMIT_CODES),
+        }
+    }
+
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.http.set_policy(policy);
+        self
+    }
+
+    /// Sends the requests to `base` instead of the exchange (e.g. a local mock server), empty keeps the default
+    pub fn with_base_url(mut self, base: &str) -> Self {
+        if !base.is_empty() {
+            self.base = base.trim_end_matches('/').to_string();
+        }
+        self
+    }
+
+    fn symbol(pair: &Pair) -> String {
+        pair.join("")
+    }
+
+    fn interval(timeframe: Timeframe) -> &'static str {
+        match timeframe {
+            Timeframe::Min1 => "1m",
+            Timeframe::Min5 => "5m",
+            Timeframe::Hour1 => "1h",
+            Timeframe::Hour4 => "4h",
+            Timeframe::Day1 => "1d",
+            Timeframe::Week1 => "1w",
+        }
+    }
+
+    /// Signature is hex encoded HMAC-SHA256 of the query string
+    fn sign(&self, query: &str) -> String {
+        let mut mac = HmacSha256::new_from_slice(self.secret.as_bytes()).expect("HMAC can take key of any size");
+        mac.update(query.as_bytes());
+        mac.finalize().into_bytes().iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    /// Appends timestamp and signature to the query string
+    fn signed_query(&self, params: &str) -> String {
+        let ts = Utc::now().timestamp_millis();
+        let query = if params.is_empty() {
+            format!("recvWindow={RECV_WINDOW}&timestamp={ts}")
+        } else {
+            format!("{params}&recvWindow={RECV_WINDOW}&timestamp={ts}")
+        };
+        let signature = self.sign(&query);
+        format!("{query}&signature={signature}")
+    }
+
+    /// Errors are returned as `{"code": .., "msg": ..}` instead of the expected response
+    fn parse<T: DeserializeOwned>(json: Value) -> Result<T> {
+        if let Ok(err) = BinanceError::deserialize(&json) {
+            check_code(err.code, &err.msg)?;
+        }
+        Ok(serde_json::from_value(json)?)
+    }
+
+    async fn get_public<T: DeserializeOwned>(&self, endpoint: &str, query: &str) -> Result<T> {
+        let url = format!("{}{endpoint}?{query}", self.base);
+        debug!("GET {}", url);
+        Self::parse(self.http.send_json(|client| client.get(&url)).await?)
+    }
+
+    /// POST requests create orders, they are not replayed after a timeout
+    async fn request_signed<T: DeserializeOwned>(&self, method: Method, endpoint: &str, params: &str) -> Result<T> {
+        debug!("{} {}{}", method, self.base, endpoint);
+        let build = |client: &reqwest::Client| {
+            let url = format!("{}{endpoint}?{}", self.base, self.signed_query(params));
+            client
+                .request(method.clone(), url)
+                .header("X-MBX-APIKEY", &self.api_key)
+        };
+        let json = if method == Method::POST {
+            self.http.send_json_once(build).await?
+        } else {
+            self.http.send_json(build).await?
+        };
+        Self::parse(json)
+    }
+
+    /// Commissions of the order fills as (base fee, quote fee)
+    fn split_commissions<'a>(pair: &Pair, fills: impl Iterator<Item = &'a BinanceFill>) -> Result<(f64, f64)> {
+        let mut btc_fee = 0.0;
+        let mut usd_fee = 0.0;
+        for fill in fills {
+            let commission: f64 = fill.commission.parse()?;
+            if fill.commission_asset == pair.base {
+                btc_fee += commission;
+            } else if fill.commission_asset == pair.quote {
+                usd_fee += commission;
+            } else {
+                debug!("Ignoring commission {} {}", commission, fill.commission_asset);
+            }
+        }
+        Ok((btc_fee, usd_fee))
+    }
+
+    /// Converts the order, fees are not part of it and have to be passed separately
+    fn order(order: &BinanceOrder, btc_fee: f64, usd_fee: f64) -> Result<Order> {
+        let filled_amount: f64 = order.executed_qty.parse()?;
+        let quote_amount: f64 = order.cummulative_quote_qty.parse()?;
+        Ok(Order {
+            id: order.order_id.to_string(),
+            side: match order.side.as_str() {
+                "BUY" => Side::Buy,
+                "SELL" => Side::Sell,
+                other => return Err(anyhow::format_err!("Unknown Binance order side {other}")),
+            },
+            status: match order.status.as_str() {
+                "NEW" => OrderStatus::New,
+                "PARTIALLY_FILLED" => OrderStatus::PartiallyFilled,
+                "FILLED" => OrderStatus::Filled,
+                "CANCELED" | "PENDING_CANCEL" | "EXPIRED" | "EXPIRED_IN_MATCH" => OrderStatus::Cancelled,
+                "REJECTED" => OrderStatus::Rejected,
+                other => return Err(anyhow::format_err!("Unknown Binance order status {other}")),
+            },
+            amount: order.orig_qty.parse()?,
+            price: order.price.parse::<f64>().ok().filter(|price| *price > 0.0),
+            filled_amount,
+            avg_price: if filled_amount > 0.0 {
+                quote_amount / filled_amount
+            } else {
+                0.0
+            },
+            btc_fee,
+            usd_fee,
+        })
+    }
+}
+
+#[async_trait]
+impl CexClient for BinanceClient {
+    async fn get_price(&self, pair: &Pair) -> Result<f64> {
+        let query = format!("symbol={}", Self::symbol(pair));
+        let ticker: BinanceTicker = self.get_public("/api/v3/ticker/price", &query).await?;
+        Ok(ticker.price.parse()?)
+    }
+
+    async fn get_candles(&self, pair: &Pair, timeframe: Timeframe, bars: u32) -> Result<Vec<Candle>> {
+        // End = start of the current bar minus 1 second (i.e., end of the last finished bar)
+        let (start, end) = timeframe.window(Utc::now(), bars);
+        let end_ts = (end - Duration::seconds(1)).timestamp_millis();
+        let start_ts = start.timestamp_millis();
+
+        // Pages are returned oldest first, the next page starts after the newest candle so far
+        let mut candles = Vec::new();
+        let mut start_at = start_ts;
+        loop {
+            let query = format!(
+                "symbol={}&interval={}&startTime={}&endTime={}&limit={CANDLES_LIMIT}",
+                Self::symbol(pair),
+                Self::interval(timeframe),
+                start_at,
+                end_ts,
+            );
+            let data: Vec<Vec<Value>> = self.get_public("/api/v3/klines", &query).await?;
+            let page = data.iter().map(|c| parse_kline(c)).collect::<Result<Vec<Candle>>>()?;
+            let count = page.len();
+            let newest = page.iter().map(|c| c.ts).max();
+            candles.extend(page);
+
+            match newest {
+                Some(newest) if count >= CANDLES_LIMIT && newest < end_ts && newest >= start_at => {
+                    start_at = newest + 1
+                }
+                _ => break,
+            }
+        }
+
+        Ok(candle::merge_pages(candles, timeframe))
+    }
+
+    async fn instrument_info(&self, pair: &Pair) -> Result<InstrumentInfo> {
+        let query = format!("symbol={}", Self::symbol(pair));
+        let exchange_info: BinanceExchangeInfo = self.get_public("/api/v3/exchangeInfo", &query).await?;
+        let symbol = exchange_info
+            .symbols
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Empty Binance exchange info"))?;
+
+        let parse = |value: &Option<String>| -> Result<f64> { Ok(value.as_deref().unwrap_or("0").parse()?) };
+        let mut info = InstrumentInfo::default();
+        for filter in &symbol.filters {
+            match filter.filter_type.as_str() {
+                "LOT_SIZE" => {
+                    info.min_size = parse(&filter.min_qty)?;
+                    info.size_increment = parse(&filter.step_size)?;
+                }
+                "PRICE_FILTER" => info.price_tick = parse(&filter.tick_size)?,
+                "NOTIONAL" | "MIN_NOTIONAL" => info.min_notional = parse(&filter.min_notional)?,
+                _ => {}
+            }
+        }
+        Ok(info)
+    }
+
+    async fn get_balances(&self, pair: &Pair) -> Result<ExchangeBalance> {
+        let account: BinanceAccount = self
+            .request_signed(Method::GET, "/api/v3/account", "omitZeroBalances=true")
+            .await?;
+
+        let mut balance = ExchangeBalance::default();
+        for asset in account.balances {
+            if asset.asset == pair.base {
+                balance.btc_balance = asset.free.parse()?;
+            } else if asset.asset == pair.quote {
+                balance.usd_balance = asset.free.parse()?;
+            }
+        }
+        Ok(balance)
+    }
+
+    async fn put_order(
+        &self,
+        pair: &Pair,
+        side: Side,
+        amount: f64,
+        order_type: OrderType,
+        price: Option<f64>,
+    ) -> Result<OrderResponse> {
+        let endpoint = "/api/v3/order";
+        let mut params = format!(
+            "symbol={}&side={}&type={}&quantity={}&newOrderRespType=FULL",
+            Self::symbol(pair),
+            match side {
+                Side::Buy => "BUY",
+                Side::Sell => "SELL",
+            },
+            match order_type {
+                OrderType::Market => "MARKET",
+                OrderType::PostOnly => "LIMIT_MAKER",
+                OrderType::Limit | OrderType::Ioc => "LIMIT",
+            },
+            format_decimal(amount),
+        );
+        if let Some(price) = order_type.limit_price(price)? {
+            params.push_str(&format!("&price={}", format_decimal(price)));
+        }
+        match order_type {
+            OrderType::Limit => params.push_str("&timeInForce=GTC"),
+            OrderType::Ioc => params.push_str("&timeInForce=IOC"),
+            _ => {}
+        }
+        let order: BinanceOrder = self.request_signed(Method::POST, endpoint, &params).await?;
+
+        let executed_amount: f64 = order.executed_qty.parse()?;
+        if executed_amount <= 0.0 && order_type == OrderType::Market {
+            return Err(anyhow::format_err!(
+                "Binance order {} was not filled (status {})",
+                order.order_id,
+                order.status
+            ));
+        }
+        // resting limit order may be not filled yet
+        let (btc_fee, usd_fee) = Self::split_commissions(pair, order.fills.iter())?;
+        Ok(Self::order(&order, btc_fee, usd_fee)?.response())
+    }
+
+    async fn get_order(&self, pair: &Pair, order_id: &str) -> Result<Order> {
+        let params = format!("symbol={}&orderId={order_id}", Self::symbol(pair));
+        let order: BinanceOrder = self.request_signed(Method::GET, "/api/v3/order", &params).await?;
+        let trades: Vec<BinanceFill> = self.request_signed(Method::GET, "/api/v3/myTrades", &params).await?;
+        let (btc_fee, usd_fee) = Self::split_commissions(pair, trades.iter())?;
+        Self::order(&order, btc_fee, usd_fee)
+    }
+
+    async fn cancel_order(&self, pair: &Pair, order_id: &str) -> Result<()> {
+        let params = format!("symbol={}&orderId={order_id}", Self::symbol(pair));
+        let _: BinanceOrder = self.request_signed(Method::DELETE, "/api/v3/order", &params).await?;
+        Ok(())
+    }
+
+    /// Fees are not fetched for the open orders
+    async fn open_orders(&self, pair: &Pair) -> Result<Vec<Order>> {
+        let params = format!("symbol={}", Self::symbol(pair));
+        let orders: Vec<BinanceOrder> = self.request_signed(Method::GET, "/api/v3/openOrders", &params).await?;
+        orders.iter().map(|order| Self::order(order, 0.0, 0.0)).collect()
+    }
+}
+
+fn check_code(code: i64, msg: &str) -> Result<(), CexError> {
+    let msg = msg.to_string();
+    match code {
+        0 => Ok(()),
+        // unauthorized, invalid signature, timestamp outside of the receive window, invalid API key or permissions
+        -1002 | -1021 | -1022 | -2014 | -2015 => Err(CexError::Auth(msg)),
+        -1003 | -1015 => Err(CexError::RateLimited(msg)),
+        // filter failure (lot size, minimum notional), too many decimals
+        -1013 | -1111 => Err(CexError::InvalidSize(msg)),
+        // the order rejection carries the reason only in the message
+        -2010 if msg.to_lowercase().contains("insufficient balance") => Err(CexError::InsufficientFunds(msg)),
+        // unknown error, disconnected, unexpected response, timeout, server busy
+        -1000 | -1001 | -1006 | -1007 | -1008 => Err(CexError::Network(msg)),
+        _ => Err(CexError::Unknown {
+            code: code.to_string(),
+            msg,
+        }),
+    }
+}
+
+#[derive(Deserialize)]
+struct BinanceError {
+    code: i64,
+    msg: String,
+}
+
+#[derive(Deserialize)]
+struct BinanceTicker {
+    price: String,
+}
+
+#[derive(Deserialize)]
+struct BinanceExchangeInfo {
+    symbols: Vec<BinanceSymbol>,
+}
+
+#[derive(Deserialize)]
+struct BinanceSymbol {
+    filters: Vec<BinanceFilter>,
+}
+
+/// Only the used fields of the filters, each filter type has different ones
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BinanceFilter {
+    filter_type: String,
+    min_qty: Option<String>,
+    step_size: Option<String>,
+    tick_size: Option<String>,
+    min_notional: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BinanceAccount {
+    balances: Vec<BinanceAssetBalance>,
+}
+
+#[derive(Deserialize)]
+struct BinanceAssetBalance {
+    asset: String,
+    free: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BinanceOrder {
+    order_id: i64,
+    side: String,
+    status: String,
+    price: String,
+    orig_qty: String,
+    executed_qty: String,
+    cummulative_quote_qty: String,
+    // only in the FULL response of a new order
+    #[serde(default)]
+    fills: Vec<BinanceFill>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BinanceFill {
+    commission: String,
+    commission_asset: String,
+}
+
+/// Kline as `[open time, open, high, low, close, volume, ...]`, the prices are strings
+fn parse_kline(c: &[Value]) -> Result<Candle> {
+    let parse = |i: usize| -> Result<f64> {
+        let v = c
+            .get(i)
+            .ok_or_else(|| anyhow::anyhow!("Binance kline has no value {i}"))?;
+        v.as_str()
+            .ok_or_else(|| anyhow::anyhow!("Invalid Binance kline value {v}"))?
+            .parse::<f64>()
+            .map_err(Into::into)
+    };
+    Ok(Candle {
+        ts: c.first().and_then(Value::as_i64).unwrap_or_default(),
+        open: parse(1)?,
+        high: parse(2)?,
+        low: parse(3)?,
+        close: parse(4)?,
+        volume: parse(5)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_code_classifies_the_errors() {
+        let m = |msg: &str| msg.to_string();
+        for (code, msg, expected) in [
+            (0, "", Ok(())),
+            (-2015, "invalid key", Err(CexError::Auth(m("invalid key")))),
+            (-1021, "timestamp", Err(CexError::Auth(m("timestamp")))),
+            (-1003, "too many", Err(CexError::RateLimited(m("too many")))),
+            (-1013, "LOT_SIZE", Err(CexError::InvalidSize(m("LOT_SIZE")))),
+            (
+                -2010,
+                "Account has insufficient balance",
+                Err(CexError::InsufficientFunds(m("Account has insufficient balance"))),
+            ),
+            (
+                -2010,
+                "Market is closed",
+                Err(CexError::Unknown {
+                    code: "-2010".into(),
+                    msg: m("Market is closed"),
+                }),
+            ),
+            (-1001, "disconnected", Err(CexError::Network(m("disconnected")))),
+        ] {
+            assert_eq!(check_code(code, msg), expected, "code {code}: {msg}");
+        }
+    }
+}
+
+// eof
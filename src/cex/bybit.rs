@@ -0,0 +1,561 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use hmac::{Hmac, Mac};
+use log::debug;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use sha2::Sha256;
+
+use crate::{
+    balance::ExchangeBalance,
+    candle::{self, Candle, Timeframe},
+    cex::{
+        CexClient,
+        error::CexError,
+        http::{HttpClient, RetryPolicy},
+    },
+    instrument::{InstrumentInfo, format_decimal},
+    order::{Order, OrderResponse, OrderStatus, OrderType, Side},
+    pair::Pair,
+};
+
+const BYBIT_LIVE: &str = "https://api.bybit.com";
+const BYBIT_DEMO: &str = "https://api-demo.bybit.com";
+const RECV_WINDOW: &str = "5000";
+// most klines returned by one request
+const CANDLES_LIMIT: usize = 1000;
+// 10006 = too many requests, 10018 = IP rate limit
+const RATE_LIMIT_CODES: &[&str] = &["10006", "10018"];
+const EXECUTION_POLL_ATTEMPTS: u32 = 10;
+const EXECUTION_POLL_DELAY_MS: u64 = 500;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub struct BybitClient {
+    api_key: String,
+    secret: String,
+    base: String,
+    http: HttpClient,
+}
+
+impl BybitClient {
+    /// `is_demo` trades on the demo account, which has its own API keys
+    pub fn new(api_key: String, secret: String, is_demo: bool) -> Self {
+        BybitClient {
+            api_key,
+            secret,
+            base: if is_demo { BYBIT_DEMO } else { BYBIT_LIVE }.to_string(),
+            http: HttpClient::new("bybit-rust-client/0.1", RATE_LIMIT_CODES),
+        }
+    }
+
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.http.set_policy(policy);
+        self
+    }
+
+    /// Sends the requests to `base` instead of the exchange (e.g. a local mock server), empty keeps the default
+    pub fn with_base_url(mut self, base: &str) -> Self {
+        if !base.is_empty() {
+            self.base = base.trim_end_matches('/').to_string();
+        }
+        self
+    }
+
+    fn symbol(pair: &Pair) -> String {
+        pair.join("")
+    }
+
+    fn interval(timeframe: Timeframe) -> &'static str {
+        match timeframe {
+            Timeframe::Min1 => "1",
+            Timeframe::Min5 => "5",
+            Timeframe::Hour1 => "60",
+            Timeframe::Hour4 => "240",
+            Timeframe::Day1 => "D",
+            Timeframe::Week1 => "W",
+        }
+    }
+
+    /// Signature is hex encoded HMAC-SHA256 of timestamp + api key + recv window + (query string | json body)
+    fn sign(&self, timestamp: &str, payload: &str) -> String {
+        let prehash = format!("{timestamp}{}{RECV_WINDOW}{payload}", self.api_key);
+        let mut mac = HmacSha256::new_from_slice(self.secret.as_bytes()).expect("HMAC can take key of any size");
+        mac.update(prehash.as_bytes());
+        mac.finalize().into_bytes().iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    async fn public<T: DeserializeOwned>(&self, path: &str, query: &str) -> Result<T> {
+        let url = format!("{}{path}?{query}", self.base);
+        debug!("GET {}", url);
+        let resp: BybitResponse = self.http.send_json(|client| client.get(&url)).await?;
+        resp.into_result(path)
+    }
+
+    async fn get_signed<T: DeserializeOwned>(&self, path: &str, query: &str) -> Result<T> {
+        let url = format!("{}{path}?{query}", self.base);
+        debug!("GET {}", url);
+        let resp: BybitResponse = self
+            .http
+            .send_json(|client| {
+                let ts = Utc::now().timestamp_millis().to_string();
+                let sign = self.sign(&ts, query);
+                client
+                    .get(&url)
+                    .header("X-BAPI-API-KEY", &self.api_key)
+                    .header("X-BAPI-TIMESTAMP", ts)
+                    .header("X-BAPI-RECV-WINDOW", RECV_WINDOW)
+                    .header("X-BAPI-SIGN", sign)
+            })
+            .await?;
+        resp.into_result(path)
+    }
+
+    /// POST requests change the state (orders), they are not replayed after a timeout
+    async fn post_signed<T: DeserializeOwned>(&self, path: &str, body: String) -> Result<T> {
+        let url = format!("{}{path}", self.base);
+        debug!("POST {}", url);
+        let resp: BybitResponse = self
+            .http
+            .send_json_once(|client| {
+                let ts = Utc::now().timestamp_millis().to_string();
+                let sign = self.sign(&ts, &body);
+                client
+                    .post(&url)
+                    .header("X-BAPI-API-KEY", &self.api_key)
+                    .header("X-BAPI-TIMESTAMP", ts)
+                    .header("X-BAPI-RECV-WINDOW", RECV_WINDOW)
+                    .header("X-BAPI-SIGN", sign)
+                    .header("Content-Type", "application/json")
+                    .body(body.clone())
+            })
+            .await?;
+        resp.into_result(path)
+    }
+
+    /// Fills of the order, they can appear with a small delay after the order is placed
+    async fn get_executions(&self, symbol: &str, order_id: &str) -> Result<Vec<BybitExecution>> {
+        let query = format!("category=spot&symbol={symbol}&orderId={order_id}");
+        for _ in 0..EXECUTION_POLL_ATTEMPTS {
+            let result: BybitList<BybitExecution> = self.get_signed("/v5/execution/list", &query).await?;
+            if !result.list.is_empty() {
+                return Ok(result.list);
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(EXECUTION_POLL_DELAY_MS)).await;
+        }
+        Err(anyhow::format_err!("No executions found for Bybit order {order_id}"))
+    }
+
+    /// Aggregates the fills into (amount, average price, coin fee, USDT fee)
+    fn aggregate(pair: &Pair, executions: &[BybitExecution]) -> Result<(f64, f64, f64, f64)> {
+        let mut executed_amount = 0.0;
+        let mut executed_value = 0.0;
+        let mut btc_fee = 0.0;
+        let mut usd_fee = 0.0;
+        for exec in executions {
+            let qty: f64 = exec.exec_qty.parse()?;
+            let price: f64 = exec.exec_price.parse()?;
+            let fee: f64 = exec.exec_fee.parse()?;
+            executed_amount += qty;
+            executed_value += qty * price;
+            if exec.fee_currency == pair.base {
+                btc_fee += fee;
+            } else if exec.fee_currency == pair.quote {
+                usd_fee += fee;
+            } else {
+                debug!("Ignoring fee {} {}", fee, exec.fee_currency);
+            }
+        }
+        let executed_price = if executed_amount > 0.0 {
+            executed_value / executed_amount
+        } else {
+            0.0
+        };
+        Ok((executed_amount, executed_price, btc_fee, usd_fee))
+    }
+}
+
+impl BybitOrder {
+    /// Fees are not part of the order and have to be passed separately
+    fn into_order(self, btc_fee: f64, usd_fee: f64) -> Result<Order> {
+        let amount: f64 = self.qty.parse()?;
+        let filled_amount: f64 = self.cum_exec_qty.parse()?;
+        Ok(Order {
+            side: match self.side.as_str() {
+                "Buy" => Side::Buy,
+                "Sell" => Side::Sell,
+                other => return Err(anyhow::format_err!("Unknown Bybit order side {other}")),
+            },
+            status: match self.order_status.as_str() {
+                "New" | "PartiallyFilled" | "Untriggered" => Order::open_status(amount, filled_amount),
+                "Filled" => OrderStatus::Filled,
+                "Cancelled" | "PartiallyFilledCanceled" | "Deactivated" => OrderStatus::Cancelled,
+                "Rejected" => OrderStatus::Rejected,
+                other => return Err(anyhow::format_err!("Unknown Bybit order status {other}")),
+            },
+            amount,
+            price: self.price.parse::<f64>().ok().filter(|price| *price > 0.0),
+            filled_amount,
+            avg_price: self.avg_price.parse().unwrap_or(0.0),
+            btc_fee,
+            usd_fee,
+            id: self.order_id,
+        })
+    }
+}
+
+#[async_trait]
+impl CexClient for BybitClient {
+    async fn get_price(&self, pair: &Pair) -> Result<f64> {
+        let query = format!("category=spot&symbol={}", Self::symbol(pair));
+        let result: BybitList<BybitTicker> = self.public("/v5/market/tickers", &query).await?;
+        let t = result
+            .list
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("Empty Bybit ticker"))?;
+        Ok(t.last_price.parse()?)
+    }
+
+    async fn get_candles(&self, pair: &Pair, timeframe: Timeframe, bars: u32) -> Result<Vec<Candle>> {
+        // End = start of the current bar minus 1 second (i.e., end of the last finished bar)
+        let (start, end) = timeframe.window(Utc::now(), bars);
+        let end_ts = (end - Duration::seconds(1)).timestamp_millis();
+        let start_ts = start.timestamp_millis();
+
+        // Pages are returned newest first, the next page ends before the oldest candle so far
+        let mut candles = Vec::new();
+        let mut end_at = end_ts;
+        loop {
+            let query = format!(
+                "category=spot&symbol={}&interval={}&start={}&end={}&limit={CANDLES_LIMIT}",
+                Self::symbol(pair),
+                Self::interval(timeframe),
+                start_ts,
+                end_at
+            );
+            let result: BybitList<Vec<String>> = self.public("/v5/market/kline", &query).await?;
+            let page = result
+                .list
+                .iter()
+                .map(|c| parse_kline(c))
+                .collect::<Result<Vec<Candle>>>()?;
+            let count = page.len();
+            let oldest = page.iter().map(|c| c.ts).min();
+            candles.extend(page);
+
+            match oldest {
+                Some(oldest) if count >= CANDLES_LIMIT && oldest > start_ts && oldest <= end_at => end_at = oldest - 1,
+                _ => break,
+            }
+        }
+
+        Ok(candle::merge_pages(candles, timeframe))
+    }
+
+    async fn instrument_info(&self, pair: &Pair) -> Result<InstrumentInfo> {
+        let query = format!("category=spot&symbol={}", Self::symbol(pair));
+        let result: BybitList<BybitInstrument> = self.public("/v5/market/instruments-info", &query).await?;
+        let instrument = result
+            .list
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Empty Bybit instrument info"))?;
+        Ok(InstrumentInfo {
+            min_size: instrument.lot_size_filter.min_order_qty.parse()?,
+            size_increment: instrument.lot_size_filter.base_precision.parse()?,
+            price_tick: instrument.price_filter.tick_size.parse()?,
+            min_notional: instrument.lot_size_filter.min_order_amt.parse()?,
+        })
+    }
+
+    async fn get_balances(&self, pair: &Pair) -> Result<ExchangeBalance> {
+        let query = format!("accountType=UNIFIED&coin={},{}", pair.base, pair.quote);
+        let result: BybitList<BybitWallet> = self.get_signed("/v5/account/wallet-balance", &query).await?;
+
+        let mut balance = ExchangeBalance::default();
+        for c in result.list.iter().flat_map(|w| w.coin.iter()) {
+            let available = c.wallet_balance.parse::<f64>()? - c.locked.parse::<f64>().unwrap_or(0.0);
+            if c.coin == pair.base {
+                balance.btc_balance = available;
+            } else if c.coin == pair.quote {
+                balance.usd_balance = available;
+            }
+        }
+        Ok(balance)
+    }
+
+    async fn put_order(
+        &self,
+        pair: &Pair,
+        side: Side,
+        amount: f64,
+        order_type: OrderType,
+        price: Option<f64>,
+    ) -> Result<OrderResponse> {
+        let symbol = Self::symbol(pair);
+        let req = BybitOrderRequest {
+            category: "spot",
+            symbol: &symbol,
+            side: match side {
+                Side::Buy => "Buy",
+                Side::Sell => "Sell",
+            },
+            order_type: match order_type {
+                OrderType::Market => "Market",
+                _ => "Limit",
+            },
+            qty: format_decimal(amount),
+            price: order_type.limit_price(price)?.map(format_decimal),
+            time_in_force: match order_type {
+                OrderType::Market => None,
+                OrderType::Limit => Some("GTC"),
+                OrderType::PostOnly => Some("PostOnly"),
+                OrderType::Ioc => Some("IOC"),
+            },
+            // Spot market buy is in quote coin by default
+            market_unit: (order_type == OrderType::Market).then_some("baseCoin"),
+        };
+        let body = serde_json::to_string(&req)?;
+        let order: BybitOrderResult = self.post_signed("/v5/order/create", body).await?;
+
+        // Fetch executions to get the fill price and fees, a resting limit order or an IOC which missed the book
+        // has none
+        let executions = match order_type {
+            OrderType::Limit | OrderType::PostOnly | OrderType::Ioc => {
+                let query = format!("category=spot&symbol={symbol}&orderId={}", order.order_id);
+                let result: BybitList<BybitExecution> = self.get_signed("/v5/execution/list", &query).await?;
+                result.list
+            }
+            _ => self.get_executions(&symbol, &order.order_id).await?,
+        };
+
+        let (executed_amount, executed_price, btc_fee, usd_fee) = Self::aggregate(pair, &executions)?;
+
+        Ok(OrderResponse {
+            order_id: order.order_id,
+            executed_price,
+            executed_amount,
+            btc_fee,
+            usd_fee,
+        })
+    }
+
+    async fn get_order(&self, pair: &Pair, order_id: &str) -> Result<Order> {
+        let query = format!("category=spot&symbol={}&orderId={order_id}", Self::symbol(pair));
+        // realtime endpoint returns only open orders for spot, closed ones are in the history
+        let mut result: BybitList<BybitOrder> = self.get_signed("/v5/order/realtime", &query).await?;
+        if result.list.is_empty() {
+            result = self.get_signed("/v5/order/history", &query).await?;
+        }
+        let order = result
+            .list
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::format_err!("Bybit order {order_id} not found"))?;
+
+        let executions: BybitList<BybitExecution> = self.get_signed("/v5/execution/list", &query).await?;
+        let (_, _, btc_fee, usd_fee) = Self::aggregate(pair, &executions.list)?;
+        order.into_order(btc_fee, usd_fee)
+    }
+
+    async fn cancel_order(&self, pair: &Pair, order_id: &str) -> Result<()> {
+        let body = serde_json::to_string(&BybitCancelRequest {
+            category: "spot",
+            symbol: &Self::symbol(pair),
+            order_id,
+        })?;
+        let _: BybitOrderResult = self.post_signed("/v5/order/cancel", body).await?;
+        Ok(())
+    }
+
+    /// Fees are not fetched for the open orders
+    async fn open_orders(&self, pair: &Pair) -> Result<Vec<Order>> {
+        let query = format!("category=spot&symbol={}", Self::symbol(pair));
+        let result: BybitList<BybitOrder> = self.get_signed("/v5/order/realtime", &query).await?;
+        result
+            .list
+            .into_iter()
+            .map(|order| order.into_order(0.0, 0.0))
+            .collect()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BybitResponse {
+    ret_code: i64,
+    ret_msg: String,
+    // an empty object when the request failed, it is parsed only after the code is checked
+    result: Option<Value>,
+}
+
+impl BybitResponse {
+    fn into_result<T: DeserializeOwned>(self, endpoint: &str) -> Result<T> {
+        check_code(self.ret_code, &self.ret_msg)?;
+        let result = self
+            .result
+            .ok_or_else(|| anyhow::format_err!("No data returned from Bybit API. Endpoint {}", endpoint))?;
+        Ok(serde_json::from_value(result)?)
+    }
+}
+
+fn check_code(code: i64, msg: &str) -> Result<(), CexError> {
+    let msg = msg.to_string();
+    match code {
+        0 => Ok(()),
+        // timestamp or receive window, invalid API key, signature, permission denied, authentication, IP not allowed
+        10002 | 10003 | 10004 | 10005 | 10007 | 10010 => Err(CexError::Auth(msg)),
+        170131 => Err(CexError::InsufficientFunds(msg)),
+        10006 | 10018 => Err(CexError::RateLimited(msg)),
+        // quantity above the maximum, too many decimals, value below the minimum
+        170136 | 170137 | 170140 => Err(CexError::InvalidSize(msg)),
+        // server timeout, server error
+        10000 | 10016 => Err(CexError::Network(msg)),
+        _ => Err(CexError::Unknown {
+            code: code.to_string(),
+            msg,
+        }),
+    }
+}
+
+#[derive(Deserialize)]
+struct BybitList<T> {
+    list: Vec<T>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BybitTicker {
+    last_price: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BybitInstrument {
+    lot_size_filter: BybitLotSizeFilter,
+    price_filter: BybitPriceFilter,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BybitLotSizeFilter {
+    base_precision: String,
+    min_order_qty: String,
+    // minimum order value in the quote coin
+    min_order_amt: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BybitPriceFilter {
+    tick_size: String,
+}
+
+#[derive(Deserialize)]
+struct BybitWallet {
+    coin: Vec<BybitCoinBalance>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BybitCoinBalance {
+    coin: String,
+    wallet_balance: String,
+    locked: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BybitOrderRequest<'a> {
+    category: &'a str,
+    symbol: &'a str,
+    side: &'a str,
+    order_type: &'a str,
+    qty: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    price: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time_in_force: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    market_unit: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BybitOrderResult {
+    order_id: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BybitCancelRequest<'a> {
+    category: &'a str,
+    symbol: &'a str,
+    order_id: &'a str,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BybitOrder {
+    order_id: String,
+    side: String,
+    order_status: String,
+    price: String,
+    qty: String,
+    cum_exec_qty: String,
+    avg_price: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BybitExecution {
+    exec_price: String,
+    exec_qty: String,
+    exec_fee: String,
+    #[serde(default)]
+    fee_currency: String,
+}
+
+/// Kline as `[start time, open, high, low, close, volume, turnover]`
+fn parse_kline(c: &[String]) -> Result<Candle> {
+    let value = |i: usize| c.get(i).ok_or_else(|| anyhow::anyhow!("Bybit kline has no value {i}"));
+    Ok(Candle {
+        ts: value(0)?.parse()?,
+        open: value(1)?.parse()?,
+        high: value(2)?.parse()?,
+        low: value(3)?.parse()?,
+        close: value(4)?.parse()?,
+        volume: value(5)?.parse()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_code_classifies_the_errors() {
+        let m = || "msg".to_string();
+        for (code, expected) in [
+            (0, Ok(())),
+            (10003, Err(CexError::Auth(m()))),
+            (10010, Err(CexError::Auth(m()))),
+            (170131, Err(CexError::InsufficientFunds(m()))),
+            (10006, Err(CexError::RateLimited(m()))),
+            (170140, Err(CexError::InvalidSize(m()))),
+            (10016, Err(CexError::Network(m()))),
+            (
+                110001,
+                Err(CexError::Unknown {
+                    code: "110001".into(),
+                    msg: m(),
+                }),
+            ),
+        ] {
+            assert_eq!(check_code(code, "msg"), expected, "code {code}");
+        }
+    }
+}
+
+// eof
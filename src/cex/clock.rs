@@ -0,0 +1,101 @@
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use log::{debug, warn};
+use std::{
+    sync::{
+        Mutex,
+        atomic::{AtomicI64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+// the offset is measured again after this time
+const SYNC_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// a failed measurement is tried again after this time, not before every request
+const SYNC_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+// drift of the local clock which is worth a warning, exchanges reject requests older than a few seconds
+const DRIFT_WARN_MS: i64 = 1000;
+
+/// Exchange server time, as the local time corrected by the measured offset. Signed requests are timestamped
+/// with it, so that they are not rejected when the local clock drifts.
+pub struct ServerClock {
+    offset_ms: AtomicI64,
+    // when the offset should be measured (again), `None` before the first attempt
+    next_sync: Mutex<Option<Instant>>,
+}
+
+impl Default for ServerClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ServerClock {
+    pub fn new() -> Self {
+        ServerClock {
+            offset_ms: AtomicI64::new(0),
+            next_sync: Mutex::new(None),
+        }
+    }
+
+    /// True when the offset was never measured, is too old, or the last failed attempt is old enough to retry
+    pub fn needs_sync(&self) -> bool {
+        self.next_sync
+            .lock()
+            .unwrap()
+            .is_none_or(|next_sync| Instant::now() >= next_sync)
+    }
+
+    /// Records a failed measurement, the last known offset is kept and the next attempt waits a bit
+    pub fn mark_failed(&self) {
+        *self.next_sync.lock().unwrap() = Some(Instant::now() + SYNC_RETRY_INTERVAL);
+    }
+
+    /// Stores the offset of the server time, `sent_ms` and `received_ms` are the local times of the request and
+    /// the response
+    pub fn update(&self, server_ms: i64, sent_ms: i64, received_ms: i64) {
+        let offset = server_ms - (sent_ms + received_ms) / 2;
+        if offset.abs() > DRIFT_WARN_MS {
+            warn!("Local clock differs from the exchange server time by {offset} ms");
+        } else {
+            debug!("Server time offset {offset} ms");
+        }
+        self.offset_ms.store(offset, Ordering::Relaxed);
+        *self.next_sync.lock().unwrap() = Some(Instant::now() + SYNC_INTERVAL);
+    }
+
+    pub fn offset_ms(&self) -> i64 {
+        self.offset_ms.load(Ordering::Relaxed)
+    }
+
+    /// Current exchange server time
+    pub fn now(&self) -> DateTime<Utc> {
+        Utc::now() + ChronoDuration::milliseconds(self.offset_ms())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn synced_until_the_interval_passes() {
+        let clock = ServerClock::new();
+        assert!(clock.needs_sync());
+        clock.update(10_500, 1_000, 2_000);
+        assert_eq!(clock.offset_ms(), 9_000);
+        assert!(!clock.needs_sync());
+    }
+
+    #[test]
+    fn failed_sync_is_not_retried_immediately() {
+        let clock = ServerClock::new();
+        clock.mark_failed();
+        assert!(!clock.needs_sync());
+        assert_eq!(clock.offset_ms(), 0);
+
+        *clock.next_sync.lock().unwrap() = Some(Instant::now());
+        assert!(clock.needs_sync());
+    }
+}
+
+// eof
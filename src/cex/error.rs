@@ -0,0 +1,97 @@
+use std::fmt;
+
+/// Error reported by the exchange, classified so that the caller can decide how to react
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CexError {
+    /// Invalid API key, signature, passphrase or missing permissions
+    Auth(String),
+    /// Not enough balance for the order
+    InsufficientFunds(String),
+    /// Too many requests, even after the retries
+    RateLimited(String),
+    /// Order size or value does not meet the trading rules
+    InvalidSize(String),
+    /// The exchange could not be reached or failed to respond
+    Network(String),
+    /// Any other error code of the exchange
+    Unknown { code: String, msg: String },
+}
+
+/// What to do when placing an order fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorAction {
+    /// The order can be placed again a bit later
+    Retry,
+    /// The order is dropped, trading continues
+    Skip,
+    /// Trading cannot continue until the problem is fixed
+    Halt,
+}
+
+impl CexError {
+    pub fn action(&self) -> ErrorAction {
+        match self {
+            CexError::RateLimited(_) => ErrorAction::Retry,
+            CexError::Auth(_) => ErrorAction::Halt,
+            // the order may have reached the exchange, the balance is reconciled in the next cycle
+            CexError::Network(_) => ErrorAction::Skip,
+            CexError::InsufficientFunds(_) | CexError::InvalidSize(_) | CexError::Unknown { .. } => ErrorAction::Skip,
+        }
+    }
+}
+
+impl ErrorAction {
+    /// Action for any error of the cex module, errors which are not [`CexError`] are skipped
+    pub fn of(e: &anyhow::Error) -> Self {
+        e.downcast_ref::<CexError>()
+            .map(CexError::action)
+            .unwrap_or(ErrorAction::Skip)
+    }
+}
+
+impl fmt::Display for CexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CexError::Auth(msg) => write!(f, "Authentication failed: {msg}"),
+            CexError::InsufficientFunds(msg) => write!(f, "Insufficient funds: {msg}"),
+            CexError::RateLimited(msg) => write!(f, "Rate limited: {msg}"),
+            CexError::InvalidSize(msg) => write!(f, "Invalid order size: {msg}"),
+            CexError::Network(msg) => write!(f, "Network error: {msg}"),
+            CexError::Unknown { code, msg } => write!(f, "Exchange error {code}: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for CexError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn action_of_the_errors() {
+        for (error, action) in [
+            (CexError::Auth("key".into()), ErrorAction::Halt),
+            (CexError::InsufficientFunds("balance".into()), ErrorAction::Skip),
+            (CexError::RateLimited("429".into()), ErrorAction::Retry),
+            (CexError::InvalidSize("lot".into()), ErrorAction::Skip),
+            (CexError::Network("timeout".into()), ErrorAction::Skip),
+            (
+                CexError::Unknown {
+                    code: "1".into(),
+                    msg: "other".into(),
+                },
+                ErrorAction::Skip,
+            ),
+        ] {
+            assert_eq!(error.action(), action, "{error}");
+            assert_eq!(ErrorAction::of(&error.clone().into()), action, "{error}");
+        }
+        assert_eq!(
+            ErrorAction::of(&anyhow::anyhow!("not an exchange error")),
+            ErrorAction::Skip
+        );
+    }
+}
+
+// eof
@@ -0,0 +1,139 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, info, warn};
+use std::time::Duration;
+use tokio::{
+    sync::watch,
+    task::JoinHandle,
+    time::{self, Instant},
+};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+use crate::pair::Pair;
+
+// delay before the first reconnect, doubled after every failed one
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// WebSocket endpoint of the ticker stream
+pub struct FeedEndpoint {
+    pub url: String,
+    /// How often the heartbeat is sent, the connection is considered dead after two intervals without data
+    pub ping_interval: Duration,
+}
+
+/// Exchange specific part of the streaming ticker
+#[async_trait]
+pub trait FeedSource: Send + Sync {
+    /// Name of the exchange for the logs
+    fn name(&self) -> &'static str;
+
+    /// Endpoint to connect to, it may contain a token which is valid for a single connection
+    async fn endpoint(&self) -> Result<FeedEndpoint>;
+
+    /// Message which subscribes the ticker of the pair
+    fn subscribe_message(&self, pair: &Pair) -> String;
+
+    /// Heartbeat message
+    fn ping_message(&self) -> String;
+
+    /// Last price from the ticker message, `None` for the other messages (acks, pongs)
+    fn parse_price(&self, text: &str) -> Option<f64>;
+}
+
+/// Streaming last price of the pair. A background task keeps the connection alive by the heartbeat and reconnects
+/// it after failures.
+pub struct PriceFeed {
+    price: watch::Receiver<Option<f64>>,
+    task: JoinHandle<()>,
+}
+
+impl PriceFeed {
+    pub fn start(source: Box<dyn FeedSource>, pair: Pair) -> Self {
+        let (tx, rx) = watch::channel(None);
+        let task = tokio::spawn(async move {
+            let mut delay = RECONNECT_DELAY;
+            loop {
+                if let Err(e) = stream(&*source, &pair, &tx, &mut delay).await {
+                    warn!("{} price feed failed: {e}, reconnect in {delay:?}", source.name());
+                }
+                time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+            }
+        });
+        PriceFeed { price: rx, task }
+    }
+
+    /// Last streamed price, `None` before the first ticker message
+    pub fn latest(&self) -> Option<f64> {
+        *self.price.borrow()
+    }
+
+    /// Waits for the next streamed price
+    pub async fn next_price(&mut self) -> f64 {
+        loop {
+            if self.price.changed().await.is_err() {
+                // the feed task has ended, no more prices
+                std::future::pending::<()>().await;
+            }
+            if let Some(price) = *self.price.borrow_and_update() {
+                return price;
+            }
+        }
+    }
+}
+
+impl Drop for PriceFeed {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Streams the prices until the connection fails. The reconnect delay is reset once a price is received.
+async fn stream(
+    source: &dyn FeedSource,
+    pair: &Pair,
+    tx: &watch::Sender<Option<f64>>,
+    delay: &mut Duration,
+) -> Result<()> {
+    let endpoint = source.endpoint().await?;
+    let (mut ws, _) = connect_async(endpoint.url.as_str()).await?;
+    ws.send(Message::text(source.subscribe_message(pair))).await?;
+    info!("{} price feed connected", source.name());
+
+    let mut ping = time::interval(endpoint.ping_interval);
+    // the first tick is immediate
+    ping.tick().await;
+    let mut last_message = Instant::now();
+    loop {
+        tokio::select! {
+            _ = ping.tick() => {
+                if last_message.elapsed() > endpoint.ping_interval * 2 {
+                    anyhow::bail!("no data for {:?}", last_message.elapsed());
+                }
+                ws.send(Message::text(source.ping_message())).await?;
+            }
+            msg = ws.next() => {
+                last_message = Instant::now();
+                match msg {
+                    Some(Ok(Message::Text(text))) => {
+                        if let Some(price) = source.parse_price(text.as_str()) {
+                            *delay = RECONNECT_DELAY;
+                            tx.send_replace(Some(price));
+                        } else {
+                            debug!("{} feed: {}", source.name(), text.as_str());
+                        }
+                    }
+                    Some(Ok(Message::Close(frame))) => anyhow::bail!("closed by the server {frame:?}"),
+                    // pings are answered by the library
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => return Err(e.into()),
+                    None => anyhow::bail!("connection closed"),
+                }
+            }
+        }
+    }
+}
+
+// eof
@@ -0,0 +1,280 @@
+use anyhow::Result;
+use log::warn;
+use reqwest::{Client, RequestBuilder, StatusCode, header::RETRY_AFTER};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{cex::error::CexError, config::Config};
+
+/// Retry settings of the exchange HTTP requests
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Number of retries after the first attempt
+    pub retries: u32,
+    /// Delay before the first retry, doubled with every next one
+    pub backoff: Duration,
+    /// Upper bound of the delay between retries
+    pub max_backoff: Duration,
+    /// Timeout of a single request
+    pub timeout: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 3,
+            backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl From<&Config> for RetryPolicy {
+    fn from(cfg: &Config) -> Self {
+        RetryPolicy {
+            retries: cfg.http_retries,
+            backoff: Duration::from_millis(cfg.http_backoff_ms),
+            timeout: Duration::from_secs(cfg.http_timeout_secs),
+            ..Default::default()
+        }
+    }
+}
+
+/// HTTP client shared by the exchange clients. Transient failures (network errors, timeouts, HTTP 429 and 5xx,
+/// exchange rate-limit codes) are retried with exponential backoff. When the retries run out they are reported as
+/// [`CexError::RateLimited`] or [`CexError::Network`].
+pub struct HttpClient {
+    client: Client,
+    policy: RetryPolicy,
+    // error codes in the response body which mean the request was rate limited
+    rate_limit_codes: &'static [&'static str],
+}
+
+impl HttpClient {
+    pub fn new(user_agent: &str, rate_limit_codes: &'static [&'static str]) -> Self {
+        HttpClient {
+            client: Client::builder().user_agent(user_agent).build().unwrap(),
+            policy: RetryPolicy::default(),
+            rate_limit_codes,
+        }
+    }
+
+    pub fn set_policy(&mut self, policy: RetryPolicy) {
+        self.policy = policy;
+    }
+
+    /// Sends the request built by `build` and parses the JSON response. The request is built again for every
+    /// attempt, so that signed requests get a fresh timestamp.
+    pub async fn send_json<T: DeserializeOwned>(&self, build: impl Fn(&Client) -> RequestBuilder) -> Result<T> {
+        self.send(build, true).await
+    }
+
+    /// Like [`HttpClient::send_json`] for requests which must not be executed twice (placing an order). They are
+    /// retried only when they surely did not reach the exchange or were rejected because of the rate limit.
+    pub async fn send_json_once<T: DeserializeOwned>(&self, build: impl Fn(&Client) -> RequestBuilder) -> Result<T> {
+        self.send(build, false).await
+    }
+
+    async fn send<T: DeserializeOwned>(&self, build: impl Fn(&Client) -> RequestBuilder, replay: bool) -> Result<T> {
+        let mut attempt = 0;
+        loop {
+            let (error, retry_after) = match build(&self.client).timeout(self.policy.timeout).send().await {
+                Ok(res) => {
+                    let status = res.status();
+                    let retry_after = res
+                        .headers()
+                        .get(RETRY_AFTER)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(|value| value.trim().parse::<u64>().ok())
+                        .map(Duration::from_secs);
+                    match res.text().await {
+                        Ok(text) if status == StatusCode::TOO_MANY_REQUESTS => {
+                            (CexError::RateLimited(format!("HTTP {status}: {text}")), retry_after)
+                        }
+                        Ok(text) if replay && status.is_server_error() => {
+                            (CexError::Network(format!("HTTP {status}: {text}")), retry_after)
+                        }
+                        Ok(text) => match self.rate_limit_code(&text) {
+                            Some(code) => (CexError::RateLimited(format!("code {code}")), retry_after),
+                            None => {
+                                return serde_json::from_str(&text)
+                                    .map_err(|e| anyhow::format_err!("Invalid response ({e}): {text}"));
+                            }
+                        },
+                        Err(e) if replay => (CexError::Network(format!("Failed to read response: {e}")), None),
+                        Err(e) => return Err(CexError::Network(format!("Failed to read response: {e}")).into()),
+                    }
+                }
+                Err(e) if e.is_connect() || (replay && (e.is_timeout() || e.is_request())) => {
+                    (CexError::Network(format!("Request failed: {e}")), None)
+                }
+                Err(e) => return Err(CexError::Network(format!("Request failed: {e}")).into()),
+            };
+
+            if attempt >= self.policy.retries {
+                let attempts = format!(" (after {} attempts)", attempt + 1);
+                return Err(match error {
+                    CexError::RateLimited(reason) => CexError::RateLimited(reason + &attempts),
+                    CexError::Network(reason) => CexError::Network(reason + &attempts),
+                    error => error,
+                }
+                .into());
+            }
+            let delay = retry_after.unwrap_or_else(|| self.backoff(attempt));
+            attempt += 1;
+            warn!("{error}, retry {attempt}/{} in {delay:?}", self.policy.retries);
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Error code of the response body: the `code` field (OKX, KuCoin, Binance), `retCode` (Bybit) or the first
+    /// entry of the `error` list (Kraken)
+    fn rate_limit_code(&self, text: &str) -> Option<String> {
+        if self.rate_limit_codes.is_empty() {
+            return None;
+        }
+        let body = serde_json::from_str::<Value>(text).ok()?;
+        let code = match body.get("code").or(body.get("retCode")) {
+            Some(Value::String(code)) => code.clone(),
+            Some(code) => code.to_string(),
+            None => body.get("error")?.get(0)?.as_str()?.to_string(),
+        };
+        self.rate_limit_codes.contains(&code.as_str()).then_some(code)
+    }
+
+    /// Exponential backoff with jitter: a random delay between the half and the full exponential delay
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .policy
+            .backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.policy.max_backoff);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default();
+        delay.mul_f64(0.5 + (nanos % 1000) as f64 / 2000.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    fn client(retries: u32, timeout: Duration) -> HttpClient {
+        let mut http = HttpClient::new("test", &["50011"]);
+        http.set_policy(RetryPolicy {
+            retries,
+            backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+            timeout,
+        });
+        http
+    }
+
+    /// Local server answering every connection with `status` and `body` after `delay`, returns its URL and the
+    /// number of received requests
+    fn serve(status: &'static str, body: &'static str, delay: Duration) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                counter.fetch_add(1, Ordering::SeqCst);
+                // a slow answer must not delay counting the next request
+                std::thread::spawn(move || {
+                    let _ = stream.read(&mut [0u8; 4096]);
+                    std::thread::sleep(delay);
+                    let _ = write!(
+                        stream,
+                        "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                        body.len()
+                    );
+                });
+            }
+        });
+        (url, hits)
+    }
+
+    #[test]
+    fn backoff_bounds() {
+        let http = client(3, Duration::from_secs(1));
+        for attempt in 0..10 {
+            let full = Duration::from_millis(1 << attempt).min(Duration::from_millis(5));
+            let delay = http.backoff(attempt);
+            assert!(delay >= full / 2 && delay <= full, "attempt {attempt}: {delay:?}");
+        }
+        assert!(http.backoff(u32::MAX) <= Duration::from_millis(5));
+    }
+
+    #[test]
+    fn rate_limit_code_of_the_body() {
+        let http = client(0, Duration::from_secs(1));
+        assert_eq!(http.rate_limit_code(r#"{"code":"50011"}"#).as_deref(), Some("50011"));
+        assert_eq!(http.rate_limit_code(r#"{"retCode":50011}"#).as_deref(), Some("50011"));
+        assert_eq!(http.rate_limit_code(r#"{"error":["50011"]}"#).as_deref(), Some("50011"));
+        assert_eq!(http.rate_limit_code(r#"{"code":"0"}"#), None);
+        assert_eq!(http.rate_limit_code("not json"), None);
+    }
+
+    #[tokio::test]
+    async fn server_error_is_retried() {
+        let (url, hits) = serve("503 Service Unavailable", "down", Duration::ZERO);
+        let res: Result<Value> = client(2, Duration::from_secs(5)).send_json(|c| c.get(&url)).await;
+        let err = res.unwrap_err().downcast::<CexError>().unwrap();
+        assert!(matches!(&err, CexError::Network(reason) if reason.ends_with("(after 3 attempts)")));
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn success_is_not_retried() {
+        let (url, hits) = serve("200 OK", r#"{"code":"0"}"#, Duration::ZERO);
+        let res: Value = client(2, Duration::from_secs(5))
+            .send_json(|c| c.get(&url))
+            .await
+            .unwrap();
+        assert_eq!(res["code"], "0");
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn rate_limit_is_retried_also_once() {
+        let (url, hits) = serve("429 Too Many Requests", "slow down", Duration::ZERO);
+        let res: Result<Value> = client(2, Duration::from_secs(5)).send_json_once(|c| c.post(&url)).await;
+        let err = res.unwrap_err().downcast::<CexError>().unwrap();
+        assert!(matches!(err, CexError::RateLimited(_)));
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn once_server_error_is_not_replayed() {
+        let (url, hits) = serve("500 Internal Server Error", "oops", Duration::ZERO);
+        let res: Result<Value> = client(2, Duration::from_secs(5)).send_json_once(|c| c.post(&url)).await;
+        assert!(res.is_err());
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn once_timeout_is_not_replayed() {
+        let (url, hits) = serve("200 OK", "{}", Duration::from_millis(500));
+        let res: Result<Value> = client(2, Duration::from_millis(100))
+            .send_json_once(|c| c.post(&url))
+            .await;
+        let err = res.unwrap_err().downcast::<CexError>().unwrap();
+        assert!(matches!(err, CexError::Network(_)));
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+}
+
+// eof
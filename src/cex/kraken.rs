@@ -0,0 +1,557 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use base64::{Engine as _, engine::general_purpose};
+use chrono::{Duration, Utc};
+use hmac::{Hmac, Mac};
+use log::debug;
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::Value;
+use sha2::{Digest, Sha256, Sha512};
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    balance::ExchangeBalance,
+    candle::{Candle, Timeframe},
+    cex::{
+        CexClient,
+        error::CexError,
+        http::{HttpClient, RetryPolicy},
+    },
+    instrument::{InstrumentInfo, format_decimal},
+    order::{Order, OrderResponse, OrderStatus, OrderType, Side},
+    pair::Pair,
+};
+
+const BASE_URL: &str = "https://api.kraken.com";
+// OHLC returns only the most recent candles, older ones are not available at all
+const OHLC_LIMIT: u32 = 720;
+const ORDER_POLL_ATTEMPTS: u32 = 10;
+const ORDER_POLL_DELAY_MS: u64 = 500;
+const RATE_LIMIT_CODES: &[&str] = &[
+    "EAPI:Rate limit exceeded",
+    "EOrder:Rate limit exceeded",
+    "EGeneral:Too many requests",
+];
+
+type HmacSha512 = Hmac<Sha512>;
+
+pub struct KrakenClient {
+    api_key: String,
+    secret: String,
+    base: String,
+    http: HttpClient,
+    // last used nonce, Kraken requires it to be increasing
+    nonce: AtomicU64,
+}
+
+impl KrakenClient {
+    pub fn new(api_key: String, secret: String) -> Self {
+        KrakenClient {
+            api_key,
+            secret,
+            base: BASE_URL.to_string(),
+            http: HttpClient::new("kraken-rust-client/0.1", RATE_LIMIT_CODES),
+            nonce: AtomicU64::new(0),
+        }
+    }
+
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.http.set_policy(policy);
+        self
+    }
+
+    /// Sends the requests to `base` instead of the exchange (e.g. a local mock server), empty keeps the default
+    pub fn with_base_url(mut self, base: &str) -> Self {
+        if !base.is_empty() {
+            self.base = base.trim_end_matches('/').to_string();
+        }
+        self
+    }
+
+    /// Kraken uses XBT instead of BTC and XDG instead of DOGE
+    fn asset_name(currency: &str) -> &str {
+        match currency {
+            "BTC" => "XBT",
+            "DOGE" => "XDG",
+            _ => currency,
+        }
+    }
+
+    fn pair_name(pair: &Pair) -> String {
+        format!("{}{}", Self::asset_name(&pair.base), Self::asset_name(&pair.quote))
+    }
+
+    fn next_nonce(&self) -> u64 {
+        let now = Utc::now().timestamp_millis() as u64;
+        let prev = self
+            .nonce
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| Some(now.max(last + 1)))
+            .unwrap();
+        now.max(prev + 1)
+    }
+
+    /// API-Sign = base64(HMAC-SHA512(base64decode(secret), path + SHA256(nonce + postdata)))
+    fn sign(secret: &[u8], path: &str, nonce: u64, post_data: &str) -> String {
+        let mut sha = Sha256::new();
+        sha.update(format!("{nonce}{post_data}").as_bytes());
+        let digest = sha.finalize();
+
+        let mut mac = HmacSha512::new_from_slice(secret).expect("HMAC can take key of any size");
+        mac.update(path.as_bytes());
+        mac.update(&digest);
+        general_purpose::STANDARD.encode(mac.finalize().into_bytes())
+    }
+
+    async fn public<T: DeserializeOwned>(&self, endpoint: &str) -> Result<T> {
+        let url = format!("{}{endpoint}", self.base);
+        debug!("GET {}", url);
+        let resp: KrakenResponse = self.http.send_json(|client| client.get(&url)).await?;
+        resp.into_result(endpoint)
+    }
+
+    /// Every private request is a POST, only placing and cancelling an order is not replayed after a timeout
+    async fn private<T: DeserializeOwned>(&self, path: &str, params: &str) -> Result<T> {
+        let secret = general_purpose::STANDARD.decode(&self.secret)?;
+        let url = format!("{}{path}", self.base);
+        debug!("POST {}", url);
+        let build = |client: &reqwest::Client| {
+            let nonce = self.next_nonce();
+            let post_data = if params.is_empty() {
+                format!("nonce={nonce}")
+            } else {
+                format!("nonce={nonce}&{params}")
+            };
+            client
+                .post(&url)
+                .header("API-Key", &self.api_key)
+                .header("API-Sign", Self::sign(&secret, path, nonce, &post_data))
+                .header("Content-Type", "application/x-www-form-urlencoded; charset=utf-8")
+                .body(post_data)
+        };
+        let resp: KrakenResponse = if matches!(path, "/0/private/AddOrder" | "/0/private/CancelOrder") {
+            self.http.send_json_once(build).await?
+        } else {
+            self.http.send_json(build).await?
+        };
+        resp.into_result(path)
+    }
+
+    async fn query_order(&self, txid: &str) -> Result<KrakenOrderInfo> {
+        let mut orders: HashMap<String, KrakenOrderInfo> =
+            self.private("/0/private/QueryOrders", &format!("txid={txid}")).await?;
+        orders
+            .remove(txid)
+            .ok_or_else(|| anyhow::format_err!("Kraken order {txid} not found"))
+    }
+}
+
+impl KrakenOrderInfo {
+    fn into_order(self, txid: String) -> Result<Order> {
+        let amount: f64 = self.vol.parse()?;
+        let filled_amount: f64 = self.vol_exec.parse()?;
+        let avg_price: f64 = self.price.parse().unwrap_or(0.0);
+        let fee: f64 = self.fee.parse()?;
+        // the fee is charged in the quote currency unless the order asked for the base one
+        let (btc_fee, usd_fee) = if self.oflags.split(',').any(|flag| flag == "fcib") {
+            (if avg_price > 0.0 { fee / avg_price } else { 0.0 }, 0.0)
+        } else {
+            (0.0, fee)
+        };
+        Ok(Order {
+            id: txid,
+            side: match self.descr.type_.as_str() {
+                "buy" => Side::Buy,
+                "sell" => Side::Sell,
+                other => return Err(anyhow::format_err!("Unknown Kraken order side {other}")),
+            },
+            status: match self.status.as_str() {
+                "pending" | "open" => Order::open_status(amount, filled_amount),
+                "closed" => OrderStatus::Filled,
+                "canceled" | "expired" => OrderStatus::Cancelled,
+                other => return Err(anyhow::format_err!("Unknown Kraken order status {other}")),
+            },
+            amount,
+            price: self.descr.price.parse::<f64>().ok().filter(|price| *price > 0.0),
+            filled_amount,
+            avg_price,
+            btc_fee,
+            usd_fee,
+        })
+    }
+}
+
+#[async_trait]
+impl CexClient for KrakenClient {
+    async fn get_price(&self, pair: &Pair) -> Result<f64> {
+        let endpoint = format!("/0/public/Ticker?pair={}", Self::pair_name(pair));
+        let result: HashMap<String, KrakenTicker> = self.public(&endpoint).await?;
+        let ticker = result
+            .into_values()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Empty Kraken ticker"))?;
+        // c = last trade closed [price, lot volume]
+        let last = ticker.c.first().ok_or_else(|| anyhow::anyhow!("Empty Kraken ticker"))?;
+        Ok(last.parse()?)
+    }
+
+    /// Kraken keeps only the last 720 candles including the unfinished one, a longer period is rejected instead
+    /// of being cut
+    async fn get_candles(&self, pair: &Pair, timeframe: Timeframe, bars: u32) -> Result<Vec<Candle>> {
+        if bars >= OHLC_LIMIT {
+            anyhow::bail!("Kraken returns only the last {OHLC_LIMIT} {timeframe} candles, period {bars} needs more");
+        }
+        let (start, end) = timeframe.window(Utc::now(), bars);
+        let end_ts = end.timestamp();
+
+        // Start of the first bar (minus 1 second, `since` is exclusive)
+        let start_ts = (start - Duration::seconds(1)).timestamp();
+
+        let endpoint = format!(
+            "/0/public/OHLC?pair={}&interval={}&since={}",
+            Self::pair_name(pair),
+            timeframe.minutes(),
+            start_ts
+        );
+        let result: HashMap<String, Value> = self.public(&endpoint).await?;
+
+        // result contains the candles under the pair name and `last` with the cursor
+        let candles = result
+            .into_iter()
+            .find(|(key, _)| key != "last")
+            .map(|(_, value)| value)
+            .ok_or_else(|| anyhow::anyhow!("Empty Kraken OHLC response"))?;
+        let data: Vec<Vec<Value>> = serde_json::from_value(candles)?;
+
+        let mut candles = data.iter().map(|c| parse_ohlc(c)).collect::<Result<Vec<Candle>>>()?;
+        candles.retain(|c| c.ts < end_ts * 1000); // without the unfinished candle
+        candles.sort_by_key(|c| c.ts);
+        Ok(candles)
+    }
+
+    async fn instrument_info(&self, pair: &Pair) -> Result<InstrumentInfo> {
+        let endpoint = format!("/0/public/AssetPairs?pair={}", Self::pair_name(pair));
+        let result: HashMap<String, KrakenAssetPair> = self.public(&endpoint).await?;
+        let pair = result
+            .into_values()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Empty Kraken asset pair"))?;
+        Ok(InstrumentInfo {
+            min_size: pair.ordermin.parse()?,
+            size_increment: 10f64.powi(-pair.lot_decimals),
+            price_tick: pair.tick_size.parse()?,
+            min_notional: pair.costmin.parse()?,
+        })
+    }
+
+    async fn get_balances(&self, pair: &Pair) -> Result<ExchangeBalance> {
+        let result: HashMap<String, String> = self.private("/0/private/Balance", "").await?;
+
+        // legacy assets are returned with the X (crypto) or Z (fiat) prefix, like XXBT, XETH or ZUSD
+        let parse = |currency: &str| -> Result<f64> {
+            let asset = Self::asset_name(currency);
+            let value = [format!("X{asset}"), format!("Z{asset}"), asset.to_string()]
+                .iter()
+                .find_map(|name| result.get(name));
+            match value {
+                Some(value) => Ok(value.parse()?),
+                None => Ok(0.0),
+            }
+        };
+        Ok(ExchangeBalance {
+            btc_balance: parse(&pair.base)?,
+            usd_balance: parse(&pair.quote)?,
+        })
+    }
+
+    async fn put_order(
+        &self,
+        pair: &Pair,
+        side: Side,
+        amount: f64,
+        order_type: OrderType,
+        price: Option<f64>,
+    ) -> Result<OrderResponse> {
+        let mut params = format!(
+            "ordertype={}&type={}&volume={}&pair={}",
+            match order_type {
+                OrderType::Market => "market",
+                _ => "limit",
+            },
+            match side {
+                Side::Buy => "buy",
+                Side::Sell => "sell",
+            },
+            format_decimal(amount),
+            Self::pair_name(pair)
+        );
+        if let Some(price) = order_type.limit_price(price)? {
+            params.push_str(&format!("&price={}", format_decimal(price)));
+        }
+        match order_type {
+            OrderType::PostOnly => params.push_str("&oflags=post"),
+            OrderType::Ioc => params.push_str("&timeinforce=IOC"),
+            _ => {}
+        }
+        let added: KrakenAddOrder = self.private("/0/private/AddOrder", &params).await?;
+        let txid = added
+            .txid
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Empty Kraken order response"))?;
+
+        // Market order is filled almost immediately, wait until it is closed. Limit order can rest on the book.
+        let mut info = self.query_order(&txid).await?;
+        let attempts = if order_type == OrderType::Limit || order_type == OrderType::PostOnly {
+            0
+        } else {
+            ORDER_POLL_ATTEMPTS
+        };
+        for _ in 0..attempts {
+            if info.status == "closed" || info.status == "canceled" || info.status == "expired" {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(ORDER_POLL_DELAY_MS)).await;
+            info = self.query_order(&txid).await?;
+        }
+
+        let order = info.into_order(txid)?;
+        if order.filled_amount <= 0.0 && order_type == OrderType::Market {
+            return Err(anyhow::format_err!(
+                "Kraken order {} was not filled (status {:?})",
+                order.id,
+                order.status
+            ));
+        }
+        Ok(order.response())
+    }
+
+    async fn get_order(&self, _pair: &Pair, order_id: &str) -> Result<Order> {
+        self.query_order(order_id).await?.into_order(order_id.to_string())
+    }
+
+    async fn cancel_order(&self, _pair: &Pair, order_id: &str) -> Result<()> {
+        let _: Value = self
+            .private("/0/private/CancelOrder", &format!("txid={order_id}"))
+            .await?;
+        Ok(())
+    }
+
+    async fn open_orders(&self, pair: &Pair) -> Result<Vec<Order>> {
+        let result: KrakenOpenOrders = self.private("/0/private/OpenOrders", "").await?;
+        let name = Self::pair_name(pair);
+        result
+            .open
+            .into_iter()
+            .filter(|(_, info)| info.descr.pair == name)
+            .map(|(txid, info)| info.into_order(txid))
+            .collect()
+    }
+}
+
+#[derive(Deserialize)]
+struct KrakenResponse {
+    error: Vec<String>,
+    // parsed only when there is no error
+    result: Option<Value>,
+}
+
+impl KrakenResponse {
+    fn into_result<T: DeserializeOwned>(self, endpoint: &str) -> Result<T> {
+        check_error(&self.error)?;
+        let result = self
+            .result
+            .ok_or_else(|| anyhow::format_err!("No data returned from Kraken API. Endpoint {}", endpoint))?;
+        Ok(serde_json::from_value(result)?)
+    }
+}
+
+/// Errors are strings like `EOrder:Insufficient funds`, some have details after another colon
+fn check_error(errors: &[String]) -> Result<(), CexError> {
+    let Some(error) = errors.first() else {
+        return Ok(());
+    };
+    let msg = errors.join(", ");
+    let is = |prefixes: &[&str]| prefixes.iter().any(|prefix| error.starts_with(prefix));
+    if is(&[
+        "EAPI:Invalid key",
+        "EAPI:Invalid signature",
+        "EAPI:Invalid nonce",
+        "EGeneral:Permission denied",
+    ]) {
+        Err(CexError::Auth(msg))
+    } else if is(&["EOrder:Insufficient funds"]) {
+        Err(CexError::InsufficientFunds(msg))
+    } else if is(RATE_LIMIT_CODES) {
+        Err(CexError::RateLimited(msg))
+    } else if is(&[
+        "EOrder:Order minimum not met",
+        "EOrder:Cost minimum not met",
+        "EGeneral:Invalid arguments:volume",
+    ]) {
+        Err(CexError::InvalidSize(msg))
+    } else if is(&["EService:Unavailable", "EService:Busy", "EService:Deadline elapsed"]) {
+        Err(CexError::Network(msg))
+    } else {
+        Err(CexError::Unknown {
+            code: error.split(':').next().unwrap_or_default().to_string(),
+            msg,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct KrakenTicker {
+    c: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct KrakenAssetPair {
+    ordermin: String,
+    lot_decimals: i32,
+    tick_size: String,
+    // minimum order cost in the quote currency
+    costmin: String,
+}
+
+#[derive(Deserialize)]
+struct KrakenAddOrder {
+    txid: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct KrakenOpenOrders {
+    open: HashMap<String, KrakenOrderInfo>,
+}
+
+#[derive(Deserialize)]
+struct KrakenOrderInfo {
+    status: String,
+    descr: KrakenOrderDescr,
+    vol: String,
+    vol_exec: String,
+    // average price
+    price: String,
+    // total fee, valued in the quote currency
+    fee: String,
+    // comma separated order flags, `fcib` takes the fee in the base currency
+    #[serde(default)]
+    oflags: String,
+}
+
+#[derive(Deserialize)]
+struct KrakenOrderDescr {
+    pair: String,
+    #[serde(rename = "type")]
+    type_: String,
+    // limit price
+    price: String,
+}
+
+/// OHLC row as `[time (seconds), open, high, low, close, vwap, volume, count]`, the prices are strings
+fn parse_ohlc(c: &[Value]) -> Result<Candle> {
+    let value = |i: usize| {
+        c.get(i)
+            .ok_or_else(|| anyhow::anyhow!("Kraken OHLC row has no value {i}"))
+    };
+    let parse = |i: usize| -> Result<f64> {
+        let v = value(i)?;
+        v.as_str()
+            .ok_or_else(|| anyhow::anyhow!("Invalid Kraken OHLC value {v}"))?
+            .parse::<f64>()
+            .map_err(Into::into)
+    };
+    let ts = value(0)?;
+    Ok(Candle {
+        ts: ts
+            .as_i64()
+            .ok_or_else(|| anyhow::anyhow!("Invalid Kraken OHLC time {ts}"))?
+            * 1000,
+        open: parse(1)?,
+        high: parse(2)?,
+        low: parse(3)?,
+        close: parse(4)?,
+        volume: parse(6)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pair_name_uses_kraken_asset_names() {
+        assert_eq!(KrakenClient::pair_name(&Pair::new("BTC", "USD")), "XBTUSD");
+        assert_eq!(KrakenClient::pair_name(&Pair::new("DOGE", "USDT")), "XDGUSDT");
+        assert_eq!(KrakenClient::pair_name(&Pair::new("ETH", "BTC")), "ETHXBT");
+    }
+
+    #[test]
+    fn parse_ohlc_row() {
+        let row = serde_json::json!([1700000000, "1", "3", "0.5", "2", "1.5", "10", 5]);
+        let candle = parse_ohlc(row.as_array().unwrap()).unwrap();
+        assert_eq!(candle.ts, 1700000000000);
+        assert_eq!(
+            (candle.open, candle.high, candle.low, candle.close),
+            (1.0, 3.0, 0.5, 2.0)
+        );
+        assert_eq!(candle.volume, 10.0);
+    }
+
+    #[test]
+    fn parse_ohlc_rejects_malformed_rows() {
+        for row in [
+            serde_json::json!([1700000000, "1", "3", "0.5", "2", "1.5"]),
+            serde_json::json!(["1700000000", "1", "3", "0.5", "2", "1.5", "10", 5]),
+            serde_json::json!([null, "1", "3", "0.5", "2", "1.5", "10", 5]),
+            serde_json::json!([1700000000, 1, "3", "0.5", "2", "1.5", "10", 5]),
+            serde_json::json!([]),
+        ] {
+            assert!(parse_ohlc(row.as_array().unwrap()).is_err(), "{row}");
+        }
+    }
+
+    #[test]
+    fn check_error_classifies_the_errors() {
+        let errors = |errors: &[&str]| errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        let m = |msg: &str| msg.to_string();
+        for (list, expected) in [
+            (errors(&[]), Ok(())),
+            (
+                errors(&["EAPI:Invalid key"]),
+                Err(CexError::Auth(m("EAPI:Invalid key"))),
+            ),
+            (
+                errors(&["EOrder:Insufficient funds"]),
+                Err(CexError::InsufficientFunds(m("EOrder:Insufficient funds"))),
+            ),
+            (
+                errors(&["EAPI:Rate limit exceeded", "EGeneral:Internal error"]),
+                Err(CexError::RateLimited(m(
+                    "EAPI:Rate limit exceeded, EGeneral:Internal error",
+                ))),
+            ),
+            (
+                errors(&["EOrder:Order minimum not met"]),
+                Err(CexError::InvalidSize(m("EOrder:Order minimum not met"))),
+            ),
+            (
+                errors(&["EService:Unavailable"]),
+                Err(CexError::Network(m("EService:Unavailable"))),
+            ),
+            (
+                errors(&["EQuery:Unknown asset pair"]),
+                Err(CexError::Unknown {
+                    code: "EQuery".into(),
+                    msg: m("EQuery:Unknown asset pair"),
+                }),
+            ),
+        ] {
+            assert_eq!(check_error(&list), expected, "{list:?}");
+        }
+    }
+}
+
+// eof
@@ -3,18 +3,33 @@ use async_trait::async_trait;
 use base64::{Engine as _, engine::general_purpose};
 use chrono::{Duration, Utc};
 use hmac::{Hmac, Mac};
-use log::debug;
-use reqwest::Client;
-use serde::{Deserialize, Serialize};
+use log::{debug, warn};
+use reqwest::Method;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use sha2::Sha256;
 
 use crate::{
-    cex::CexClient,
-    coin::Coin,
-    order::{OrderResponse, Side},
+    balance::ExchangeBalance,
+    candle::{self, Candle, Timeframe},
+    cex::{
+        CexClient,
+        clock::ServerClock,
+        error::CexError,
+        feed::{FeedEndpoint, FeedSource},
+        http::{HttpClient, RetryPolicy},
+    },
+    instrument::{InstrumentInfo, format_decimal},
+    order::{Order, OrderResponse, OrderStatus, OrderType, Side},
+    pair::Pair,
 };
 
 const BASE_URL: &str = "https://api.kucoin.com";
+// 429000 = too many requests
+const RATE_LIMIT_CODES: &[&str] = &["429000"];
+// maximum candles per request
+const CANDLES_LIMIT: usize = 1500;
+const ORDER_POLL_ATTEMPTS: u32 = 10;
+const ORDER_POLL_DELAY_MS: u64 = 500;
 
 type HmacSha256 = Hmac<Sha256>;
 
@@ -22,7 +37,9 @@ pub struct KucoinClient {
     api_key: String,
     secret: String,
     passphrase: String,
-    client: Client,
+    base: String,
+    http: HttpClient,
+    clock: ServerClock,
 }
 
 impl KucoinClient {
@@ -31,10 +48,25 @@ impl KucoinClient {
             api_key,
             secret,
             passphrase,
-            client: Client::builder().user_agent("kucoin-rust-client/0.1").build().unwrap(),
+            base: BASE_URL.to_string(),
+            http: HttpClient::new("kucoin-rust-client/0.1", RATE_LIMIT_CODES),
+            clock: ServerClock::new(),
         }
     }
 
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.http.set_policy(policy);
+        self
+    }
+
+    /// Sends the requests to `base` instead of the exchange (e.g. a local mock server), empty keeps the default
+    pub fn with_base_url(mut self, base: &str) -> Self {
+        if !base.is_empty() {
+            self.base = base.trim_end_matches('/').to_string();
+        }
+        self
+    }
+
     fn sign(&self, method: &str, endpoint: &str, body: &str, timestamp: &str) -> String {
         // Prehash string: timestamp + method + requestPath + body
         let prehash = format!("{timestamp}{method}{endpoint}{body}");
@@ -43,138 +75,499 @@ impl KucoinClient {
         let result = mac.finalize().into_bytes();
         general_purpose::STANDARD.encode(result)
     }
+
+    /// With API key version 2 the passphrase is sent signed by the API secret
+    fn signed_passphrase(&self) -> String {
+        let mut mac = HmacSha256::new_from_slice(self.secret.as_bytes()).expect("HMAC can take key of any size");
+        mac.update(self.passphrase.as_bytes());
+        general_purpose::STANDARD.encode(mac.finalize().into_bytes())
+    }
+
+    async fn get_public<T: DeserializeOwned>(&self, endpoint: &str) -> Result<T> {
+        let url = format!("{}{endpoint}", self.base);
+        debug!("GET: {}", url);
+        self.http.send_json(|client| client.get(&url)).await
+    }
+
+    /// Measures the offset of the server time when it is not known or is too old. A failure is logged and retried
+    /// after a minute, meanwhile the last known offset is used.
+    async fn sync_time(&self) {
+        if !self.clock.needs_sync() {
+            return;
+        }
+        let endpoint = "/api/v1/timestamp";
+        let sent = Utc::now().timestamp_millis();
+        let resp: Result<ApiResponse<i64>> = self.get_public(endpoint).await;
+        let received = Utc::now().timestamp_millis();
+        match resp.and_then(|resp| resp.into_result(endpoint)) {
+            Ok(server_ts) => self.clock.update(server_ts, sent, received),
+            Err(e) => {
+                warn!("Failed to fetch KuCoin server time: {e}");
+                self.clock.mark_failed();
+            }
+        }
+    }
+
+    async fn get_signed<T: DeserializeOwned>(&self, endpoint: &str) -> Result<T> {
+        self.request_signed(Method::GET, endpoint, "").await
+    }
+
+    /// POST requests create orders, they are not replayed after a timeout
+    async fn request_signed<T: DeserializeOwned>(&self, method: Method, endpoint: &str, body: &str) -> Result<T> {
+        self.sync_time().await;
+        let url = format!("{}{endpoint}", self.base);
+        debug!("{}: {}", method, url);
+        let build = |client: &reqwest::Client| {
+            let ts = self.clock.now().timestamp_millis().to_string();
+            let sign = self.sign(method.as_str(), endpoint, body, &ts);
+            let req = client
+                .request(method.clone(), &url)
+                .header("KC-API-KEY", &self.api_key)
+                .header("KC-API-SIGN", sign)
+                .header("KC-API-TIMESTAMP", ts)
+                .header("KC-API-PASSPHRASE", self.signed_passphrase())
+                .header("KC-API-KEY-VERSION", "2");
+            if body.is_empty() {
+                req
+            } else {
+                req.header("Content-Type", "application/json").body(body.to_string())
+            }
+        };
+        if method == Method::POST {
+            self.http.send_json_once(build).await
+        } else {
+            self.http.send_json(build).await
+        }
+    }
+
+    fn symbol(pair: &Pair) -> String {
+        pair.join("-")
+    }
+
+    fn candle_type(timeframe: Timeframe) -> &'static str {
+        match timeframe {
+            Timeframe::Min1 => "1min",
+            Timeframe::Min5 => "5min",
+            Timeframe::Hour1 => "1hour",
+            Timeframe::Hour4 => "4hour",
+            Timeframe::Day1 => "1day",
+            Timeframe::Week1 => "1week",
+        }
+    }
+
+    /// Aggregates the fills of the order into (amount, average price, coin fee, USDT fee)
+    async fn get_fills(&self, pair: &Pair, order_id: &str) -> Result<(f64, f64, f64, f64)> {
+        let endpoint = format!("/api/v1/fills?orderId={order_id}&pageSize=500");
+        let resp: ApiResponse<KucoinFillPage> = self.get_signed(&endpoint).await?;
+
+        let mut executed_amount = 0.0;
+        let mut executed_value = 0.0;
+        let mut btc_fee = 0.0;
+        let mut usd_fee = 0.0;
+        for fill in resp.into_result(&endpoint)?.items {
+            let fee: f64 = fill.fee.parse()?;
+            executed_amount += fill.size.parse::<f64>()?;
+            executed_value += fill.funds.parse::<f64>()?;
+            if fill.fee_currency == pair.base {
+                btc_fee += fee;
+            } else if fill.fee_currency == pair.quote {
+                usd_fee += fee;
+            } else {
+                debug!("Ignoring fee {} {}", fee, fill.fee_currency);
+            }
+        }
+        let executed_price = if executed_amount > 0.0 {
+            executed_value / executed_amount
+        } else {
+            0.0
+        };
+        Ok((executed_amount, executed_price, btc_fee, usd_fee))
+    }
+}
+
+impl KucoinOrder {
+    fn into_order(self, pair: &Pair) -> Result<Order> {
+        let amount: f64 = self.size.parse().unwrap_or(0.0);
+        let filled_amount: f64 = self.deal_size.parse()?;
+        let deal_funds: f64 = self.deal_funds.parse()?;
+        let fee: f64 = self.fee.parse()?;
+        Ok(Order {
+            side: match self.side.as_str() {
+                "buy" => Side::Buy,
+                "sell" => Side::Sell,
+                other => return Err(anyhow::format_err!("Unknown KuCoin order side {other}")),
+            },
+            status: if self.is_active {
+                Order::open_status(amount, filled_amount)
+            } else if self.cancel_exist {
+                OrderStatus::Cancelled
+            } else {
+                OrderStatus::Filled
+            },
+            amount,
+            price: self.price.parse::<f64>().ok().filter(|price| *price > 0.0),
+            filled_amount,
+            avg_price: if filled_amount > 0.0 {
+                deal_funds / filled_amount
+            } else {
+                0.0
+            },
+            btc_fee: if self.fee_currency == pair.base { fee } else { 0.0 },
+            usd_fee: if self.fee_currency == pair.quote { fee } else { 0.0 },
+            id: self.id,
+        })
+    }
 }
 
 #[async_trait]
 impl CexClient for KucoinClient {
-    async fn get_price(&self, coin: &Coin) -> Result<f64> {
-        let endpoint = format!("/api/v1/market/orderbook/level1?symbol={}-USDT", coin.symbol());
-        let url = format!("{BASE_URL}{endpoint}");
-        let resp = self
-            .client
-            .get(&url)
-            .send()
-            .await?
-            .json::<ApiResponse<OrderBookLevel1>>()
-            .await?;
-        let data = resp
-            .data
-            .ok_or_else(|| anyhow::anyhow!("No data returned from KuCoin API"))?;
-        Ok(data.price.parse()?)
-    }
-
-    async fn get_historical(&self, coin: &Coin, days: u32) -> Result<Vec<f64>> {
-        // End = today's midnight UTC minus 1 second (i.e., end of yesterday)
-        let today_midnight = Utc::now()
-            .date_naive()
-            .and_hms_opt(0, 0, 0)
-            .expect("valid time")
-            .and_local_timezone(Utc)
-            .unwrap();
-        let end_ts = (today_midnight - Duration::seconds(1)).timestamp();
-
-        // Start = end_ts minus number of days
-        let start_ts = (today_midnight - Duration::days(days as i64) - Duration::seconds(1)).timestamp();
+    async fn get_price(&self, pair: &Pair) -> Result<f64> {
+        let endpoint = format!("/api/v1/market/orderbook/level1?symbol={}", Self::symbol(pair));
+        let resp: ApiResponse<OrderBookLevel1> = self.get_public(&endpoint).await?;
+        Ok(resp.into_result(&endpoint)?.price.parse()?)
+    }
 
-        let endpoint = format!(
-            "/api/v1/market/candles?symbol={}-USDT&startAt={}&endAt={}&type=1day",
-            coin.symbol(),
-            start_ts,
-            end_ts
-        );
-        let url = format!("{BASE_URL}{endpoint}");
-        debug!("GET: {}", url);
+    async fn get_candles(&self, pair: &Pair, timeframe: Timeframe, bars: u32) -> Result<Vec<Candle>> {
+        // End = start of the current bar minus 1 second (i.e., end of the last finished bar)
+        let (start, end) = timeframe.window(Utc::now(), bars);
+        let end_ts = (end - Duration::seconds(1)).timestamp();
 
-        let resp = self
-            .client
-            .get(&url)
-            .send()
-            .await?
-            .json::<ApiResponse<Vec<Vec<String>>>>()
-            .await?;
-
-        let mut data = resp
-            .data
-            .ok_or_else(|| anyhow::anyhow!("No data returned from KuCoin API"))?;
-
-        // Sort by timestamp (c[0]), oldest first
-        data.sort_by(|a, b| a[0].parse::<i64>().unwrap().cmp(&b[0].parse::<i64>().unwrap()));
-
-        // 0 - start time of the candle cycle,
-        // 1 - opening price,
-        // 2 - closing price,
-        // 3 - highest price,
-        // 4 - lowest price,
-        // 5 - transaction volume (in base asset),
-        // 6 - transaction amount (in quote asset)
-        // Extrahujeme iba záverečné ceny
-        let prices = data
-            .into_iter()
-            .map(
-                |c| {
-                    (c[3].parse::<f64>().expect("parsing highest price")
-                        + c[4].parse::<f64>().expect("parsing lower price"))
-                        / 2.0
-                }, // Average of high and low prices
-            )
-            .collect();
+        // Start = start of the first bar minus 1 second
+        let start_ts = (start - Duration::seconds(1)).timestamp();
+
+        // Pages are returned newest first, the next page ends before the oldest candle so far
+        let mut candles = Vec::new();
+        let mut end_at = end_ts;
+        loop {
+            let endpoint = format!(
+                "/api/v1/market/candles?symbol={}&startAt={}&endAt={}&type={}",
+                Self::symbol(pair),
+                start_ts,
+                end_at,
+                Self::candle_type(timeframe)
+            );
+            let resp: ApiResponse<Vec<Vec<String>>> = self.get_public(&endpoint).await?;
+            let page = resp
+                .into_result(&endpoint)?
+                .iter()
+                .map(|c| parse_candle(c))
+                .collect::<Result<Vec<Candle>>>()?;
+            let count = page.len();
+            let oldest = page.iter().map(|c| c.ts / 1000).min();
+            candles.extend(page);
+
+            match oldest {
+                Some(oldest) if count >= CANDLES_LIMIT && oldest > start_ts + 1 && oldest <= end_at => {
+                    end_at = oldest - 1
+                }
+                _ => break,
+            }
+        }
+
+        Ok(candle::merge_pages(candles, timeframe))
+    }
+
+    async fn instrument_info(&self, pair: &Pair) -> Result<InstrumentInfo> {
+        let endpoint = format!("/api/v2/symbols/{}", Self::symbol(pair));
+        let resp: ApiResponse<KucoinSymbol> = self.get_public(&endpoint).await?;
+        let symbol = resp.into_result(&endpoint)?;
+        Ok(InstrumentInfo {
+            min_size: symbol.base_min_size.parse()?,
+            size_increment: symbol.base_increment.parse()?,
+            price_tick: symbol.price_increment.parse()?,
+            min_notional: symbol.min_funds.as_deref().unwrap_or("0").parse()?,
+        })
+    }
+
+    async fn get_balances(&self, pair: &Pair) -> Result<ExchangeBalance> {
+        let endpoint = "/api/v1/accounts?type=trade";
+        let resp: ApiResponse<Vec<KucoinAccount>> = self.get_signed(endpoint).await?;
+        let accounts = resp.into_result(endpoint)?;
 
-        Ok(prices)
+        let mut balance = ExchangeBalance::default();
+        for account in accounts {
+            if account.currency == pair.base {
+                balance.btc_balance += account.available.parse::<f64>()?;
+            } else if account.currency == pair.quote {
+                balance.usd_balance += account.available.parse::<f64>()?;
+            }
+        }
+        Ok(balance)
     }
 
-    async fn put_order(&self, coin: &Coin, side: Side, amount: f64, _price: Option<f64>) -> Result<OrderResponse> {
-        let inst = format!("{}-USDT", coin.symbol());
+    async fn put_order(
+        &self,
+        pair: &Pair,
+        side: Side,
+        amount: f64,
+        order_type: OrderType,
+        price: Option<f64>,
+    ) -> Result<OrderResponse> {
+        let inst = Self::symbol(pair);
         let endpoint = "/api/v1/orders";
-        let ts = Utc::now().timestamp().to_string();
+        let price = order_type.limit_price(price)?;
 
         let req = KucoinOrderRequest {
+            client_oid: Utc::now().timestamp_nanos_opt().unwrap_or_default().to_string(),
             symbol: &inst,
             side: match side {
                 Side::Buy => "buy",
                 Side::Sell => "sell",
             },
-            type_: "market",
-            size: amount.to_string(),
+            type_: match order_type {
+                OrderType::Market => "market",
+                _ => "limit",
+            },
+            size: format_decimal(amount),
+            price: price.map(format_decimal),
+            time_in_force: match order_type {
+                OrderType::Market => None,
+                OrderType::Ioc => Some("IOC"),
+                _ => Some("GTC"),
+            },
+            post_only: (order_type == OrderType::PostOnly).then_some(true),
         };
         let body = serde_json::to_string(&req)?;
-        let sign = self.sign("POST", endpoint, &body, &ts);
-
-        let url = format!("{BASE_URL}{endpoint}");
-        let resp = self
-            .client
-            .post(&url)
-            .header("KC-API-KEY", &self.api_key)
-            .header("KC-API-SIGN", sign)
-            .header("KC-API-TIMESTAMP", ts)
-            .header("KC-API-PASSPHRASE", &self.passphrase)
-            .header("KC-API-KEY-VERSION", "2")
-            .header("Content-Type", "application/json")
-            .body(body)
-            .send()
-            .await?
-            .json::<KucoinOrderResponse>()
-            .await?;
-
-        let data = resp.data;
-        let executed_price: f64 = data.price.parse()?;
-        let executed_amount: f64 = data.size.parse()?;
-        let btc_fee: f64 = data.fee.parse()?;
-        let usd_fee = btc_fee * executed_price;
+        let resp: ApiResponse<KucoinOrderResponseData> = self.request_signed(Method::POST, endpoint, &body).await?;
+        // only the order id is returned, fills have to be fetched separately
+        let order_id = resp.into_result(endpoint)?.order_id;
+
+        // Market and IOC orders are done almost immediately, wait until they are closed. Limit order can rest on
+        // the book.
+        let mut filled_amount = 0.0;
+        if matches!(order_type, OrderType::Market | OrderType::Ioc) {
+            for _ in 0..ORDER_POLL_ATTEMPTS {
+                let order = self.get_order(pair, &order_id).await?;
+                filled_amount = order.filled_amount;
+                if !order.status.is_open() {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(ORDER_POLL_DELAY_MS)).await;
+            }
+        }
+
+        // fills can appear with a small delay after the order is done
+        let mut fills = self.get_fills(pair, &order_id).await?;
+        for _ in 0..ORDER_POLL_ATTEMPTS {
+            if filled_amount <= 0.0 || fills.0 > 0.0 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(ORDER_POLL_DELAY_MS)).await;
+            fills = self.get_fills(pair, &order_id).await?;
+        }
+        let (executed_amount, executed_price, btc_fee, usd_fee) = fills;
+        if executed_amount <= 0.0 && order_type == OrderType::Market {
+            return Err(anyhow::format_err!("KuCoin order {order_id} was not filled"));
+        }
         Ok(OrderResponse {
+            order_id,
             executed_price,
             executed_amount,
             btc_fee,
             usd_fee,
         })
     }
+
+    async fn get_order(&self, pair: &Pair, order_id: &str) -> Result<Order> {
+        let endpoint = format!("/api/v1/orders/{order_id}");
+        let resp: ApiResponse<KucoinOrder> = self.get_signed(&endpoint).await?;
+        resp.into_result(&endpoint)?.into_order(pair)
+    }
+
+    async fn cancel_order(&self, _pair: &Pair, order_id: &str) -> Result<()> {
+        let endpoint = format!("/api/v1/orders/{order_id}");
+        let resp: ApiResponse<serde_json::Value> = self.request_signed(Method::DELETE, &endpoint, "").await?;
+        resp.into_result(&endpoint)?;
+        Ok(())
+    }
+
+    async fn open_orders(&self, pair: &Pair) -> Result<Vec<Order>> {
+        // 500 is the maximum page size, more open orders are not expected
+        let endpoint = format!(
+            "/api/v1/orders?status=active&symbol={}&pageSize=500",
+            Self::symbol(pair)
+        );
+        let resp: ApiResponse<KucoinOrderPage> = self.get_signed(&endpoint).await?;
+        resp.into_result(&endpoint)?
+            .items
+            .into_iter()
+            .map(|order| order.into_order(pair))
+            .collect()
+    }
+}
+
+/// Public ticker stream of KuCoin
+pub struct KucoinPriceFeed {
+    // REST API which hands out the WebSocket server and token
+    base: String,
+    http: HttpClient,
+}
+
+impl Default for KucoinPriceFeed {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KucoinPriceFeed {
+    pub fn new() -> Self {
+        KucoinPriceFeed {
+            base: BASE_URL.to_string(),
+            http: HttpClient::new("kucoin-rust-client/0.1", RATE_LIMIT_CODES),
+        }
+    }
+
+    /// Asks `base` for the WebSocket server instead of the exchange, empty keeps the default
+    pub fn with_base_url(mut self, base: &str) -> Self {
+        if !base.is_empty() {
+            self.base = base.trim_end_matches('/').to_string();
+        }
+        self
+    }
+
+    fn request_id() -> String {
+        Utc::now().timestamp_millis().to_string()
+    }
+}
+
+#[async_trait]
+impl FeedSource for KucoinPriceFeed {
+    fn name(&self) -> &'static str {
+        "KuCoin"
+    }
+
+    /// The server and a token valid for one connection are obtained by a REST request
+    async fn endpoint(&self) -> Result<FeedEndpoint> {
+        let endpoint = "/api/v1/bullet-public";
+        let url = format!("{}{endpoint}", self.base);
+        debug!("POST: {}", url);
+        let resp: ApiResponse<KucoinBullet> = self.http.send_json(|client| client.post(&url)).await?;
+        let bullet = resp.into_result(endpoint)?;
+        let server = bullet
+            .instance_servers
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("No KuCoin WebSocket server"))?;
+        Ok(FeedEndpoint {
+            url: format!(
+                "{}?token={}&connectId={}",
+                server.endpoint,
+                bullet.token,
+                Self::request_id()
+            ),
+            ping_interval: std::time::Duration::from_millis(server.ping_interval),
+        })
+    }
+
+    fn subscribe_message(&self, pair: &Pair) -> String {
+        serde_json::json!({
+            "id": Self::request_id(),
+            "type": "subscribe",
+            "topic": format!("/market/ticker:{}", KucoinClient::symbol(pair)),
+            "privateChannel": false,
+            "response": true,
+        })
+        .to_string()
+    }
+
+    fn ping_message(&self) -> String {
+        serde_json::json!({ "id": Self::request_id(), "type": "ping" }).to_string()
+    }
+
+    fn parse_price(&self, text: &str) -> Option<f64> {
+        let push: KucoinTickerPush = serde_json::from_str(text).ok()?;
+        if push.type_ != "message" {
+            return None;
+        }
+        push.data?.price.parse().ok()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct KucoinBullet {
+    token: String,
+    instance_servers: Vec<KucoinInstanceServer>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct KucoinInstanceServer {
+    endpoint: String,
+    // milliseconds
+    ping_interval: u64,
+}
+
+#[derive(Deserialize)]
+struct KucoinTickerPush {
+    #[serde(rename = "type")]
+    type_: String,
+    data: Option<OrderBookLevel1>,
 }
 
 #[derive(Deserialize)]
 struct ApiResponse<T> {
-    //code: String,
+    #[serde(default)]
+    code: String,
+    #[serde(default)]
+    msg: String,
     data: Option<T>,
 }
 
+impl<T> ApiResponse<T> {
+    fn into_result(self, endpoint: &str) -> Result<T> {
+        check_code(&self.code, &self.msg)?;
+        self.data
+            .ok_or_else(|| anyhow::format_err!("No data returned from KuCoin API. Endpoint {}", endpoint))
+    }
+}
+
+/// Parses the candle row:
+/// 0 - start time of the candle cycle (seconds),
+/// 1 - opening price,
+/// 2 - closing price,
+/// 3 - highest price,
+/// 4 - lowest price,
+/// 5 - transaction volume (in base asset),
+/// 6 - transaction amount (in quote asset)
+pub(crate) fn parse_candle(c: &[String]) -> Result<Candle> {
+    let value = |i: usize| {
+        c.get(i)
+            .ok_or_else(|| anyhow::anyhow!("KuCoin candle has no value {i}"))
+    };
+    Ok(Candle {
+        ts: value(0)?.parse::<i64>()? * 1000,
+        open: value(1)?.parse()?,
+        close: value(2)?.parse()?,
+        high: value(3)?.parse()?,
+        low: value(4)?.parse()?,
+        volume: value(5)?.parse()?,
+    })
+}
+
+/// Fails with the classified error unless the code is "200000" (success)
+fn check_code(code: &str, msg: &str) -> Result<(), CexError> {
+    let msg = msg.to_string();
+    match code {
+        "200000" => Ok(()),
+        // missing headers, invalid timestamp, key, passphrase or signature, IP not whitelisted, no permission,
+        // frozen account
+        "400001" | "400002" | "400003" | "400004" | "400005" | "400006" | "400007" | "411100" => {
+            Err(CexError::Auth(msg))
+        }
+        "200004" => Err(CexError::InsufficientFunds(msg)),
+        "429000" => Err(CexError::RateLimited(msg)),
+        // parameter error, the size problems are told apart only by the message
+        "400100" if msg.to_lowercase().contains("size") || msg.to_lowercase().contains("funds") => {
+            Err(CexError::InvalidSize(msg))
+        }
+        "500000" => Err(CexError::Network(msg)),
+        _ => Err(CexError::Unknown {
+            code: code.to_string(),
+            msg,
+        }),
+    }
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct OrderBookLevel1 {
@@ -184,6 +577,22 @@ struct OrderBookLevel1 {
     //time: i64,
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct KucoinSymbol {
+    base_min_size: String,
+    base_increment: String,
+    price_increment: String,
+    // minimum order value in the quote currency
+    min_funds: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct KucoinAccount {
+    currency: String,
+    available: String,
+}
+
 /*
 #[derive(Debug)]
 struct Candle {
@@ -210,23 +619,122 @@ pub struct OrderResponse {
 */
 
 #[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
 struct KucoinOrderRequest<'a> {
+    client_oid: String,
     symbol: &'a str,
     side: &'a str,
+    #[serde(rename = "type")]
     type_: &'a str,
     size: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    price: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time_in_force: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    post_only: Option<bool>,
 }
 
 #[derive(Deserialize)]
-struct KucoinOrderResponseData {
+#[serde(rename_all = "camelCase")]
+struct KucoinOrder {
+    id: String,
+    side: String,
     price: String,
     size: String,
+    deal_size: String,
+    deal_funds: String,
+    fee: String,
+    fee_currency: String,
+    is_active: bool,
+    cancel_exist: bool,
+}
+
+#[derive(Deserialize)]
+struct KucoinOrderPage {
+    items: Vec<KucoinOrder>,
+}
+
+#[derive(Deserialize)]
+struct KucoinOrderResponseData {
+    #[serde(rename = "orderId")]
+    order_id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct KucoinFill {
+    size: String,
+    // size * price in the quote currency
+    funds: String,
     fee: String,
+    fee_currency: String,
 }
 
 #[derive(Deserialize)]
-struct KucoinOrderResponse {
-    data: KucoinOrderResponseData,
+struct KucoinFillPage {
+    items: Vec<KucoinFill>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_candle_row() {
+        // start time in seconds, open, close, high, low, volume, turnover
+        let candle = parse_candle(&row(&["1700000000", "1", "2", "3", "0.5", "10", "0"])).unwrap();
+        assert_eq!(candle.ts, 1700000000000);
+        assert_eq!(
+            (candle.open, candle.high, candle.low, candle.close),
+            (1.0, 3.0, 0.5, 2.0)
+        );
+        assert_eq!(candle.volume, 10.0);
+    }
+
+    #[test]
+    fn parse_candle_rejects_short_and_invalid_rows() {
+        assert!(parse_candle(&row(&["1700000000", "1", "2", "3", "0.5"])).is_err());
+        assert!(parse_candle(&[]).is_err());
+        assert!(parse_candle(&row(&["x", "1", "2", "3", "0.5", "10"])).is_err());
+    }
+
+    #[test]
+    fn check_code_classifies_the_errors() {
+        let m = |msg: &str| msg.to_string();
+        for (code, msg, expected) in [
+            ("200000", "", Ok(())),
+            ("400005", "bad sign", Err(CexError::Auth(m("bad sign")))),
+            ("411100", "frozen", Err(CexError::Auth(m("frozen")))),
+            ("200004", "balance", Err(CexError::InsufficientFunds(m("balance")))),
+            ("429000", "too many", Err(CexError::RateLimited(m("too many")))),
+            (
+                "400100",
+                "Size too small",
+                Err(CexError::InvalidSize(m("Size too small"))),
+            ),
+            (
+                "400100",
+                "Funds below minimum",
+                Err(CexError::InvalidSize(m("Funds below minimum"))),
+            ),
+            (
+                "400100",
+                "Invalid symbol",
+                Err(CexError::Unknown {
+                    code: "400100".into(),
+                    msg: m("Invalid symbol"),
+                }),
+            ),
+            ("500000", "busy", Err(CexError::Network(m("busy")))),
+        ] {
+            assert_eq!(check_code(code, msg), expected, "code {code}: {msg}");
+        }
+    }
 }
 
 // eof
@@ -3,12 +3,31 @@ use async_trait::async_trait;
 use chrono::{NaiveDate, Utc};
 
 use crate::{
-    cex::{kucoin::KucoinClient, okx::OkxClient, simulate::SimulateClient},
-    coin::Coin,
+    balance::ExchangeBalance,
+    candle::{Candle, Timeframe},
+    cex::{
+        binance::BinanceClient,
+        bybit::BybitClient,
+        feed::{FeedSource, PriceFeed},
+        http::RetryPolicy,
+        kraken::KrakenClient,
+        kucoin::{KucoinClient, KucoinPriceFeed},
+        okx::{OkxClient, OkxPriceFeed},
+        simulate::SimulateClient,
+    },
     config::Config,
-    order::{OrderResponse, Side},
+    instrument::InstrumentInfo,
+    order::{Order, OrderResponse, OrderType, Side},
+    pair::Pair,
 };
 
+pub mod binance;
+pub mod bybit;
+pub mod clock;
+pub mod error;
+pub mod feed;
+pub mod http;
+pub mod kraken;
 pub mod kucoin;
 pub mod okx;
 pub mod simulate;
@@ -16,30 +35,100 @@ pub mod simulate;
 /// Trait that governs all clients for centralized exchanges
 #[async_trait]
 pub trait CexClient: Send + Sync {
-    /// Gets the current price of the symbol in USDT
-    async fn get_price(&self, coin: &Coin) -> Result<f64>;
+    /// Gets the current price of the pair in the quote currency
+    async fn get_price(&self, pair: &Pair) -> Result<f64>;
 
-    /// Gets historical prices of the symbol for the last `days` days, oldest first
-    async fn get_historical(&self, coin: &Coin, days: u32) -> Result<Vec<f64>>;
+    /// Gets the last `bars` finished candles of the pair, oldest first
+    async fn get_candles(&self, pair: &Pair, timeframe: Timeframe, bars: u32) -> Result<Vec<Candle>>;
 
-    // Places a market order on the exchange
-    async fn put_order(&self, coin: &Coin, side: Side, amount: f64, price: Option<f64>) -> Result<OrderResponse>;
+    /// Gets historical mid prices ((high + low) / 2) of the last `bars` finished candles, oldest first
+    async fn get_historical(&self, pair: &Pair, timeframe: Timeframe, bars: u32) -> Result<Vec<f64>> {
+        Ok(self
+            .get_candles(pair, timeframe, bars)
+            .await?
+            .iter()
+            .map(Candle::mid)
+            .collect())
+    }
+
+    /// Gets the trading rules (order size and price steps, minimums) of the pair
+    async fn instrument_info(&self, pair: &Pair) -> Result<InstrumentInfo>;
+
+    /// Gets available balances of the base and quote currency on the account
+    async fn get_balances(&self, pair: &Pair) -> Result<ExchangeBalance>;
+
+    /// Places an order on the exchange. `price` is the limit price, it is required for all but market orders.
+    async fn put_order(
+        &self,
+        pair: &Pair,
+        side: Side,
+        amount: f64,
+        order_type: OrderType,
+        price: Option<f64>,
+    ) -> Result<OrderResponse>;
+
+    /// Gets the current state of the order placed by `put_order`
+    async fn get_order(&self, pair: &Pair, order_id: &str) -> Result<Order>;
+
+    /// Cancels the order, fails when it is not open anymore
+    async fn cancel_order(&self, pair: &Pair, order_id: &str) -> Result<()>;
+
+    /// Lists the orders of the pair which are still resting on the order book
+    async fn open_orders(&self, pair: &Pair) -> Result<Vec<Order>>;
 }
 
 pub fn create_cex_client_from_config(config: &Config) -> Result<Box<dyn CexClient>> {
-    match config.cex.to_lowercase().as_str() {
-        "kucoin" => Ok(Box::new(KucoinClient::new(
-            config.cex_api_key.clone(),
-            config.cex_api_secret.clone(),
-            config.cex_api_passphrase.clone(),
-        ))),
-        "okx" => Ok(Box::new(OkxClient::new(
-            config.cex_api_key.clone(),
-            config.cex_api_secret.clone(),
-            config.cex_api_passphrase.clone(),
-            true,
-        ))),
+    let cex = config.cex.to_lowercase();
+    if config.cex_demo && matches!(cex.as_str(), "kraken" | "kucoin") {
+        anyhow::bail!("Demo trading is not supported on {}", config.cex);
+    }
+    match cex.as_str() {
+        "binance" => Ok(Box::new(
+            BinanceClient::new(
+                config.cex_api_key.clone(),
+                config.cex_api_secret.clone(),
+                config.cex_demo,
+            )
+            .with_retry_policy(RetryPolicy::from(config))
+            .with_base_url(&config.cex_base_url),
+        )),
+        "bybit" => Ok(Box::new(
+            BybitClient::new(
+                config.cex_api_key.clone(),
+                config.cex_api_secret.clone(),
+                config.cex_demo,
+            )
+            .with_retry_policy(RetryPolicy::from(config))
+            .with_base_url(&config.cex_base_url),
+        )),
+        "kraken" => Ok(Box::new(
+            KrakenClient::new(config.cex_api_key.clone(), config.cex_api_secret.clone())
+                .with_retry_policy(RetryPolicy::from(config))
+                .with_base_url(&config.cex_base_url),
+        )),
+        "kucoin" => Ok(Box::new(
+            KucoinClient::new(
+                config.cex_api_key.clone(),
+                config.cex_api_secret.clone(),
+                config.cex_api_passphrase.clone(),
+            )
+            .with_retry_policy(RetryPolicy::from(config))
+            .with_base_url(&config.cex_base_url),
+        )),
+        "okx" => Ok(Box::new(
+            OkxClient::new(
+                config.cex_api_key.clone(),
+                config.cex_api_secret.clone(),
+                config.cex_api_passphrase.clone(),
+                config.cex_demo,
+            )
+            .with_retry_policy(RetryPolicy::from(config))
+            .with_base_url(&config.cex_base_url),
+        )),
         "simulate" => {
+            if config.timeframe != Timeframe::Day1 {
+                anyhow::bail!("Simulation supports only the 1d timeframe, not {}", config.timeframe);
+            }
             let simulate_int: u32 = config.simulate_day; // 20250722
             let year = (simulate_int / 10_000) as i32; // 2025
             let month = (simulate_int / 100) % 100; // 07
@@ -55,7 +144,12 @@ pub fn create_cex_client_from_config(config: &Config) -> Result<Box<dyn CexClien
                 anyhow::bail!("simulate_day is in the future!");
             }
 
-            if let Ok(simulate) = SimulateClient::new(config.simulate_file.clone(), days_back as u32) {
+            if let Ok(simulate) = SimulateClient::new(
+                config.simulate_file.clone(),
+                days_back as u32,
+                config.initial_btc,
+                config.initial_usd,
+            ) {
                 return Ok(Box::new(simulate));
             }
             Err(anyhow::anyhow!("Invalid simulate client"))
@@ -64,4 +158,17 @@ pub fn create_cex_client_from_config(config: &Config) -> Result<Box<dyn CexClien
     }
 }
 
+/// Starts the streaming price feed of the exchange when `price_feed` is enabled. There is none in the simulation.
+pub fn create_price_feed_from_config(config: &Config) -> Result<Option<PriceFeed>> {
+    if !config.price_feed || config.is_simulation() {
+        return Ok(None);
+    }
+    let source: Box<dyn FeedSource> = match config.cex.to_lowercase().as_str() {
+        "okx" => Box::new(OkxPriceFeed::new(config.cex_demo).with_base_url(&config.cex_base_url)),
+        "kucoin" => Box::new(KucoinPriceFeed::new().with_base_url(&config.cex_base_url)),
+        _ => anyhow::bail!("Price feed is not supported on {}", config.cex),
+    };
+    Ok(Some(PriceFeed::start(source, config.pair())))
+}
+
 // eof
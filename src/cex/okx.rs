@@ -3,18 +3,35 @@ use async_trait::async_trait;
 use base64::{Engine as _, engine::general_purpose};
 use chrono::{Duration, SecondsFormat, Utc};
 use hmac::{Hmac, Mac};
-use log::debug;
-use reqwest::Client;
-use serde::{Deserialize, Serialize};
+use log::{debug, warn};
+use reqwest::RequestBuilder;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use sha2::Sha256;
 
 use crate::{
-    cex::CexClient,
-    coin::Coin,
-    order::{OrderResponse, Side},
+    balance::ExchangeBalance,
+    candle::{self, Candle, Timeframe},
+    cex::{
+        CexClient,
+        clock::ServerClock,
+        error::CexError,
+        feed::{FeedEndpoint, FeedSource},
+        http::{HttpClient, RetryPolicy},
+    },
+    instrument::{InstrumentInfo, format_decimal},
+    order::{Order, OrderResponse, OrderStatus, OrderType, Side},
+    pair::Pair,
 };
 
 const OKX_LIVE: &str = "https://www.okx.com";
+const OKX_WS_PATH: &str = "/ws/v5/public";
+const OKX_WS_LIVE: &str = "wss://ws.okx.com:8443/ws/v5/public";
+const OKX_WS_DEMO: &str = "wss://wspap.okx.com:8443/ws/v5/public";
+// maximum candles per request of the recent candles and of the history endpoint
+const CANDLES_LIMIT: usize = 300;
+const HISTORY_CANDLES_LIMIT: usize = 100;
+// 50011 = too many requests, 50061 = sub-account rate limit
+const RATE_LIMIT_CODES: &[&str] = &["50011", "50061"];
 
 type HmacSha256 = Hmac<Sha256>;
 
@@ -23,23 +40,38 @@ pub struct OkxClient {
     secret: String,
     passphrase: String,
     base: String,
-    client: Client,
+    http: HttpClient,
+    clock: ServerClock,
     is_demo: bool,
 }
 
 impl OkxClient {
+    /// `is_demo` trades on the demo account (requests are sent with the `X-SIMULATED-TRADING` header)
     pub fn new(api_key: String, secret: String, passphrase: String, is_demo: bool) -> Self {
         OkxClient {
             api_key,
             secret,
             passphrase,
-            client: Client::builder().user_agent("okx-rust-client/0.1").build().unwrap(),
+            http: HttpClient::new("okx-rust-client/0.1", RATE_LIMIT_CODES),
+            clock: ServerClock::new(),
             base: OKX_LIVE.to_string(),
             is_demo,
-            //base: if demo { OKX_TEST.to_string() } else { OKX_LIVE.to_string() },
         }
     }
 
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.http.set_policy(policy);
+        self
+    }
+
+    /// Sends the requests to `base` instead of the exchange (e.g. a local mock server), empty keeps the default
+    pub fn with_base_url(mut self, base: &str) -> Self {
+        if !base.is_empty() {
+            self.base = base.trim_end_matches('/').to_string();
+        }
+        self
+    }
+
     fn sign(&self, method: &str, endpoint: &str, body: &str, timestamp: &str) -> String {
         // Prehash string: timestamp + method + requestPath + body
         let prehash = format!("{timestamp}{method}{endpoint}{body}");
@@ -49,100 +81,294 @@ impl OkxClient {
         general_purpose::STANDARD.encode(result)
     }
 
-    async fn get_order_details(&self, inst: &str, ord_id: &str) -> Result<OkxOrderDetailsData> {
-        let endpoint = format!("/api/v5/trade/order?instId={inst}&ordId={ord_id}");
+    async fn get_public<T: DeserializeOwned>(&self, endpoint: &str) -> Result<T> {
+        let url = format!("{}{}", self.base, endpoint);
+        debug!("GET {}", url);
+        self.http.send_json(|client| client.get(&url)).await
+    }
 
-        let ts = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
-        let sign = self.sign("GET", &endpoint, "", &ts);
+    /// Measures the offset of the server time when it is not known or is too old. A failure is logged and retried
+    /// after a minute, meanwhile the last known offset is used.
+    async fn sync_time(&self) {
+        if !self.clock.needs_sync() {
+            return;
+        }
+        let sent = Utc::now().timestamp_millis();
+        let resp: Result<OkxTimeResponse> = self.get_public("/api/v5/public/time").await;
+        let received = Utc::now().timestamp_millis();
+        let server_ts = resp.and_then(|resp| {
+            check_code(&resp.code, &resp.msg)?;
+            let data = resp
+                .data
+                .first()
+                .ok_or_else(|| anyhow::anyhow!("Empty OKX time response"))?;
+            Ok(data.ts.parse::<i64>()?)
+        });
+        match server_ts {
+            Ok(server_ts) => self.clock.update(server_ts, sent, received),
+            Err(e) => {
+                warn!("Failed to fetch OKX server time: {e}");
+                self.clock.mark_failed();
+            }
+        }
+    }
+
+    async fn get_signed<T: DeserializeOwned>(&self, endpoint: &str) -> Result<T> {
+        self.sync_time().await;
+        let url = format!("{}{}", self.base, endpoint);
+        debug!("GET {}", url);
+        self.http
+            .send_json(|client| {
+                let ts = self.clock.now().to_rfc3339_opts(SecondsFormat::Millis, true);
+                let sign = self.sign("GET", endpoint, "", &ts);
+                let req = client
+                    .get(&url)
+                    .header("OK-ACCESS-KEY", &self.api_key)
+                    .header("OK-ACCESS-SIGN", sign)
+                    .header("OK-ACCESS-TIMESTAMP", ts)
+                    .header("OK-ACCESS-PASSPHRASE", &self.passphrase);
+                self.demo_header(req)
+            })
+            .await
+    }
 
+    /// POST requests change the state (orders), they are not replayed after a timeout
+    async fn post_signed<T: DeserializeOwned>(&self, endpoint: &str, body: String) -> Result<T> {
+        self.sync_time().await;
         let url = format!("{}{}", self.base, endpoint);
-        let mut req = self
-            .client
-            .get(&url)
-            .header("OK-ACCESS-KEY", &self.api_key)
-            .header("OK-ACCESS-SIGN", sign)
-            .header("OK-ACCESS-TIMESTAMP", ts.clone())
-            .header("OK-ACCESS-PASSPHRASE", &self.passphrase);
+        debug!("POST {}", url);
+        self.http
+            .send_json_once(|client| {
+                let ts = self.clock.now().to_rfc3339_opts(SecondsFormat::Millis, true);
+                let sign = self.sign("POST", endpoint, &body, &ts);
+                let req = client
+                    .post(&url)
+                    .header("OK-ACCESS-KEY", &self.api_key)
+                    .header("OK-ACCESS-SIGN", sign)
+                    .header("OK-ACCESS-TIMESTAMP", ts)
+                    .header("OK-ACCESS-PASSPHRASE", &self.passphrase)
+                    .header("Content-Type", "application/json")
+                    .body(body.clone());
+                self.demo_header(req)
+            })
+            .await
+    }
 
+    fn demo_header(&self, req: RequestBuilder) -> RequestBuilder {
         if self.is_demo {
-            req = req.header("X-SIMULATED-TRADING", "1");
+            req.header("X-SIMULATED-TRADING", "1")
+        } else {
+            req
         }
+    }
 
-        let res = req.send().await?;
-        let json = res.json::<OkxOrderDetailsResponse>().await?;
-        if let Some(data) = json.data {
-            let detail = data
-                .into_iter()
-                .next()
-                .ok_or_else(|| anyhow::format_err!("Empty OKX order details response. Error code {}", json.code))?;
-            Ok(detail)
-        } else {
-            Err(anyhow::format_err!("Error code {} Endpoint {}", json.code, endpoint))
+    fn inst_id(pair: &Pair) -> String {
+        pair.join("-")
+    }
+
+    /// Daily and weekly bars are requested aligned to UTC (suffix `utc`), by default they start at midnight
+    /// Hong Kong time
+    fn bar(timeframe: Timeframe) -> &'static str {
+        match timeframe {
+            Timeframe::Min1 => "1m",
+            Timeframe::Min5 => "5m",
+            Timeframe::Hour1 => "1H",
+            Timeframe::Hour4 => "4H",
+            Timeframe::Day1 => "1Dutc",
+            Timeframe::Week1 => "1Wutc",
         }
     }
+
+    async fn get_order_details(&self, inst: &str, ord_id: &str) -> Result<OkxOrderDetailsData> {
+        let endpoint = format!("/api/v5/trade/order?instId={inst}&ordId={ord_id}");
+
+        let json: OkxOrderDetailsResponse = self.get_signed(&endpoint).await?;
+        check_code(&json.code, &json.msg)?;
+        json.data
+            .unwrap_or_default()
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::format_err!("Empty OKX order details response. Endpoint {}", endpoint))
+    }
+}
+
+/// Parses the candle row: start time, open, high, low, close, volume (in base asset), ...
+pub(crate) fn parse_candle(c: &[String]) -> Result<Candle> {
+    let value = |i: usize| c.get(i).ok_or_else(|| anyhow::anyhow!("OKX candle has no value {i}"));
+    Ok(Candle {
+        ts: value(0)?.parse()?,
+        open: value(1)?.parse()?,
+        high: value(2)?.parse()?,
+        low: value(3)?.parse()?,
+        close: value(4)?.parse()?,
+        volume: value(5)?.parse()?,
+    })
+}
+
+/// Fails with the classified error unless the code is "0" (success)
+fn check_code(code: &str, msg: &str) -> Result<(), CexError> {
+    let msg = msg.to_string();
+    match code {
+        "0" => Ok(()),
+        // API key, passphrase, timestamp, signature or permission problems
+        "50100" | "50101" | "50102" | "50103" | "50104" | "50105" | "50106" | "50107" | "50111" | "50112" | "50113"
+        | "50114" | "50120" => Err(CexError::Auth(msg)),
+        "51008" | "51127" | "51131" => Err(CexError::InsufficientFunds(msg)),
+        "50011" | "50061" => Err(CexError::RateLimited(msg)),
+        // below the minimum, not a multiple of the lot size, above the maximum
+        "51020" | "51121" | "51201" | "51202" | "51203" => Err(CexError::InvalidSize(msg)),
+        // service unavailable, timeout, system busy, system error
+        "50001" | "50004" | "50013" | "50026" => Err(CexError::Network(msg)),
+        _ => Err(CexError::Unknown {
+            code: code.to_string(),
+            msg,
+        }),
+    }
+}
+
+impl OkxOrderDetailsData {
+    fn into_order(self, pair: &Pair) -> Result<Order> {
+        // limit order may be not filled (yet), average price is empty then
+        let filled_amount: f64 = self.acc_fill_sz.parse().unwrap_or(0.0);
+        let avg_price: f64 = self.avg_px.parse().unwrap_or(0.0);
+        // OKX reports the charged fee as a negative number
+        let fee = self.fee.parse::<f64>().unwrap_or(0.0).abs();
+        Ok(Order {
+            side: match self.side.as_str() {
+                "buy" => Side::Buy,
+                "sell" => Side::Sell,
+                other => return Err(anyhow::format_err!("Unknown OKX order side {other}")),
+            },
+            status: match self.state.as_str() {
+                "live" => OrderStatus::New,
+                "partially_filled" => OrderStatus::PartiallyFilled,
+                "filled" => OrderStatus::Filled,
+                "canceled" | "mmp_canceled" => OrderStatus::Cancelled,
+                other => return Err(anyhow::format_err!("Unknown OKX order state {other}")),
+            },
+            amount: self.sz.parse()?,
+            price: self.px.parse::<f64>().ok().filter(|px| *px > 0.0),
+            filled_amount,
+            avg_price,
+            btc_fee: if self.fee_ccy == pair.base { fee } else { 0.0 },
+            usd_fee: if self.fee_ccy == pair.quote { fee } else { 0.0 },
+            id: self.ord_id,
+        })
+    }
 }
 
 #[async_trait]
 impl CexClient for OkxClient {
-    async fn get_price(&self, coin: &Coin) -> Result<f64> {
-        let inst = format!("{}-USDT", coin.symbol());
-        let url = format!("{}/api/v5/market/ticker?instId={}", self.base, inst);
-        debug!("GET {}", url);
-        let resp: TickerResp = self.client.get(&url).send().await?.json().await?;
+    async fn get_price(&self, pair: &Pair) -> Result<f64> {
+        let inst = Self::inst_id(pair);
+        let resp: TickerResp = self.get_public(&format!("/api/v5/market/ticker?instId={inst}")).await?;
+        check_code(&resp.code, &resp.msg)?;
         let t = resp.data.first().ok_or_else(|| anyhow::anyhow!("Empty OKX ticker"))?;
         Ok(t.last.parse()?)
     }
 
-    async fn get_historical(&self, coin: &Coin, days: u32) -> Result<Vec<f64>> {
-        // End = today's midnight UTC minus 1 second (i.e., end of yesterday)
-        let today_midnight = Utc::now()
-            .date_naive()
-            .and_hms_opt(0, 0, 0)
-            .expect("valid time")
-            .and_local_timezone(Utc)
-            .unwrap();
-        let end_ts = (today_midnight - Duration::seconds(1)).timestamp_millis();
+    async fn get_candles(&self, pair: &Pair, timeframe: Timeframe, bars: u32) -> Result<Vec<Candle>> {
+        // End = start of the current bar minus 1 second (i.e., end of the last finished bar)
+        let (start, end) = timeframe.window(Utc::now(), bars);
+        let end_ts = (end - Duration::seconds(1)).timestamp_millis();
+
+        // Start = start of the first bar minus 1 second
+        let start_ts = (start - Duration::seconds(1)).timestamp_millis();
+
+        // Pages are returned newest first, the next page is older than the oldest candle so far. The recent
+        // candles endpoint keeps only the last 1440 bars, the older ones are fetched from the history.
+        let mut candles = Vec::new();
+        let mut history = false;
+        let mut after = end_ts;
+        loop {
+            let (path, limit) = if history {
+                ("/api/v5/market/history-candles", HISTORY_CANDLES_LIMIT)
+            } else {
+                ("/api/v5/market/candles", CANDLES_LIMIT)
+            };
+            let endpoint = format!(
+                "{path}?instId={}&bar={}&after={}&before={}&limit={limit}",
+                Self::inst_id(pair),
+                Self::bar(timeframe),
+                after,
+                start_ts,
+            );
+            let resp: CandleResp = self.get_public(&endpoint).await?;
+            check_code(&resp.code, &resp.msg)?;
+            let page = resp
+                .data
+                .iter()
+                .map(|c| parse_candle(c))
+                .collect::<Result<Vec<Candle>>>()?;
+            let count = page.len();
+            let oldest = page.iter().map(|c| c.ts).min();
+            candles.extend(page);
+
+            match oldest {
+                // the first bar of the window is reached, or no progress
+                Some(oldest) if oldest <= start_ts + 1000 || oldest >= after => break,
+                Some(oldest) if count >= limit => after = oldest,
+                // the recent candles run out, continue in the history
+                Some(oldest) if !history => {
+                    after = oldest;
+                    history = true;
+                }
+                None if !history => history = true,
+                // no older candles at all
+                _ => break,
+            }
+        }
 
-        // Start = end_ts minus number of days
-        let start_ts = (today_midnight - Duration::days(days as i64) - Duration::seconds(1)).timestamp_millis();
+        Ok(candle::merge_pages(candles, timeframe))
+    }
 
-        let inst = format!("{}-USDT", coin.symbol());
-        let url = format!(
-            "{}/api/v5/market/candles?instId={}&bar=1D&after={}&before={}&limit=300",
-            self.base, inst, end_ts, start_ts,
+    async fn instrument_info(&self, pair: &Pair) -> Result<InstrumentInfo> {
+        let endpoint = format!(
+            "/api/v5/public/instruments?instType=SPOT&instId={}",
+            Self::inst_id(pair)
         );
-        debug!("GET {}", url);
-        let resp: CandleResp = self.client.get(&url).send().await?.json().await?;
-
-        let mut data = resp.data;
-        // Sort by timestamp (c[0]), oldest first
-        data.sort_by(|a, b| a[0].parse::<i64>().unwrap().cmp(&b[0].parse::<i64>().unwrap()));
+        let resp: OkxInstrumentResponse = self.get_public(&endpoint).await?;
+        check_code(&resp.code, &resp.msg)?;
+        let instrument = resp
+            .data
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("Empty OKX instrument response"))?;
+        // OKX has no minimum order value for spot
+        Ok(InstrumentInfo {
+            min_size: instrument.min_sz.parse()?,
+            size_increment: instrument.lot_sz.parse()?,
+            price_tick: instrument.tick_sz.parse()?,
+            min_notional: 0.0,
+        })
+    }
 
-        // let closes = resp
-        //     .data
-        //     .iter()
-        //     .map(|c| c[4].parse().unwrap())
-        //     .collect();
+    async fn get_balances(&self, pair: &Pair) -> Result<ExchangeBalance> {
+        let endpoint = format!("/api/v5/account/balance?ccy={},{}", pair.base, pair.quote);
 
-        let prices = data
-            .into_iter()
-            .map(
-                |c| {
-                    (c[2].parse::<f64>().expect("Failed to parse highest price")
-                        + c[3].parse::<f64>().expect("Failed to parse lower price"))
-                        / 2.0
-                }, // Average of high and low price
-            )
-            .collect();
+        let json: OkxBalanceResponse = self.get_signed(&endpoint).await?;
+        check_code(&json.code, &json.msg)?;
 
-        Ok(prices)
+        let mut balance = ExchangeBalance::default();
+        for detail in json.data.iter().flat_map(|d| d.details.iter()) {
+            if detail.ccy == pair.base {
+                balance.btc_balance = detail.avail_bal.parse()?;
+            } else if detail.ccy == pair.quote {
+                balance.usd_balance = detail.avail_bal.parse()?;
+            }
+        }
+        Ok(balance)
     }
 
-    async fn put_order(&self, coin: &Coin, side: Side, amount: f64, _price: Option<f64>) -> Result<OrderResponse> {
-        let inst_id = format!("{}-USDT", coin.symbol());
-        let endpoint = "/api/v5/trade/order";
-        //let ts = Utc::now().to_rfc3339();
-        let ts = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+    async fn put_order(
+        &self,
+        pair: &Pair,
+        side: Side,
+        amount: f64,
+        order_type: OrderType,
+        price: Option<f64>,
+    ) -> Result<OrderResponse> {
+        let inst_id = Self::inst_id(pair);
+        let px = order_type.limit_price(price)?;
 
         let req = OkxPutOrderRequest {
             inst_id: &inst_id,
@@ -151,71 +377,151 @@ impl CexClient for OkxClient {
                 Side::Buy => "buy",
                 Side::Sell => "sell",
             },
-            ord_type: "market",
-            tgt_ccy: "base_ccy",
-            sz: amount.to_string(),
+            ord_type: match order_type {
+                OrderType::Market => "market",
+                OrderType::Limit => "limit",
+                OrderType::PostOnly => "post_only",
+                OrderType::Ioc => "ioc",
+            },
+            // size of spot market orders is in the quote currency by default
+            tgt_ccy: (order_type == OrderType::Market).then_some("base_ccy"),
+            sz: format_decimal(amount),
+            px: px.map(format_decimal),
         };
 
         let body = serde_json::to_string(&req)?;
-        let sign = self.sign("POST", endpoint, &body, &ts);
+        let json: OkxPutOrderResponse = self.post_signed("/api/v5/trade/order", body).await?;
+        let Some(data) = json.data.into_iter().next() else {
+            // request rejected as a whole (e.g. authentication), there are no order results
+            check_code(&json.code, &json.msg)?;
+            anyhow::bail!("Empty OKX order response");
+        };
+        // the reason of the rejected order is in sCode, the top level code is just "1"
+        check_code(&data.s_code, &data.s_msg)?;
+        check_code(&json.code, &json.msg)?;
 
-        let url = format!("{}{}", self.base, endpoint);
-        let mut req = self
-            .client
-            .post(&url)
-            .header("OK-ACCESS-KEY", &self.api_key)
-            .header("OK-ACCESS-SIGN", sign)
-            .header("OK-ACCESS-TIMESTAMP", ts)
-            .header("OK-ACCESS-PASSPHRASE", &self.passphrase)
-            .header("Content-Type", "application/json")
-            .header("X-SIMULATED-TRADING", "1")
-            .body(body);
+        // Fetch order details to get fees
+        Ok(self.get_order(pair, &data.ord_id).await?.response())
+    }
 
-        if self.is_demo {
-            req = req.header("X-SIMULATED-TRADING", "1");
-        }
+    async fn get_order(&self, pair: &Pair, order_id: &str) -> Result<Order> {
+        self.get_order_details(&Self::inst_id(pair), order_id)
+            .await?
+            .into_order(pair)
+    }
 
-        let res = req.send().await?;
+    async fn cancel_order(&self, pair: &Pair, order_id: &str) -> Result<()> {
+        let endpoint = "/api/v5/trade/cancel-order";
+        let body = serde_json::to_string(&OkxCancelOrderRequest {
+            inst_id: &Self::inst_id(pair),
+            ord_id: order_id,
+        })?;
+        let json: OkxCancelOrderResponse = self.post_signed(endpoint, body).await?;
+        if let Some(data) = json.data.first() {
+            check_code(&data.s_code, &data.s_msg)?;
+        }
+        check_code(&json.code, &json.msg)?;
+        Ok(())
+    }
 
-        let json = res.json::<OkxPutOrderResponse>().await?;
-        let data = json
-            .data
+    async fn open_orders(&self, pair: &Pair) -> Result<Vec<Order>> {
+        let endpoint = format!(
+            "/api/v5/trade/orders-pending?instType=SPOT&instId={}",
+            Self::inst_id(pair)
+        );
+        let json: OkxOrderDetailsResponse = self.get_signed(&endpoint).await?;
+        check_code(&json.code, &json.msg)?;
+        json.data
+            .unwrap_or_default()
             .into_iter()
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("Empty OKX order response"))?;
-        if json.code != "0" {
-            return Err(anyhow::format_err!(
-                "Príkaz sa nepodarilo zadať. OKX error: {}",
-                json.code
-            ));
+            .map(|detail| detail.into_order(pair))
+            .collect()
+    }
+}
+
+/// Public ticker stream of OKX
+pub struct OkxPriceFeed {
+    url: String,
+}
+
+impl OkxPriceFeed {
+    pub fn new(is_demo: bool) -> Self {
+        OkxPriceFeed {
+            url: if is_demo { OKX_WS_DEMO } else { OKX_WS_LIVE }.to_string(),
         }
+    }
 
-        // Fetch order details to get fees
-        let details: OkxOrderDetailsData = self.get_order_details(&inst_id, &data.ord_id).await?;
-        let executed_price: f64 = details.fill_px.parse()?;
-        let executed_amount: f64 = details.fill_sz.parse()?;
-        let btc_fee = if details.fee_ccy == "BTC" {
-            details.fee.parse()?
-        } else {
-            0.0
-        };
-        let usd_fee = if details.fee_ccy == "USDT" {
-            details.fee.parse()?
-        } else {
-            0.0
-        };
+    /// Connects to the WebSocket of `base` (e.g. a local mock server) instead of the exchange, empty keeps the
+    /// default. The http(s) scheme becomes ws(s) and the OKX public path is appended.
+    pub fn with_base_url(mut self, base: &str) -> Self {
+        if !base.is_empty() {
+            let base = base.trim_end_matches('/');
+            let base = match base.split_once("://") {
+                Some(("https", rest)) => format!("wss://{rest}"),
+                Some(("http", rest)) => format!("ws://{rest}"),
+                _ => base.to_string(),
+            };
+            self.url = format!("{base}{}", OKX_WS_PATH);
+        }
+        self
+    }
+}
 
-        Ok(OrderResponse {
-            executed_price,
-            executed_amount,
-            btc_fee,
-            usd_fee,
+#[async_trait]
+impl FeedSource for OkxPriceFeed {
+    fn name(&self) -> &'static str {
+        "OKX"
+    }
+
+    async fn endpoint(&self) -> Result<FeedEndpoint> {
+        Ok(FeedEndpoint {
+            url: self.url.clone(),
+            // the connection is closed when there is no data for 30 seconds
+            ping_interval: std::time::Duration::from_secs(20),
         })
     }
+
+    fn subscribe_message(&self, pair: &Pair) -> String {
+        serde_json::json!({
+            "op": "subscribe",
+            "args": [{ "channel": "tickers", "instId": OkxClient::inst_id(pair) }],
+        })
+        .to_string()
+    }
+
+    fn ping_message(&self) -> String {
+        "ping".to_string()
+    }
+
+    fn parse_price(&self, text: &str) -> Option<f64> {
+        let push: OkxTickerPush = serde_json::from_str(text).ok()?;
+        push.data.first()?.last.parse().ok()
+    }
+}
+
+#[derive(Deserialize)]
+struct OkxTickerPush {
+    data: Vec<TickerData>,
+}
+
+#[derive(Deserialize)]
+struct OkxTimeResponse {
+    code: String,
+    #[serde(default)]
+    msg: String,
+    data: Vec<OkxTimeData>,
+}
+
+#[derive(Deserialize)]
+struct OkxTimeData {
+    ts: String,
 }
 
 #[derive(Deserialize)]
 struct TickerResp {
+    code: String,
+    #[serde(default)]
+    msg: String,
     data: Vec<TickerData>,
 }
 
@@ -225,8 +531,31 @@ struct TickerData {
     last: String,
 }
 
+#[derive(Deserialize)]
+struct OkxInstrumentResponse {
+    code: String,
+    #[serde(default)]
+    msg: String,
+    data: Vec<OkxInstrument>,
+}
+
+#[derive(Deserialize)]
+struct OkxInstrument {
+    #[serde(rename = "minSz")]
+    min_sz: String,
+
+    #[serde(rename = "lotSz")]
+    lot_sz: String,
+
+    #[serde(rename = "tickSz")]
+    tick_sz: String,
+}
+
 #[derive(Deserialize)]
 struct CandleResp {
+    code: String,
+    #[serde(default)]
+    msg: String,
     data: Vec<Vec<String>>,
 }
 
@@ -243,15 +572,20 @@ struct OkxPutOrderRequest<'a> {
     #[serde(rename = "ordType")]
     ord_type: &'a str,
 
-    #[serde(rename = "tgtCcy")]
-    tgt_ccy: &'a str,
+    #[serde(rename = "tgtCcy", skip_serializing_if = "Option::is_none")]
+    tgt_ccy: Option<&'a str>,
 
     sz: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    px: Option<String>,
 }
 
 #[derive(Deserialize)]
 struct OkxPutOrderResponse {
     code: String,
+    #[serde(default)]
+    msg: String,
     data: Vec<OkxPutOrderData>,
 }
 
@@ -261,15 +595,58 @@ struct OkxPutOrderData {
     //fee: String,
     #[serde(rename = "ordId")]
     ord_id: String,
+
+    #[serde(rename = "sCode")]
+    s_code: String,
+
+    #[serde(rename = "sMsg", default)]
+    s_msg: String,
+}
+
+#[derive(Serialize)]
+struct OkxCancelOrderRequest<'a> {
+    #[serde(rename = "instId")]
+    inst_id: &'a str,
+
+    #[serde(rename = "ordId")]
+    ord_id: &'a str,
+}
+
+#[derive(Deserialize)]
+struct OkxCancelOrderResponse {
+    code: String,
+    #[serde(default)]
+    msg: String,
+    data: Vec<OkxCancelOrderData>,
+}
+
+#[derive(Deserialize)]
+struct OkxCancelOrderData {
+    #[serde(rename = "sCode")]
+    s_code: String,
+
+    #[serde(rename = "sMsg", default)]
+    s_msg: String,
 }
 
 #[derive(Deserialize)]
 struct OkxOrderDetailsData {
-    #[serde(rename = "fillPx")]
-    fill_px: String,
+    #[serde(rename = "ordId")]
+    ord_id: String,
+
+    side: String,
+
+    state: String,
+
+    sz: String,
+
+    px: String,
+
+    #[serde(rename = "avgPx")]
+    avg_px: String,
 
-    #[serde(rename = "fillSz")]
-    fill_sz: String,
+    #[serde(rename = "accFillSz")]
+    acc_fill_sz: String,
 
     fee: String,
 
@@ -277,10 +654,99 @@ struct OkxOrderDetailsData {
     fee_ccy: String,
 }
 
+#[derive(Deserialize)]
+struct OkxBalanceResponse {
+    code: String,
+    #[serde(default)]
+    msg: String,
+    data: Vec<OkxBalanceData>,
+}
+
+#[derive(Deserialize)]
+struct OkxBalanceData {
+    details: Vec<OkxBalanceDetail>,
+}
+
+#[derive(Deserialize)]
+struct OkxBalanceDetail {
+    ccy: String,
+
+    #[serde(rename = "availBal")]
+    avail_bal: String,
+}
+
 #[derive(Deserialize)]
 struct OkxOrderDetailsResponse {
     code: String,
+    #[serde(default)]
+    msg: String,
     data: Option<Vec<OkxOrderDetailsData>>,
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_candle_row() {
+        let candle = parse_candle(&row(&["1700000000000", "1", "3", "0.5", "2", "10", "0"])).unwrap();
+        assert_eq!(candle.ts, 1700000000000);
+        assert_eq!(
+            (candle.open, candle.high, candle.low, candle.close),
+            (1.0, 3.0, 0.5, 2.0)
+        );
+        assert_eq!(candle.volume, 10.0);
+    }
+
+    #[test]
+    fn parse_candle_rejects_short_and_invalid_rows() {
+        assert!(parse_candle(&row(&["1700000000000", "1", "3", "0.5", "2"])).is_err());
+        assert!(parse_candle(&[]).is_err());
+        assert!(parse_candle(&row(&["x", "1", "3", "0.5", "2", "10"])).is_err());
+    }
+
+    #[tokio::test]
+    async fn price_feed_base_url() {
+        let url = |feed: OkxPriceFeed| async move { feed.endpoint().await.unwrap().url };
+        assert_eq!(url(OkxPriceFeed::new(true)).await, OKX_WS_DEMO);
+        assert_eq!(url(OkxPriceFeed::new(false).with_base_url("")).await, OKX_WS_LIVE);
+        assert_eq!(
+            url(OkxPriceFeed::new(false).with_base_url("http://127.0.0.1:8080/")).await,
+            "ws://127.0.0.1:8080/ws/v5/public"
+        );
+        assert_eq!(
+            url(OkxPriceFeed::new(false).with_base_url("https://example.com")).await,
+            "wss://example.com/ws/v5/public"
+        );
+    }
+
+    #[test]
+    fn check_code_classifies_the_errors() {
+        let m = || "msg".to_string();
+        for (code, expected) in [
+            ("0", Ok(())),
+            ("50100", Err(CexError::Auth(m()))),
+            ("50113", Err(CexError::Auth(m()))),
+            ("51008", Err(CexError::InsufficientFunds(m()))),
+            ("50011", Err(CexError::RateLimited(m()))),
+            ("51020", Err(CexError::InvalidSize(m()))),
+            ("51121", Err(CexError::InvalidSize(m()))),
+            ("50001", Err(CexError::Network(m()))),
+            (
+                "59999",
+                Err(CexError::Unknown {
+                    code: "59999".into(),
+                    msg: m(),
+                }),
+            ),
+        ] {
+            assert_eq!(check_code(code, "msg"), expected, "code {code}");
+        }
+    }
+}
+
 // eof
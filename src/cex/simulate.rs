@@ -1,94 +1,459 @@
 use anyhow::Result;
 use async_trait::async_trait;
+use chrono::NaiveDate;
 use log::debug;
+use serde::Deserialize;
 use std::{
     collections::HashMap,
     fs,
-    sync::atomic::{AtomicU32, Ordering},
+    sync::{
+        Mutex,
+        atomic::{AtomicU32, Ordering},
+    },
 };
 
 use crate::{
+    balance::ExchangeBalance,
+    candle::{Candle, Timeframe},
     cex::CexClient,
-    coin::Coin,
-    order::{OrderResponse, Side},
+    instrument::InstrumentInfo,
+    order::{Order, OrderResponse, OrderStatus, OrderType, Side},
+    pair::Pair,
 };
 
+const TAKER_FEE: f64 = 0.002; // 0.2% fee
+const MAKER_FEE: f64 = 0.001; // 0.1% fee
+
 pub struct SimulateClient {
     // determines the currently simulated day, at the beginning day == days_back
     day: AtomicU32,
     // determines how many days I am shifted into the past in the data
     // days_back: u32,
-    // data contains yyyyMMdd: BTC price
-    data: Vec<(String, f64)>,
+    // data contains yyyyMMdd: candle of the day
+    data: Vec<(String, Candle)>,
+    // index of the day after the last simulated one
+    end: usize,
+    // simulated account, changed by the filled orders
+    balances: Mutex<ExchangeBalance>,
+    // all placed orders, the resting ones are filled when the price of a later day crosses their limit
+    orders: Mutex<Vec<Order>>,
 }
 
 impl SimulateClient {
-    pub fn new(file_path: String, days_back: u32) -> Result<Self> {
-        let content = fs::read_to_string(&file_path)?;
-        let raw: Vec<HashMap<String, f64>> = serde_json::from_str(&content)?;
-        let mut data = Vec::with_capacity(raw.len());
-        for entry in raw {
-            for (date, value) in entry {
-                data.push((date, value));
-            }
+    /// `initial_usd` < 0 means the same value as `initial_btc` at the price of the first simulated day
+    pub fn new(file_path: String, days_back: u32, initial_btc: f64, initial_usd: f64) -> Result<Self> {
+        let data = load(&file_path)?;
+        let start_index = data.len().saturating_sub(days_back as usize);
+        let end = data.len();
+        Ok(Self::with_window(data, start_index, end, initial_btc, initial_usd))
+    }
+
+    /// Simulates only the days between `from` and `to` (both inclusive), independent of the current date
+    pub fn with_range(
+        file_path: String,
+        from: NaiveDate,
+        to: NaiveDate,
+        initial_btc: f64,
+        initial_usd: f64,
+    ) -> Result<Self> {
+        if from > to {
+            anyhow::bail!("Backtest start {from} is after its end {to}");
+        }
+        let data = load(&file_path)?;
+        let (from_key, to_key) = (from.format("%Y%m%d").to_string(), to.format("%Y%m%d").to_string());
+        let start_index = data.partition_point(|(date, _)| *date < from_key);
+        let end = data.partition_point(|(date, _)| *date <= to_key);
+        if start_index >= end {
+            anyhow::bail!("No data between {from} and {to} in {file_path}");
         }
-        data.sort_by_key(|(date, _)| date.clone());
+        Ok(Self::with_window(data, start_index, end, initial_btc, initial_usd))
+    }
 
-        let total = data.len();
-        let start_index = total.saturating_sub(days_back as usize);
+    fn with_window(
+        data: Vec<(String, Candle)>,
+        start_index: usize,
+        end: usize,
+        initial_btc: f64,
+        initial_usd: f64,
+    ) -> Self {
+        let initial_usd = if initial_usd < 0.0 {
+            let price = data.get(start_index).map(|(_, candle)| candle.close).unwrap_or(0.0);
+            initial_btc * price
+        } else {
+            initial_usd
+        };
 
-        Ok(SimulateClient {
+        SimulateClient {
             day: AtomicU32::new(start_index as u32 /* AtomicU32::new(days_back) */),
             /*days_back: days_back,*/ data,
-        })
+            end,
+            balances: Mutex::new(ExchangeBalance {
+                btc_balance: initial_btc,
+                usd_balance: initial_usd,
+            }),
+            orders: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Number of the days which are left to simulate
+    pub fn remaining_days(&self) -> u32 {
+        (self.end as u32).saturating_sub(self.day.load(Ordering::SeqCst))
+    }
+
+    /// Dates of the first and the last simulated day, yyyyMMdd
+    pub fn date_range(&self) -> (&str, &str) {
+        let start = self.day.load(Ordering::SeqCst) as usize;
+        let first = self.data.get(start).map(|(date, _)| date.as_str()).unwrap_or_default();
+        let last = self
+            .data
+            .get(self.end.wrapping_sub(1))
+            .map(|(date, _)| date.as_str())
+            .unwrap_or_default();
+        (first, last)
+    }
+
+    /// Price of the current simulated day (the one returned by the last `get_price`)
+    fn current_price(&self) -> Option<f64> {
+        let current = self.day.load(Ordering::SeqCst) as usize;
+        self.data.get(current.saturating_sub(1)).map(|(_, candle)| candle.close)
+    }
+
+    /// Fills the whole order at `price` and moves the amounts on the simulated account
+    fn fill(&self, order: &mut Order, price: f64, fee: f64) {
+        order.status = OrderStatus::Filled;
+        order.filled_amount = order.amount;
+        order.avg_price = price;
+        order.usd_fee = order.amount * price * fee;
+
+        let mut balances = self.balances.lock().unwrap();
+        match order.side {
+            Side::Buy => {
+                balances.btc_balance += order.filled_amount - order.btc_fee;
+                balances.usd_balance -= order.filled_amount * order.avg_price + order.usd_fee;
+            }
+            Side::Sell => {
+                balances.btc_balance -= order.filled_amount + order.btc_fee;
+                balances.usd_balance += order.filled_amount * order.avg_price - order.usd_fee;
+            }
+        }
+    }
+
+    /// Fills the resting orders whose limit is crossed by the price range of the day
+    fn match_orders(&self, day: &Candle) {
+        let mut orders = self.orders.lock().unwrap();
+        for order in orders.iter_mut().filter(|order| order.status.is_open()) {
+            let Some(limit) = order.price else { continue };
+            // the best price of the day for the order
+            let day_price = match order.side {
+                Side::Buy => day.low,
+                Side::Sell => day.high,
+            };
+            if crosses(order.side, day_price, limit) {
+                debug!(
+                    "{:?} {} at {limit:.2} filled, day price {day_price:.2}",
+                    order.side, order.id
+                );
+                self.fill(order, limit, MAKER_FEE);
+            }
+        }
+    }
+}
+
+/// Reads the simulation file, the days are sorted by date
+fn load(file_path: &str) -> Result<Vec<(String, Candle)>> {
+    let content = fs::read_to_string(file_path)?;
+    let raw: Vec<HashMap<String, DayData>> = serde_json::from_str(&content)?;
+    let mut data = Vec::with_capacity(raw.len());
+    for entry in raw {
+        for (date, value) in entry {
+            let ts = NaiveDate::parse_from_str(&date, "%Y%m%d")
+                .map_err(|e| anyhow::format_err!("Invalid date {date} in {file_path}: {e}"))?
+                .and_hms_opt(0, 0, 0)
+                .expect("valid time")
+                .and_utc()
+                .timestamp_millis();
+            data.push((date, value.into_candle(ts)));
+        }
+    }
+    data.sort_by_key(|(date, _)| date.clone());
+    Ok(data)
+}
+
+/// Price of the day in the simulation file, either just the close or the whole candle
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DayData {
+    Close(f64),
+    Candle {
+        open: f64,
+        high: f64,
+        low: f64,
+        close: f64,
+        #[serde(default)]
+        volume: f64,
+    },
+}
+
+impl DayData {
+    fn into_candle(self, ts: i64) -> Candle {
+        match self {
+            DayData::Close(price) => Candle::flat(ts, price),
+            DayData::Candle {
+                open,
+                high,
+                low,
+                close,
+                volume,
+            } => Candle {
+                ts,
+                open,
+                high,
+                low,
+                close,
+                volume,
+            },
+        }
+    }
+}
+
+/// Limit order is executable when the market price is at the limit or better
+fn crosses(side: Side, price: f64, limit: f64) -> bool {
+    match side {
+        Side::Buy => price <= limit,
+        Side::Sell => price >= limit,
     }
 }
 
 #[async_trait]
 impl CexClient for SimulateClient {
     // the function returns the price from data for the current day (defined in day)... after returning, day is moved forward by one
-    async fn get_price(&self, _coin: &Coin) -> Result<f64> {
+    async fn get_price(&self, _pair: &Pair) -> Result<f64> {
         let current = self.day.load(Ordering::SeqCst) as usize;
-        if current >= self.data.len() {
+        if current >= self.end {
             anyhow::bail!(
                 "Simulovaný index dňa {} je mimo rozsahu dát (max {})",
                 current,
-                self.data.len() - 1
+                self.end - 1
             );
         }
 
-        let data = self.data[current].clone();
-        let day = data.0;
+        let (day, candle) = &self.data[current];
         debug!("Simulate day: {}", day);
 
-        let price = data.1;
-
         // shift the day by 1
         self.day.fetch_add(1, Ordering::SeqCst);
-        Ok(price)
+        self.match_orders(candle);
+        Ok(candle.close)
     }
 
-    /// Returns the last `days` days of historical prices (only f64), excluding today.
-    async fn get_historical(&self, _coin: &Coin, days: u32) -> Result<Vec<f64>> {
+    /// Returns the candles of the last `bars` days, excluding today. The simulation data has daily candles only.
+    async fn get_candles(&self, _pair: &Pair, timeframe: Timeframe, bars: u32) -> Result<Vec<Candle>> {
+        if timeframe != Timeframe::Day1 {
+            anyhow::bail!("Simulation supports only the 1d timeframe, not {timeframe}");
+        }
         // current index (today)
         let current = self.day.load(Ordering::SeqCst) as usize;
 
-        let start = current.saturating_sub(days as usize);
-        let slice = &self.data[start..current];
+        let start = current.saturating_sub(bars as usize);
+        Ok(self.data[start..current].iter().map(|(_, candle)| *candle).collect())
+    }
+
+    /// Simulated exchange has no trading constraints
+    async fn instrument_info(&self, _pair: &Pair) -> Result<InstrumentInfo> {
+        Ok(InstrumentInfo::default())
+    }
+
+    async fn get_balances(&self, _pair: &Pair) -> Result<ExchangeBalance> {
+        Ok(*self.balances.lock().unwrap())
+    }
+
+    /// Market order is filled at `price`. Limit orders are filled at their price when the day's price crosses
+    /// it, otherwise they rest until the price of a later day does. Post-only order which would be filled
+    /// immediately is rejected, unfilled IOC order is cancelled.
+    async fn put_order(
+        &self,
+        _pair: &Pair,
+        side: Side,
+        amount: f64,
+        order_type: OrderType,
+        price: Option<f64>,
+    ) -> Result<OrderResponse> {
+        let limit = order_type.limit_price(price)?;
+        let mut orders = self.orders.lock().unwrap();
+        let mut order = Order {
+            id: format!("sim-{}", orders.len() + 1),
+            side,
+            status: OrderStatus::New,
+            amount,
+            price: limit,
+            filled_amount: 0.0,
+            avg_price: 0.0,
+            btc_fee: 0.0,
+            usd_fee: 0.0,
+        };
+
+        match limit {
+            None => {
+                // the market order fills at the caller's price, or at the current candle without one
+                let price = match price {
+                    Some(price) => price,
+                    None => self
+                        .current_price()
+                        .ok_or_else(|| anyhow::anyhow!("No simulated price for market order"))?,
+                };
+                self.fill(&mut order, price, TAKER_FEE)
+            }
+            Some(limit) => {
+                let day_price = self
+                    .current_price()
+                    .ok_or_else(|| anyhow::anyhow!("No simulated price for limit order"))?;
+                let crossed = crosses(side, day_price, limit);
+                match order_type {
+                    OrderType::PostOnly if crossed => order.status = OrderStatus::Rejected,
+                    OrderType::Ioc if crossed => self.fill(&mut order, limit, TAKER_FEE),
+                    OrderType::Ioc => order.status = OrderStatus::Cancelled,
+                    _ if crossed => self.fill(&mut order, limit, MAKER_FEE),
+                    _ => debug!("{order_type:?} {side:?} at {limit:.2} resting, day price {day_price:.2}"),
+                }
+            }
+        }
 
-        // extract only the price (second item of the tuple)
-        let prices: Vec<f64> = slice.iter().map(|(_, price)| *price).collect();
-        Ok(prices)
+        let res = order.response();
+        let rejected = order.status == OrderStatus::Rejected;
+        orders.push(order);
+        if rejected {
+            anyhow::bail!("Post-only order {} would take liquidity", res.order_id);
+        }
+        Ok(res)
+    }
+
+    async fn get_order(&self, _pair: &Pair, order_id: &str) -> Result<Order> {
+        self.orders
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|order| order.id == order_id)
+            .cloned()
+            .ok_or_else(|| anyhow::format_err!("Simulated order {order_id} not found"))
     }
 
-    async fn put_order(&self, _coin: &Coin, _side: Side, amount: f64, price: Option<f64>) -> Result<OrderResponse> {
-        Ok(OrderResponse {
-            //order_id: "order_123".into(),
-            executed_price: price.unwrap(),
-            executed_amount: amount,
-            btc_fee: 0.0,                             //amount * 0.002, // 0.2% fee
-            usd_fee: amount * price.unwrap() * 0.002, // 0.2% fee
-        })
+    async fn cancel_order(&self, _pair: &Pair, order_id: &str) -> Result<()> {
+        let mut orders = self.orders.lock().unwrap();
+        let order = orders
+            .iter_mut()
+            .find(|order| order.id == order_id)
+            .ok_or_else(|| anyhow::format_err!("Simulated order {order_id} not found"))?;
+        if !order.status.is_open() {
+            anyhow::bail!("Simulated order {order_id} is not open ({:?})", order.status);
+        }
+        order.status = OrderStatus::Cancelled;
+        Ok(())
+    }
+
+    async fn open_orders(&self, _pair: &Pair) -> Result<Vec<Order>> {
+        Ok(self
+            .orders
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|order| order.status.is_open())
+            .cloned()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Client over days given as (low, high, close), the first day is already the current one
+    async fn client(days: &[(f64, f64, f64)]) -> SimulateClient {
+        let data = days
+            .iter()
+            .enumerate()
+            .map(|(i, &(low, high, close))| {
+                let candle = Candle {
+                    ts: i as i64,
+                    open: close,
+                    high,
+                    low,
+                    close,
+                    volume: 0.0,
+                };
+                (format!("202401{:02}", i + 1), candle)
+            })
+            .collect();
+        let client = SimulateClient::with_window(data, 0, days.len(), 1.0, 1000.0);
+        client.get_price(&pair()).await.unwrap();
+        client
+    }
+
+    fn pair() -> Pair {
+        Pair::new("BTC", "USDT")
+    }
+
+    #[tokio::test]
+    async fn buy_limit_filled_at_the_day_low() {
+        let client = client(&[(98.0, 102.0, 100.0), (94.0, 101.0, 99.0)]).await;
+        let res = client
+            .put_order(&pair(), Side::Buy, 1.0, OrderType::Limit, Some(95.0))
+            .await
+            .unwrap();
+        assert_eq!(
+            client.get_order(&pair(), &res.order_id).await.unwrap().status,
+            OrderStatus::New
+        );
+
+        client.get_price(&pair()).await.unwrap();
+        let order = client.get_order(&pair(), &res.order_id).await.unwrap();
+        assert_eq!(order.status, OrderStatus::Filled);
+        assert_eq!((order.filled_amount, order.avg_price), (1.0, 95.0));
+        assert_eq!(order.usd_fee, 95.0 * MAKER_FEE);
+        let balances = client.get_balances(&pair()).await.unwrap();
+        assert_eq!(balances.btc_balance, 2.0);
+        assert_eq!(balances.usd_balance, 1000.0 - 95.0 - 95.0 * MAKER_FEE);
+    }
+
+    #[tokio::test]
+    async fn limit_left_unfilled() {
+        let client = client(&[(98.0, 102.0, 100.0), (96.0, 104.0, 99.0)]).await;
+        let buy = client
+            .put_order(&pair(), Side::Buy, 1.0, OrderType::Limit, Some(95.0))
+            .await
+            .unwrap();
+        let sell = client
+            .put_order(&pair(), Side::Sell, 0.5, OrderType::Limit, Some(105.0))
+            .await
+            .unwrap();
+
+        client.get_price(&pair()).await.unwrap();
+        let open: Vec<String> = client
+            .open_orders(&pair())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|order| order.id)
+            .collect();
+        assert_eq!(open, vec![buy.order_id, sell.order_id]);
+        let balances = client.get_balances(&pair()).await.unwrap();
+        assert_eq!((balances.btc_balance, balances.usd_balance), (1.0, 1000.0));
+    }
+
+    #[tokio::test]
+    async fn sell_limit_filled_at_the_day_high() {
+        let client = client(&[(98.0, 102.0, 100.0), (99.0, 111.0, 108.0)]).await;
+        let res = client
+            .put_order(&pair(), Side::Sell, 0.5, OrderType::Limit, Some(110.0))
+            .await
+            .unwrap();
+
+        client.get_price(&pair()).await.unwrap();
+        let order = client.get_order(&pair(), &res.order_id).await.unwrap();
+        assert_eq!(order.status, OrderStatus::Filled);
+        assert_eq!((order.filled_amount, order.avg_price), (0.5, 110.0));
+        let balances = client.get_balances(&pair()).await.unwrap();
+        assert_eq!(balances.btc_balance, 0.5);
+        assert_eq!(balances.usd_balance, 1000.0 + 55.0 - 55.0 * MAKER_FEE);
     }
 }
 
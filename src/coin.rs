@@ -1,8 +1,9 @@
 use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, Clone, ValueEnum)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default, ValueEnum)]
 pub enum Coin {
+    #[default]
     Bitcoin,
     Ethereum,
     Solana,
@@ -1,8 +1,18 @@
 use anyhow::Result;
-use clap::{Parser, ValueEnum};
+use chrono::NaiveDate;
+use clap::{Parser, Subcommand, ValueEnum};
 use serde::Deserialize;
 
-use crate::{coin::Coin, logger::LogLevel, strategy::Strategy};
+use crate::{
+    candle::{PriceField, Timeframe},
+    coin::Coin,
+    logger::LogLevel,
+    mock::Flavor,
+    optimize::OptimizeArgs,
+    order::OrderType,
+    pair::Pair,
+    strategy::Strategy,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ValueEnum)]
 pub enum PerfFeeMode {
@@ -22,6 +32,10 @@ pub struct Cli {
     #[arg(long)]
     pub coin: Option<Coin>,
     #[arg(long)]
+    pub pair: Option<Pair>,
+    #[arg(long)]
+    pub timeframe: Option<Timeframe>,
+    #[arg(long)]
     pub period: Option<u32>,
     #[arg(long)]
     pub initial_btc: Option<f64>,
@@ -84,6 +98,13 @@ pub struct Cli {
 
     #[arg(long)]
     pub signal_threshold: Option<f64>,
+    #[arg(long)]
+    pub price_field: Option<PriceField>,
+
+    #[arg(long)]
+    pub order_type: Option<OrderType>,
+    #[arg(long)]
+    pub limit_offset: Option<f64>,
 
     #[arg(long)]
     pub perf_fee_cycles: Option<u32>,
@@ -102,6 +123,24 @@ pub struct Cli {
     pub cex_api_secret: Option<String>,
     #[arg(long)]
     pub cex_api_key: Option<String>,
+    #[arg(long)]
+    pub cex_demo: Option<bool>,
+    #[arg(long)]
+    pub cex_base_url: Option<String>,
+
+    #[arg(long)]
+    pub price_feed: Option<bool>,
+    #[arg(long)]
+    pub feed_stop_loss: Option<f64>,
+    #[arg(long)]
+    pub feed_take_profit: Option<f64>,
+
+    #[arg(long)]
+    pub http_retries: Option<u32>,
+    #[arg(long)]
+    pub http_backoff_ms: Option<u64>,
+    #[arg(long)]
+    pub http_timeout_secs: Option<u64>,
 
     #[arg(long)]
     pub simulate_file: Option<String>,
@@ -112,14 +151,62 @@ pub struct Cli {
 
     #[arg(long)]
     pub telegram_channel_id: Option<i64>,
+
+    #[arg(long)]
+    pub state_file: Option<String>,
+
+    /// Report of the simulation, JSON or CSV by the extension
+    #[arg(long)]
+    pub report: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
     //     #[arg(long, short = 'v', long, default_value_t = false, help = "Print version")]
     //     pub version: bool,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Runs a local server which imitates the exchange API over recorded fixtures, the signed requests are verified
+    /// with the API key, secret and passphrase of the config
+    MockExchange {
+        #[arg(long)]
+        flavor: Flavor,
+        /// Address the server listens on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+        /// Directory with fixture files which replace the built-in ones
+        #[arg(long)]
+        fixtures: Option<String>,
+    },
+    /// Replays the days between the dates (both inclusive) from `simulate_file` on the simulated exchange, one
+    /// cycle per day without waiting, so the same range always gives the same result
+    Backtest {
+        /// First simulated day, like 2021-01-01
+        #[arg(long)]
+        from: NaiveDate,
+        /// Last simulated day
+        #[arg(long)]
+        to: NaiveDate,
+    },
+    /// Backtests combinations of the parameter values from a TOML file in parallel on all CPU cores and ranks the
+    /// results by the objective
+    Optimize(OptimizeArgs),
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct Config {
     pub log_level: LogLevel,
+    /// Coin traded against USDT, unless `pair` is set
+    #[serde(default)]
     pub coin: Coin,
+    /// Traded pair like "ETH-BTC" or "SOL-USDC"
+    #[serde(default)]
+    pub pair: Option<Pair>,
+    /// Length of the candles and of the trading cycle
+    #[serde(default)]
+    pub timeframe: Timeframe,
+    /// Number of the candles the strategies look back at
     pub period: u32,
     pub initial_btc: f64,
     pub initial_usd: f64,
@@ -156,6 +243,16 @@ pub struct Config {
     pub weight_tp_o_bb: f64,
 
     pub signal_threshold: f64,
+    /// Price of the historical candles which the strategies consume
+    #[serde(default)]
+    pub price_field: PriceField,
+
+    /// Type of the orders placed for the signals
+    #[serde(default)]
+    pub order_type: OrderType,
+    /// (1.0 = 100%) distance of the limit price from the current price, below it for buy, above it for sell
+    #[serde(default)]
+    pub limit_offset: f64,
 
     pub perf_fee_cycles: u32,
     pub perf_fee_rate: f64,
@@ -166,6 +263,32 @@ pub struct Config {
     pub cex_api_passphrase: String,
     pub cex_api_secret: String,
     pub cex_api_key: String,
+    /// Trade on the demo account / testnet of the exchange instead of the live one
+    #[serde(default)]
+    pub cex_demo: bool,
+    /// Base URL of the exchange REST API, e.g. of the mock exchange. Empty = the URL of the exchange.
+    #[serde(default)]
+    pub cex_base_url: String,
+
+    /// Stream the price over WebSocket (OKX, KuCoin) and check it between the cycles
+    #[serde(default)]
+    pub price_feed: bool,
+    /// (1.0 = 100%) streamed price move against the last entry which closes it, 0 = off
+    #[serde(default)]
+    pub feed_stop_loss: f64,
+    /// (1.0 = 100%) streamed price move in favour of the last entry which closes it, 0 = off
+    #[serde(default)]
+    pub feed_take_profit: f64,
+
+    /// Number of retries of a failed exchange request
+    #[serde(default = "default_http_retries")]
+    pub http_retries: u32,
+    /// Delay before the first retry, doubled with every next one
+    #[serde(default = "default_http_backoff_ms")]
+    pub http_backoff_ms: u64,
+    /// Timeout of a single exchange request
+    #[serde(default = "default_http_timeout_secs")]
+    pub http_timeout_secs: u64,
 
     pub simulate_file: String,
     pub simulate_day: u32,
@@ -173,8 +296,21 @@ pub struct Config {
 
     pub telegram_channel_id: i64,
 
+    /// Path of the file where the bot state is persisted after every cycle. Empty = no persistence.
+    #[serde(default)]
+    pub state_file: String,
+
+    /// Path of the report written at the end of the simulation (trades, daily equity, fees, set aside amounts and
+    /// the summary), JSON or CSV when it ends with `.csv`. Empty = no report.
+    #[serde(default)]
+    pub report_file: String,
+
     #[serde(skip)]
     pub is_simulation: bool,
+
+    /// Subcommand run instead of the trading
+    #[serde(skip)]
+    pub command: Option<Command>,
 }
 
 impl Config {
@@ -203,6 +339,8 @@ impl Config {
         override_opt!(log_level, cli.log_level);
 
         override_opt!(coin, cli.coin);
+        override_opt!(pair, cli.pair.map(Some));
+        override_opt!(timeframe, cli.timeframe);
         override_opt!(period, cli.period);
         override_opt!(initial_btc, cli.initial_btc);
         override_opt!(initial_usd, cli.initial_usd);
@@ -239,6 +377,10 @@ impl Config {
         override_opt!(weight_tp_o_bb, cli.weight_tp_o_bb);
 
         override_opt!(signal_threshold, cli.signal_threshold);
+        override_opt!(price_field, cli.price_field);
+
+        override_opt!(order_type, cli.order_type);
+        override_opt!(limit_offset, cli.limit_offset);
 
         override_opt!(perf_fee_cycles, cli.perf_fee_cycles);
         override_opt!(perf_fee_rate, cli.perf_fee_rate);
@@ -249,6 +391,16 @@ impl Config {
         override_opt!(cex_api_passphrase, cli.cex_api_passphrase);
         override_opt!(cex_api_secret, cli.cex_api_secret);
         override_opt!(cex_api_key, cli.cex_api_key);
+        override_opt!(cex_demo, cli.cex_demo);
+        override_opt!(cex_base_url, cli.cex_base_url);
+
+        override_opt!(price_feed, cli.price_feed);
+        override_opt!(feed_stop_loss, cli.feed_stop_loss);
+        override_opt!(feed_take_profit, cli.feed_take_profit);
+
+        override_opt!(http_retries, cli.http_retries);
+        override_opt!(http_backoff_ms, cli.http_backoff_ms);
+        override_opt!(http_timeout_secs, cli.http_timeout_secs);
 
         override_opt!(simulate_file, cli.simulate_file);
         override_opt!(simulate_day, cli.simulate_day);
@@ -256,21 +408,109 @@ impl Config {
 
         override_opt!(telegram_channel_id, cli.telegram_channel_id);
 
+        override_opt!(state_file, cli.state_file);
+        override_opt!(report_file, cli.report);
+
+        if let Some(Command::Backtest { .. } | Command::Optimize(_)) = cli.command {
+            // the backtest always runs on the simulated exchange and starts from the configured balances
+            cfg.cex = "simulate".to_string();
+            cfg.state_file.clear();
+        }
+
         cfg.is_simulation = cfg.cex.eq_ignore_ascii_case("simulate"); // && cfg.simulate_cycles > 30;
         // if cfg.is_simulation {
         //     if cfg.simulate_cycles > 30 {
         //         cfg.log_level = LogLevel::Error;
         //     }
         // }
+        cfg.command = cli.command;
 
         Ok(cfg)
     }
 }
 
+fn default_http_retries() -> u32 {
+    3
+}
+
+fn default_http_backoff_ms() -> u64 {
+    500
+}
+
+fn default_http_timeout_secs() -> u64 {
+    10
+}
+
 impl Config {
+    /// Traded pair, the configured one or the coin against USDT
+    pub fn pair(&self) -> Pair {
+        self.pair.clone().unwrap_or_else(|| Pair::from(&self.coin))
+    }
+
     pub fn is_simulation(&self) -> bool {
         self.is_simulation
     }
+
+    /// Sets the strategy parameter `name` to the value, like the same key in the config file
+    pub fn set_param(&mut self, name: &str, value: &toml::Value) -> Result<()> {
+        macro_rules! set_params {
+            ($($field:ident),*) => {
+                match name {
+                    $(stringify!($field) => {
+                        self.$field = value
+                            .clone()
+                            .try_into()
+                            .map_err(|e| anyhow::format_err!("Invalid value {value} of {name}: {e}"))?
+                    })*
+                    _ => anyhow::bail!("Unknown parameter {name}"),
+                }
+            };
+        }
+
+        set_params!(
+            period,
+            allocation,
+            take_profit_btc,
+            stop_lose_btc,
+            take_profit_usd,
+            stop_lose_usd,
+            strategy,
+            ema_short,
+            ema_long,
+            rsi_period,
+            rsi_oversold,
+            rsi_overbought,
+            grid_levels,
+            grid_range,
+            dip_pct,
+            tp_pct,
+            buyback_pct,
+            weight_ema,
+            weight_rsi,
+            weight_grid,
+            weight_buy_dip,
+            weight_tp_o_bb,
+            signal_threshold,
+            price_field,
+            order_type,
+            limit_offset,
+            perf_fee_cycles,
+            perf_fee_rate,
+            perf_fee_mode
+        );
+        Ok(())
+    }
+
+    /// Trading mode shown at startup
+    pub fn trading_mode(&self) -> &'static str {
+        if self.is_simulation {
+            "SIMULATION"
+        } else if self.cex_demo {
+            "DEMO"
+        } else {
+            "LIVE"
+        }
+    }
 }
 
 // eof
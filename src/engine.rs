@@ -0,0 +1,961 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use log::{debug, error, info, warn};
+use std::time::Duration;
+use tokio::time::{self, Instant, Interval, sleep_until};
+
+use crate::{
+    balance::Balance,
+    benchmark,
+    cex::{CexClient, feed::PriceFeed},
+    config::Config,
+    executor,
+    fear_greed::FearGreedIndex,
+    fee::PerfFeeTracker,
+    info_buf,
+    instrument::{InstrumentInfo, quote_decimals},
+    metrics::Metrics,
+    order::{Order, Side},
+    pair::Pair,
+    report::{EquityPoint, Journal, Trade},
+    state::{BotState, Position, STATE_VERSION},
+    strategy,
+    strategy::StrategyWeights,
+    telegram::Telegram,
+};
+
+/// Trading engine that owns the exchange client and the whole bot state.
+///
+/// One call to [`Engine::run_cycle`] performs a single trading cycle (price fetch, signals, execution,
+/// performance fee and take profit), [`Engine::run`] drives the cycles on a schedule.
+pub struct Engine {
+    cfg: Config,
+    // traded pair resolved from the config
+    pair: Pair,
+    exch: Box<dyn CexClient>,
+    telegram: Telegram,
+    // streamed prices checked between the cycles
+    feed: Option<PriceFeed>,
+
+    price: f64,
+    initial_balance: Balance,
+    balance: Balance,
+    perf_tracker: PerfFeeTracker,
+
+    total_take_profit_btc: f64,
+    total_take_profit_usd: f64,
+
+    // trading rules of the pair, fetched once
+    instrument: Option<InstrumentInfo>,
+
+    // limit orders resting on the order book, as far as their fills are already accounted for
+    pending_orders: Vec<Order>,
+    // last entry, closed when the streamed price crosses its stop-loss or take-profit level
+    position: Option<Position>,
+
+    cycle_count: u32,
+
+    // trades and equity curve of the run for the metrics and the report
+    journal: Journal,
+}
+
+impl Engine {
+    /// Creates the engine and sets up the starting portfolio from the current price
+    pub async fn new(cfg: Config, exch: Box<dyn CexClient>) -> Result<Self> {
+        let telegram = Telegram::new();
+        let pair = cfg.pair();
+
+        let price = match exch.get_price(&pair).await {
+            Ok(p) => p,
+            Err(e) => {
+                error!("Error fetching price: {e}");
+                telegram
+                    .send_message(cfg.telegram_channel_id, &format!("Error fetching price: {e}"))
+                    .await;
+                return Err(e);
+            }
+        };
+
+        let initial_balance = match exch.get_balances(&pair).await {
+            Ok(real) => {
+                let dp = quote_decimals(price);
+                info!(
+                    "Exchange balance: {:.8} {}, {:.dp$} {}",
+                    real.btc_balance, pair.base, real.usd_balance, pair.quote
+                );
+                Balance::new(real.btc_balance, real.usd_balance, price)
+            }
+            Err(e) => {
+                warn!("Error fetching balances, using configured initial balance: {e}");
+                let initial_usd = if cfg.initial_usd < 0.0 {
+                    cfg.initial_btc * price
+                } else {
+                    cfg.initial_usd
+                };
+                Balance::new(cfg.initial_btc, initial_usd, price)
+            }
+        };
+        let perf_tracker = PerfFeeTracker::new(&initial_balance, cfg.deduct_fee_from_balance);
+
+        let mut balance = initial_balance.clone();
+        balance.set_initial(initial_balance.clone());
+
+        let mut engine = Engine {
+            cfg,
+            pair,
+            exch,
+            telegram,
+            feed: None,
+            price,
+            initial_balance,
+            balance,
+            perf_tracker,
+            total_take_profit_btc: 0.0,
+            total_take_profit_usd: 0.0,
+            instrument: None,
+            pending_orders: Vec::new(),
+            position: None,
+            cycle_count: 0,
+            journal: Journal::default(),
+        };
+
+        if !engine.cfg.state_file.is_empty()
+            && let Some(state) = BotState::load(&engine.cfg.state_file)?
+        {
+            state.validate(&engine.cfg)?;
+            engine.restore_state(&state);
+            info!(
+                "Restored state from {} (cycle {})",
+                engine.cfg.state_file, engine.cycle_count
+            );
+        }
+
+        Ok(engine)
+    }
+
+    /// Snapshot of the state which is persisted between restarts
+    pub fn state(&self) -> BotState {
+        BotState {
+            version: STATE_VERSION,
+            pair: Some(self.pair.clone()),
+            coin: None,
+            cex: self.cfg.cex.clone(),
+            cycle_count: self.cycle_count,
+            initial_balance: (&self.initial_balance).into(),
+            balance: (&self.balance).into(),
+            total_take_profit_btc: self.total_take_profit_btc,
+            total_take_profit_usd: self.total_take_profit_usd,
+            perf_fee: (&self.perf_tracker).into(),
+            pending_orders: self.pending_orders.clone(),
+            position: self.position,
+            journal: self.journal.clone(),
+        }
+    }
+
+    /// Replaces the in-memory state with the persisted one
+    pub fn restore_state(&mut self, state: &BotState) {
+        self.initial_balance = (&state.initial_balance).into();
+
+        let mut balance: Balance = (&state.balance).into();
+        balance.set_initial(self.initial_balance.clone());
+        self.balance = balance;
+
+        self.perf_tracker = PerfFeeTracker::new(&self.initial_balance, self.cfg.deduct_fee_from_balance);
+        state.perf_fee.restore(&mut self.perf_tracker);
+
+        self.total_take_profit_btc = state.total_take_profit_btc;
+        self.total_take_profit_usd = state.total_take_profit_usd;
+        self.pending_orders = state.pending_orders.clone();
+        self.position = state.position;
+        self.journal = state.journal.clone();
+        self.cycle_count = state.cycle_count;
+    }
+
+    /// Amounts which stay on the exchange account but are not traded anymore (set aside profit and deducted
+    /// performance fees)
+    fn set_aside(&self) -> (f64, f64) {
+        let mut btc = self.total_take_profit_btc;
+        let mut usd = self.total_take_profit_usd;
+        if self.cfg.deduct_fee_from_balance {
+            btc += self.perf_tracker.total_fee_btc;
+            usd += self.perf_tracker.total_fee_usd;
+        }
+        (btc, usd)
+    }
+
+    /// Compares the tracked balance with the real one on the exchange and takes over the real values when they
+    /// diverge (failed orders, deposits, rounding)
+    async fn reconcile_balance(&mut self, msgs: &mut Vec<String>) {
+        let real = match self.exch.get_balances(&self.pair).await {
+            Ok(real) => real,
+            Err(e) => {
+                warn!("Error fetching balances, keeping tracked balance: {e}");
+                return;
+            }
+        };
+
+        let symbol = self.pair.base.as_str();
+        let quote = self.pair.quote.as_str();
+        let dp = quote_decimals(self.price);
+        let (aside_btc, aside_usd) = self.set_aside();
+        let btc = real.btc_balance - aside_btc;
+        let usd = real.usd_balance - aside_usd;
+
+        if diverges(self.balance.btc_balance, btc) {
+            let message = format!(
+                "⚠️ Tracked balance {:.8} {symbol} differs from exchange {:.8} {symbol}",
+                self.balance.btc_balance, btc
+            );
+            warn!("{message}");
+            msgs.push(message);
+            self.balance.btc_balance = btc.max(0.0);
+        }
+        if diverges(self.balance.usd_balance, usd) {
+            let message = format!(
+                "⚠️ Tracked balance {:.dp$} {quote} differs from exchange {:.dp$} {quote}",
+                self.balance.usd_balance, usd
+            );
+            warn!("{message}");
+            msgs.push(message);
+            self.balance.usd_balance = usd.max(0.0);
+        }
+    }
+
+    /// Cancels the orders left resting from the previous cycle and accounts for the fills they got meanwhile.
+    /// Nothing can be filled after the cancellation, so the reconciliation which follows sees the final balance.
+    async fn settle_pending_orders(&mut self, msgs: &mut Vec<String>) {
+        let symbol = self.pair.base.as_str();
+        for pending in std::mem::take(&mut self.pending_orders) {
+            if let Err(e) = self.exch.cancel_order(&self.pair, &pending.id).await {
+                // already filled or cancelled on the exchange
+                debug!("Cancel of order {} failed: {e}", pending.id);
+            }
+            let order = match self.exch.get_order(&self.pair, &pending.id).await {
+                Ok(order) => order,
+                Err(e) => {
+                    warn!(
+                        "Error fetching order {}, its fills are left to the reconciliation: {e}",
+                        pending.id
+                    );
+                    continue;
+                }
+            };
+
+            let amount = order.filled_amount - pending.filled_amount;
+            if amount <= 0.0 {
+                info_buf!(msgs, "Order {} {:?}, nothing more filled", order.id, order.status);
+                continue;
+            }
+            let value = order.filled_amount * order.avg_price - pending.filled_amount * pending.avg_price;
+            let btc_fee = fee_delta(order.btc_fee, pending.btc_fee, &order.id);
+            let usd_fee = fee_delta(order.usd_fee, pending.usd_fee, &order.id);
+            let price = value / amount;
+            let dp = quote_decimals(price);
+            info_buf!(
+                msgs,
+                "Order {} {:?}: {:?} {amount:.8} {symbol} at {price:.dp$} {}",
+                order.id,
+                order.status,
+                order.side,
+                self.pair.quote
+            );
+            self.journal.record_trade(Trade {
+                date: String::new(),
+                cycle: self.cycle_count,
+                order_id: order.id.clone(),
+                side: order.side,
+                amount,
+                price: value / amount,
+                btc_fee,
+                usd_fee,
+            });
+            self.position = Some(Position {
+                side: order.side,
+                price: value / amount,
+                amount,
+            });
+            match order.side {
+                Side::Buy => {
+                    self.balance.btc_balance += amount - btc_fee;
+                    self.balance.usd_balance -= value + usd_fee;
+                }
+                Side::Sell => {
+                    self.balance.btc_balance -= amount + btc_fee;
+                    self.balance.usd_balance += value - usd_fee;
+                }
+            }
+        }
+    }
+
+    /// Trading rules of the pair, cached after the first successful fetch. Without them the orders are not
+    /// rounded.
+    async fn instrument_info(&mut self) -> InstrumentInfo {
+        if let Some(info) = self.instrument {
+            return info;
+        }
+        match self.exch.instrument_info(&self.pair).await {
+            Ok(info) => {
+                debug!("Instrument info: {info:?}");
+                self.instrument = Some(info);
+                info
+            }
+            Err(e) => {
+                warn!("Error fetching instrument info, order sizes are not rounded: {e}");
+                InstrumentInfo::default()
+            }
+        }
+    }
+
+    fn save_state(&self) {
+        if self.cfg.state_file.is_empty() {
+            return;
+        }
+        if let Err(e) = self.state().save(&self.cfg.state_file) {
+            warn!("Failed to save state: {e:?}");
+        }
+    }
+
+    /// Checks the streamed prices against `feed_stop_loss` and `feed_take_profit` between the cycles
+    pub fn set_price_feed(&mut self, feed: PriceFeed) {
+        self.feed = Some(feed);
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.cfg
+    }
+
+    pub fn initial_balance(&self) -> &Balance {
+        &self.initial_balance
+    }
+
+    pub fn balance(&self) -> &Balance {
+        &self.balance
+    }
+
+    pub fn perf_tracker(&self) -> &PerfFeeTracker {
+        &self.perf_tracker
+    }
+
+    /// Returns the total set aside amounts (coin, quote currency)
+    pub fn total_take_profit(&self) -> (f64, f64) {
+        (self.total_take_profit_btc, self.total_take_profit_usd)
+    }
+
+    /// Limit orders placed by the bot which may still be filled
+    pub fn pending_orders(&self) -> &[Order] {
+        &self.pending_orders
+    }
+
+    pub fn cycle_count(&self) -> u32 {
+        self.cycle_count
+    }
+
+    /// Price seen in the last cycle
+    pub fn price(&self) -> f64 {
+        self.price
+    }
+
+    /// Logs the starting portfolio, returns the messages for Telegram
+    fn start_messages(&self) -> Vec<String> {
+        let symbol = self.pair.base.as_str();
+        let quote = self.pair.quote.as_str();
+        let price = self.price;
+        let dp = quote_decimals(price);
+        let mut msgs: Vec<String> = Vec::new();
+
+        info_buf!(
+            msgs,
+            "{} trading on {}, {} candles",
+            self.cfg.trading_mode(),
+            self.cfg.cex,
+            self.cfg.timeframe
+        );
+        info_buf!(msgs, "Current price {price:.dp$} {quote}");
+        info_buf!(msgs, "Starting portfolio:");
+        info_buf!(
+            msgs,
+            "  {:.8} {symbol} ({:.dp$} {quote})",
+            self.initial_balance.btc_balance,
+            self.initial_balance.btc_balance * price
+        );
+        info_buf!(
+            msgs,
+            "  {:.dp$} {quote} ({:.8} {symbol})",
+            self.initial_balance.usd_balance,
+            self.initial_balance.usd_balance / price
+        );
+
+        if self.cfg.is_simulation() {
+            println!("Current price {price:.dp$} {quote}");
+            println!(
+                "Starting portfolio: {:.8} {symbol}, {:.dp$} {quote}",
+                self.initial_balance.btc_balance, self.initial_balance.usd_balance
+            );
+        }
+
+        debug!("--------------------------------------------------------------------------------");
+        msgs
+    }
+
+    /// Runs the cycles until `simulate_cycles` is exceeded (or forever when it is 0)
+    pub async fn run(&mut self) -> Result<()> {
+        let msgs = self.start_messages();
+
+        if !self.cfg.is_simulation() {
+            // the cycle runs right after the bar is closed
+            let now = Utc::now();
+            let next_bar = self.cfg.timeframe.bar_start(now) + self.cfg.timeframe.duration();
+            let dur_until_next_bar = (next_bar - now).to_std().unwrap();
+            debug!(
+                "Waiting until the next {} bar: {dur_until_next_bar:?}",
+                self.cfg.timeframe
+            );
+            sleep_until(Instant::now() + dur_until_next_bar).await;
+        }
+
+        let mut interval = if self.cfg.simulate_day != 0 {
+            time::interval(Duration::from_millis(5))
+        } else {
+            time::interval(self.cfg.timeframe.duration().to_std().unwrap())
+        };
+        // Skip the first tick
+        interval.tick().await;
+
+        self.telegram
+            .send_message(self.cfg.telegram_channel_id, &msgs.join("\n"))
+            .await;
+
+        loop {
+            self.run_cycle().await?;
+
+            debug!("");
+            debug!(
+                "Waiting for next interval... {}",
+                chrono::Utc::now().format("%Y-%m-%d %H:%M:%S")
+            );
+            debug!("");
+            self.wait_for_next_cycle(&mut interval).await?;
+
+            if self.cfg.simulate_cycles > 0 && self.cycle_count > self.cfg.simulate_cycles {
+                self.finish();
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs `cycles` cycles one after another without waiting, then settles like the end of the simulation.
+    /// The exchange is expected to be the simulated one, which moves to the next day on every price fetch.
+    pub async fn run_backtest(&mut self, cycles: u32) -> Result<()> {
+        let msgs = self.start_messages();
+        self.telegram
+            .send_message(self.cfg.telegram_channel_id, &msgs.join("\n"))
+            .await;
+
+        for _ in 0..cycles {
+            self.run_cycle().await?;
+        }
+        self.finish();
+        Ok(())
+    }
+
+    /// Waits for the next scheduled cycle. Meanwhile the streamed prices are checked against the stop-loss and
+    /// take-profit levels of the last entry, when one is crossed the position is closed right away.
+    async fn wait_for_next_cycle(&mut self, interval: &mut Interval) -> Result<()> {
+        let Some(mut feed) = self.feed.take() else {
+            interval.tick().await;
+            return Ok(());
+        };
+        let result = loop {
+            tokio::select! {
+                _ = interval.tick() => break Ok(()),
+                price = feed.next_price() => {
+                    let Some(position) = self.position else {
+                        continue;
+                    };
+                    if let Some(reason) = self.price_trigger(&position, price) {
+                        warn!("{reason}, closing the position");
+                        if let Err(e) = self.close_position(position, price, reason).await {
+                            break Err(e);
+                        }
+                    }
+                }
+            }
+        };
+        self.feed = Some(feed);
+        result
+    }
+
+    /// Reason for closing the position when the streamed price moved too far against it or in its favour. The
+    /// levels stay at the entry price, a sold position gains when the price falls.
+    fn price_trigger(&self, position: &Position, price: f64) -> Option<String> {
+        if position.price <= 0.0 {
+            return None;
+        }
+        let change = price / position.price - 1.0;
+        let quote = &self.pair.quote;
+        let dp = quote_decimals(price);
+        let gain = match position.side {
+            Side::Buy => change,
+            Side::Sell => -change,
+        };
+        if self.cfg.feed_stop_loss > 0.0 && gain <= -self.cfg.feed_stop_loss {
+            Some(format!(
+                "Stop loss: price {price:.dp$} {quote} is {:.2}% against the {:?} at {:.dp$} {quote}",
+                -gain * 100.0,
+                position.side,
+                position.price
+            ))
+        } else if self.cfg.feed_take_profit > 0.0 && gain >= self.cfg.feed_take_profit {
+            Some(format!(
+                "Take profit: price {price:.dp$} {quote} is {:.2}% above the {:?} at {:.dp$} {quote}",
+                gain * 100.0,
+                position.side,
+                position.price
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Sells what the position bought or buys back what it sold with a market order, at most what the balance
+    /// allows. The position is dropped even when the order fails, so that it is not retried on every price.
+    async fn close_position(&mut self, position: Position, price: f64, reason: String) -> Result<()> {
+        let mut msgs = vec![reason];
+        self.position = None;
+        let instrument = self.instrument_info().await;
+        let (side, amount) = match position.side {
+            Side::Buy => (Side::Sell, position.amount.min(self.balance.btc_balance)),
+            Side::Sell => (Side::Buy, position.amount.min(self.balance.usd_balance / price)),
+        };
+
+        let mut balance = self.balance.clone();
+        let result = executor::execute_exit(
+            &self.cfg,
+            &*self.exch,
+            price,
+            side,
+            amount,
+            &instrument,
+            &mut balance,
+            &mut msgs,
+        )
+        .await;
+        match result {
+            Ok(Some(order)) => {
+                // the exit belongs to the bar which the next cycle closes
+                let cycle = self.cycle_count.wrapping_add(1);
+                self.journal.record_trade(Trade::from_order(cycle, &order));
+            }
+            Ok(None) => {}
+            Err(e) => {
+                msgs.push(format!("⛔⛔⛔ Trading halted: {e:#}"));
+                self.telegram
+                    .send_message(self.cfg.telegram_channel_id, &msgs.join("\n"))
+                    .await;
+                self.save_state();
+                return Err(e);
+            }
+        }
+        self.balance = balance;
+        self.telegram
+            .send_message(self.cfg.telegram_channel_id, &msgs.join("\n"))
+            .await;
+        self.save_state();
+        Ok(())
+    }
+
+    /// Runs one trading cycle. When the price or historical data cannot be fetched, the error is reported
+    /// and the cycle is skipped.
+    pub async fn run_cycle(&mut self) -> Result<()> {
+        self.cycle_count = self.cycle_count.wrapping_add(1);
+        let cycle_count = self.cycle_count;
+
+        let mut msgs: Vec<String> = Vec::new();
+
+        let price = match self.exch.get_price(&self.pair).await {
+            Ok(p) => p,
+            Err(e) => {
+                error!("Error fetching price: {e}");
+                self.telegram
+                    .send_message(
+                        self.cfg.telegram_channel_id,
+                        &format!("⛔⛔⛔ Error fetching price: {e}"),
+                    )
+                    .await;
+                return Ok(());
+            }
+        };
+
+        self.settle_pending_orders(&mut msgs).await;
+        self.reconcile_balance(&mut msgs).await;
+        let instrument = self.instrument_info().await;
+
+        let cfg = &self.cfg;
+        let symbol = self.pair.base.as_str();
+        let quote = self.pair.quote.as_str();
+        let dp = quote_decimals(price);
+        let is_perf_day = cycle_count.is_multiple_of(cfg.perf_fee_cycles);
+
+        info_buf!(
+            msgs,
+            "Current price {price:.dp$} {quote}, cycle {cycle_count}{}",
+            if is_perf_day { " 💲" } else { "" }
+        );
+
+        let mut balance = self.balance.clone();
+        info_buf!(msgs, "Initial account state:",);
+        info_buf!(msgs, "  {:.8} {symbol}", balance.btc_balance,);
+        info_buf!(msgs, "  {:.dp$} {quote}", balance.usd_balance);
+
+        let candles = match self.exch.get_candles(&self.pair, cfg.timeframe, cfg.period).await {
+            Ok(data) => data,
+            Err(e) => {
+                let message = format!("Error fetching historical data: {e:?}");
+                error!("{message}");
+                self.telegram
+                    .send_message(cfg.telegram_channel_id, &format!("⛔⛔⛔ {message}"))
+                    .await;
+                return Ok(());
+            }
+        };
+
+        let cfg_weights = StrategyWeights {
+            ema: cfg.weight_ema,
+            rsi: cfg.weight_rsi,
+            grid: cfg.weight_grid,
+            buy_dip: cfg.weight_buy_dip,
+            tp_or_bb: cfg.weight_tp_o_bb,
+        };
+
+        let weights = if cfg.use_fear_index {
+            match FearGreedIndex::fetch().await {
+                Ok(fear_greed) => {
+                    info_buf!(
+                        msgs,
+                        "F&G Index: {}% => {}",
+                        fear_greed.value,
+                        fear_greed.classification
+                    );
+                    fear_greed.apply(&cfg_weights)
+                }
+                Err(e) => {
+                    let message = format!("Error fetching fear&greed index: {e:?}");
+                    error!("{message}");
+                    self.telegram
+                        .send_message(cfg.telegram_channel_id, &format!("⛔⛔⛔ {message}"))
+                        .await;
+                    cfg_weights
+                }
+            }
+        } else {
+            cfg_weights
+        };
+
+        let signals = strategy::generate_signals(cfg, &candles, weights)?;
+
+        match executor::execute_signals(
+            cfg,
+            &*self.exch,
+            price,
+            signals,
+            &instrument,
+            &mut balance,
+            &mut self.pending_orders,
+            &mut msgs,
+        )
+        .await
+        {
+            Ok(Some(order)) => {
+                self.journal.record_trade(Trade::from_order(cycle_count, &order));
+                self.position = Some(Position {
+                    side: order.side,
+                    price: order.avg_price,
+                    amount: order.filled_amount,
+                });
+            }
+            Ok(None) => {}
+            Err(e) => {
+                msgs.push(format!("⛔⛔⛔ Trading halted: {e:#}"));
+                self.telegram
+                    .send_message(cfg.telegram_channel_id, &msgs.join("\n"))
+                    .await;
+                self.save_state();
+                return Err(e);
+            }
+        }
+
+        if cfg.perf_fee_rate > 0.0 && is_perf_day {
+            self.perf_tracker.maybe_deduct_fee(cfg, &mut balance, &mut msgs);
+        }
+
+        // Take profit
+
+        let initial_balance = &self.initial_balance;
+
+        if cfg.take_profit_btc > 0.0 {
+            let btc_profit = (balance.btc_balance - initial_balance.btc_balance).max(0.0);
+            if btc_profit / initial_balance.btc_balance >= cfg.take_profit_btc {
+                balance.btc_balance -= btc_profit;
+                self.total_take_profit_btc += btc_profit;
+                let pct = (btc_profit / initial_balance.btc_balance) * 100.0;
+                info_buf!(msgs, "Setting aside {:.8} {symbol}", btc_profit);
+                info_buf!(msgs, "  - increase {pct:.2}% since start",);
+                info_buf!(msgs, "  - total: {:.8} {symbol}", self.total_take_profit_btc);
+            }
+        }
+
+        if cfg.take_profit_usd > 0.0 {
+            let usd_profit = (balance.usd_balance - initial_balance.usd_balance).max(0.0);
+            if usd_profit / initial_balance.usd_balance >= cfg.take_profit_usd {
+                balance.usd_balance -= usd_profit;
+                self.total_take_profit_usd += usd_profit;
+                let pct = (usd_profit / initial_balance.usd_balance) * 100.0;
+                info_buf!(msgs, "Setting aside {usd_profit:.dp$} {quote}");
+                info_buf!(msgs, "  - increase {pct:.2}% since start",);
+                info_buf!(msgs, "  - total: {:.dp$} {quote}", self.total_take_profit_usd);
+            }
+        }
+
+        let total_take_profit_btc = self.total_take_profit_btc;
+        let total_take_profit_usd = self.total_take_profit_usd;
+
+        if (cfg.take_profit_btc > 0.0 || cfg.take_profit_usd > 0.0)
+            && (total_take_profit_btc > 0.0 || total_take_profit_usd > 0.0)
+        {
+            info_buf!(msgs, "Total set aside:");
+            if total_take_profit_btc > 0.0 {
+                info_buf!(msgs, "  {total_take_profit_btc:.8} {symbol}");
+            }
+            if total_take_profit_usd > 0.0 {
+                info_buf!(msgs, "  {total_take_profit_usd:.dp$} {quote}");
+            }
+        }
+
+        if let Some(candle) = candles.last() {
+            let date = DateTime::from_timestamp_millis(candle.ts)
+                .unwrap_or_default()
+                .date_naive();
+            let point = self.equity_point(date.to_string(), cycle_count, &balance, price);
+            self.journal.record_day(point, &self.initial_balance);
+        }
+        let metrics = self.metrics();
+
+        print_overall_evaluation(
+            false,
+            cfg,
+            price,
+            initial_balance,
+            &balance,
+            total_take_profit_btc,
+            total_take_profit_usd,
+            &metrics,
+            &mut msgs,
+        );
+
+        self.telegram
+            .send_message(cfg.telegram_channel_id, &msgs.join("\n"))
+            .await;
+
+        self.balance = balance;
+        self.price = price;
+
+        self.save_state();
+
+        Ok(())
+    }
+
+    /// Portfolio after the cycle for the equity curve
+    fn equity_point(&self, date: String, cycle: u32, balance: &Balance, price: f64) -> EquityPoint {
+        EquityPoint::new(
+            date,
+            cycle,
+            price,
+            balance.btc_balance,
+            balance.usd_balance,
+            (self.total_take_profit_btc, self.total_take_profit_usd),
+            (self.perf_tracker.total_fee_btc, self.perf_tracker.total_fee_usd),
+        )
+    }
+
+    /// Metrics of the run so far
+    pub fn metrics(&self) -> Metrics {
+        self.journal.metrics(&self.initial_balance, self.cfg.timeframe)
+    }
+
+    /// Runs `cycles` cycles like `run_backtest`, but without any output, and returns the metrics after the final
+    /// settlement. Used to evaluate many parameter sets over the same days.
+    pub async fn run_silent(&mut self, cycles: u32) -> Result<Metrics> {
+        for _ in 0..cycles {
+            self.run_cycle().await?;
+        }
+        self.settle(&mut Vec::new());
+        Ok(self.metrics())
+    }
+
+    /// Deducts the outstanding performance fee and updates the last day of the journal with it
+    fn settle(&mut self, msgs: &mut Vec<String>) {
+        self.perf_tracker.maybe_deduct_fee(&self.cfg, &mut self.balance, msgs);
+        if let Some(last) = self.journal.last_day() {
+            let point = self.equity_point(last.date.clone(), last.cycle, &self.balance, self.price);
+            self.journal.amend_last_day(point);
+        }
+    }
+
+    /// Final settlement: deducts the outstanding performance fee, prints the overall evaluation and writes the
+    /// report
+    pub fn finish(&mut self) {
+        let mut msgs: Vec<String> = Vec::new();
+        self.settle(&mut msgs);
+        let symbol = self.pair.base.as_str();
+        let metrics = self.metrics();
+
+        print_overall_evaluation(
+            true,
+            &self.cfg,
+            self.price,
+            &self.initial_balance,
+            &self.balance,
+            self.total_take_profit_btc,
+            self.total_take_profit_usd,
+            &metrics,
+            &mut msgs,
+        );
+
+        let benchmarks = benchmark::compare(
+            &self.initial_balance,
+            self.journal.equity(),
+            self.cfg.timeframe,
+            &metrics,
+        );
+        if !benchmarks.is_empty() {
+            info_buf!(msgs, "Benchmarks:");
+            println!("  Benchmarks:");
+        }
+        for benchmark in &benchmarks {
+            info_buf!(msgs, "  {}", benchmark.line());
+            println!("    {}", benchmark.line());
+        }
+        if self.cfg.perf_fee_rate > 0.0 {
+            println!(
+                "  Total reward {:.8} {symbol}, {:.dp$} {}",
+                self.perf_tracker.total_fee_btc,
+                self.perf_tracker.total_fee_usd,
+                self.pair.quote,
+                dp = quote_decimals(self.price)
+            );
+        }
+
+        if self.cfg.report_file.is_empty() {
+            return;
+        }
+        let report = std::mem::take(&mut self.journal).into_report(
+            self.pair.to_string(),
+            self.cfg.cex.clone(),
+            &self.initial_balance,
+            metrics,
+            benchmarks,
+        );
+        if let Some(report) = report {
+            match report.write(&self.cfg.report_file) {
+                Ok(()) => info!("Report written to {}", self.cfg.report_file),
+                Err(e) => error!("Failed to write the report: {e:#}"),
+            }
+        }
+    }
+}
+
+fn _print_historical(historical: &[f64]) {
+    let today = chrono::Utc::now().date_naive();
+    for (i, price) in historical.iter().enumerate() {
+        let date = today - chrono::Duration::days((historical.len() - i) as i64);
+        debug!("  Dátum: {date}, Cena: {price:.2}");
+    }
+}
+
+fn _print_cycle_evaluation(price: f64, old_balance: &Balance, new_balance: &Balance) {
+    // Total value in BTC
+    let old_total_btc = old_balance.btc_balance + old_balance.usd_balance / price;
+    let new_total_btc = new_balance.btc_balance + new_balance.usd_balance / price;
+    let pct_btc = if old_total_btc > 0.0 {
+        (new_total_btc - old_total_btc) / old_total_btc * 100.0
+    } else {
+        0.0
+    };
+
+    // Total value in USD
+    let old_total_usd = old_balance.btc_balance * price + old_balance.usd_balance;
+    let new_total_usd = new_balance.btc_balance * price + new_balance.usd_balance;
+    let pct_usd = if old_total_usd > 0.0 {
+        (new_total_usd - old_total_usd) / old_total_usd * 100.0
+    } else {
+        0.0
+    };
+
+    debug!("  {pct_btc:.4}% in BTC, {pct_usd:.4}% in USD");
+}
+
+/// Fee charged since the last check of the order. The fees are cumulative, a smaller one (rebate, other fee
+/// currency) is not booked as a new fee.
+fn fee_delta(current: f64, accounted: f64, order_id: &str) -> f64 {
+    let delta = current.abs() - accounted.abs();
+    if delta < 0.0 {
+        warn!("Fee of order {order_id} decreased from {accounted} to {current}, ignoring it");
+    }
+    delta.max(0.0)
+}
+
+/// Relative difference above which the tracked and the exchange balance are considered diverged
+const BALANCE_TOLERANCE: f64 = 0.001;
+
+fn diverges(tracked: f64, real: f64) -> bool {
+    (tracked - real).abs() > tracked.abs().max(real.abs()) * BALANCE_TOLERANCE
+}
+
+fn pct(initial: f64, current: f64) -> f64 {
+    (current - initial) / initial * 100.0
+}
+
+#[allow(clippy::too_many_arguments)]
+fn print_overall_evaluation(
+    print: bool,
+    cfg: &Config,
+    price: f64,
+    initial_balance: &Balance,
+    current_balance: &Balance,
+    total_take_profit_btc: f64,
+    total_take_profit_usd: f64,
+    metrics: &Metrics,
+    msgs: &mut Vec<String>,
+) {
+    let pair = cfg.pair();
+    let (symbol, quote) = (&pair.base, &pair.quote);
+    let dp = quote_decimals(price);
+
+    let initial_btc = initial_balance.btc_balance;
+    let current_btc = current_balance.btc_balance + total_take_profit_btc;
+    let pct_current_btc = pct(initial_btc, current_btc);
+
+    let initial_usd = initial_balance.usd_balance;
+    let current_usd = current_balance.usd_balance + total_take_profit_usd;
+    let pct_current_usd = pct(initial_usd, current_usd);
+
+    info_buf!(msgs, "Final account state",);
+    info_buf!(msgs, "  {:.8} {}, {pct_current_btc:.2}%", current_btc, symbol);
+    info_buf!(msgs, "  {current_usd:.dp$} {quote}, {pct_current_usd:.2}%");
+    for line in metrics.lines() {
+        info_buf!(msgs, "  {line}");
+    }
+
+    if print {
+        println!(
+            "  {symbol}: {pct_current_btc:.2}% ({current_btc:.8}), {quote}: {pct_current_usd:.2}% ({current_usd:.dp$})"
+        );
+        for line in metrics.lines() {
+            println!("  {line}");
+        }
+    }
+}
+
+// eof
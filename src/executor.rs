@@ -1,13 +1,20 @@
 use anyhow::Result;
-use log::{debug, error, trace};
+use log::{debug, error, trace, warn};
+use std::time::Duration;
 
 use crate::balance::Balance;
 use crate::cex::CexClient;
+use crate::cex::error::ErrorAction;
 use crate::config::Config;
+use crate::instrument::{InstrumentInfo, quote_decimals};
+use crate::order::{Order, OrderResponse, OrderType};
 use crate::signal::{Signal, Signals};
 use crate::strategy::Strategy;
 use crate::{info_buf, order};
 
+// delay before the rate limited order is placed again
+const ORDER_RETRY_DELAY: Duration = Duration::from_secs(5);
+
 fn print_signal(strategy: Strategy, signal: Signal, weight: f64) {
     trace!("{strategy:?} ({weight}) {signal:?}");
 }
@@ -78,14 +85,18 @@ fn combined(cfg: &Config, signals: Signals) -> Signal {
     }
 }
 
+/// Executes the signal of the configured strategy. Returns the order when some of it was filled right away.
+#[allow(clippy::too_many_arguments)]
 pub async fn execute_signals(
     cfg: &Config,
     exch: &dyn CexClient,
     price: f64,
     signals: Signals,
+    instrument: &InstrumentInfo,
     balance: &mut Balance,
+    pending: &mut Vec<Order>,
     msgs: &mut Vec<String>,
-) -> Result<()> {
+) -> Result<Option<Order>> {
     let signal = match cfg.strategy {
         Strategy::Ema => signals.ema.unwrap(),
         Strategy::Rsi => signals.rsi.unwrap(),
@@ -103,70 +114,209 @@ pub async fn execute_signals(
         let stop_lose_usd = signal == Signal::Buy && balance.stop_lose_usd(cfg.stop_lose_usd);
         let stop_lose_btc = signal == Signal::Sell && balance.stop_lose_btc(cfg.stop_lose_btc);
         if stop_lose_btc || stop_lose_usd {
-            let symbol = cfg.coin.symbol();
+            let pair = cfg.pair();
             debug!(
-                "  {signal:?} {amount:.8} {symbol}: STOP LOSE {}{}{} 🚫",
-                if stop_lose_btc { symbol } else { "" },
+                "  {signal:?} {amount:.8} {}: STOP LOSE {}{}{} 🚫",
+                pair.base,
+                if stop_lose_btc { pair.base.as_str() } else { "" },
                 if stop_lose_btc && stop_lose_usd { " and " } else { "" },
-                if stop_lose_usd { "USD" } else { "" }
+                if stop_lose_usd { pair.quote.as_str() } else { "" }
             );
         } else {
-            execute_signal(cfg, exch, price, signal, amount, balance, msgs).await?;
+            return execute_signal(
+                cfg,
+                exch,
+                price,
+                signal,
+                amount,
+                cfg.order_type,
+                instrument,
+                balance,
+                pending,
+                msgs,
+            )
+            .await;
         }
     }
 
-    Ok(())
+    Ok(None)
+}
+
+/// Closes the position with a market order of `side`, whatever the configured order type. Returns the order when
+/// some of it was filled.
+#[allow(clippy::too_many_arguments)]
+pub async fn execute_exit(
+    cfg: &Config,
+    exch: &dyn CexClient,
+    price: f64,
+    side: order::Side,
+    amount: f64,
+    instrument: &InstrumentInfo,
+    balance: &mut Balance,
+    msgs: &mut Vec<String>,
+) -> Result<Option<Order>> {
+    let signal = match side {
+        order::Side::Buy => Signal::Buy,
+        order::Side::Sell => Signal::Sell,
+    };
+    // a market order never rests on the book
+    let mut pending = Vec::new();
+    execute_signal(
+        cfg,
+        exch,
+        price,
+        signal,
+        amount,
+        OrderType::Market,
+        instrument,
+        balance,
+        &mut pending,
+        msgs,
+    )
+    .await
+}
+
+/// Remembers the unfilled part of a limit order which rests on the order book, so that its later fills are
+/// accounted for. Returns `false` when nothing rests on the book.
+#[allow(clippy::too_many_arguments)]
+fn track_resting(
+    quote: &str,
+    order_type: OrderType,
+    side: order::Side,
+    amount: f64,
+    price: f64,
+    res: &OrderResponse,
+    pending: &mut Vec<Order>,
+    msgs: &mut Vec<String>,
+) -> bool {
+    if res.executed_amount < amount && matches!(order_type, OrderType::Limit | OrderType::PostOnly) {
+        info_buf!(
+            msgs,
+            "  {order_type:?} {} at {price:.dp$} {quote} resting, {:.8} unfilled",
+            res.order_id,
+            amount - res.executed_amount,
+            dp = quote_decimals(price)
+        );
+        pending.push(res.to_order(side, amount, Some(price)));
+        return true;
+    }
+    false
+}
+
+/// Places the order, a rate limited order is placed once more after a while. Returns `None` when the order failed
+/// and was skipped, the reason is reported in the messages. Fails when trading has to halt.
+async fn place_order(
+    cfg: &Config,
+    exch: &dyn CexClient,
+    side: order::Side,
+    amount: f64,
+    order_type: OrderType,
+    price: f64,
+    msgs: &mut Vec<String>,
+) -> Result<Option<OrderResponse>> {
+    let mut retried = false;
+    loop {
+        let err = match exch.put_order(&cfg.pair(), side, amount, order_type, Some(price)).await {
+            Ok(res) => return Ok(Some(res)),
+            Err(e) => e,
+        };
+        match ErrorAction::of(&err) {
+            ErrorAction::Retry if !retried => {
+                warn!("Failed to place {side:?} order: {err}, retry in {ORDER_RETRY_DELAY:?}");
+                retried = true;
+                tokio::time::sleep(ORDER_RETRY_DELAY).await;
+            }
+            ErrorAction::Halt => {
+                error!("Failed to place {side:?} order: {err}");
+                return Err(err.context(format!("{side:?} order failed")));
+            }
+            ErrorAction::Retry | ErrorAction::Skip => {
+                error!("Failed to place {side:?} order: {err}");
+                msgs.push(format!("⛔ {side:?} order skipped: {err}"));
+                return Ok(None);
+            }
+        }
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn execute_signal(
     cfg: &Config,
     exch: &dyn CexClient,
     price: f64,
     signal: Signal,
     amount: f64,
+    order_type: OrderType,
+    instrument: &InstrumentInfo,
     balance: &mut Balance,
+    pending: &mut Vec<Order>,
     msgs: &mut Vec<String>,
-) -> Result<()> {
-    let symbol = cfg.coin.symbol();
-    info_buf!(msgs, "{signal:?} {amount:.8} {}", cfg.coin.symbol());
+) -> Result<Option<Order>> {
+    let pair = cfg.pair();
+    let (symbol, quote) = (&pair.base, pair.quote.as_str());
+    let dp = quote_decimals(price);
+    let amount = instrument.round_size(amount);
+    if let Some(reason) = instrument.dust_reason(amount, price) {
+        info_buf!(msgs, "{signal:?} {amount:.8} {symbol} skipped: {reason}");
+        return Ok(None);
+    }
+    info_buf!(msgs, "{signal:?} {amount:.8} {}", cfg.pair().base);
     match signal {
         Signal::Buy => {
-            match exch.put_order(&cfg.coin, order::Side::Buy, amount, Some(price)).await {
-                Ok(res) => {
-                    //debug!("  {res}");
-                    info_buf!(msgs, "  cena: {:.2} USD", res.executed_price);
-                    if res.btc_fee != 0.0 {
-                        info_buf!(msgs, "  poplatok: {:.8} {symbol}", res.btc_fee);
-                    }
-                    if res.usd_fee != 0.0 {
-                        info_buf!(msgs, "  poplatok: {:.2} USD", res.usd_fee);
-                    }
-                    balance.btc_balance += res.executed_amount - res.btc_fee.abs();
-                    balance.usd_balance -= res.executed_amount * res.executed_price + res.usd_fee.abs();
+            let price = match order_type {
+                OrderType::Market => price,
+                _ => instrument.round_price(price * (1.0 - cfg.limit_offset), false),
+            };
+            let Some(res) = place_order(cfg, exch, order::Side::Buy, amount, order_type, price, msgs).await? else {
+                return Ok(None);
+            };
+            if res.executed_amount <= 0.0 {
+                if !track_resting(quote, order_type, order::Side::Buy, amount, price, &res, pending, msgs) {
+                    info_buf!(msgs, "  {order_type:?} at {price:.dp$} {quote} not filled");
                 }
-                Err(e) => {
-                    error!("Failed to place BUY order: {e}");
+            } else {
+                track_resting(quote, order_type, order::Side::Buy, amount, price, &res, pending, msgs);
+                //debug!("  {res}");
+                info_buf!(msgs, "  price: {:.dp$} {quote}", res.executed_price);
+                if res.btc_fee != 0.0 {
+                    info_buf!(msgs, "  fee: {:.8} {symbol}", res.btc_fee);
                 }
+                if res.usd_fee != 0.0 {
+                    info_buf!(msgs, "  fee: {:.8} {quote}", res.usd_fee);
+                }
+                balance.btc_balance += res.executed_amount - res.btc_fee.abs();
+                balance.usd_balance -= res.executed_amount * res.executed_price + res.usd_fee.abs();
+                return Ok(Some(res.to_order(order::Side::Buy, amount, Some(price))));
             }
         }
-        Signal::Sell => match exch.put_order(&cfg.coin, order::Side::Sell, amount, Some(price)).await {
-            Ok(res) => {
+        Signal::Sell => {
+            let price = match order_type {
+                OrderType::Market => price,
+                _ => instrument.round_price(price * (1.0 + cfg.limit_offset), true),
+            };
+            let Some(res) = place_order(cfg, exch, order::Side::Sell, amount, order_type, price, msgs).await? else {
+                return Ok(None);
+            };
+            if res.executed_amount <= 0.0 {
+                if !track_resting(quote, order_type, order::Side::Sell, amount, price, &res, pending, msgs) {
+                    info_buf!(msgs, "  {order_type:?} at {price:.dp$} {quote} not filled");
+                }
+            } else {
+                track_resting(quote, order_type, order::Side::Sell, amount, price, &res, pending, msgs);
                 if res.btc_fee != 0.0 {
-                    info_buf!(msgs, "  poplatok: {:.8} {symbol}", res.btc_fee);
+                    info_buf!(msgs, "  fee: {:.8} {symbol}", res.btc_fee);
                 }
                 if res.usd_fee != 0.0 {
-                    info_buf!(msgs, "  poplatok: {:.2} USD", res.usd_fee);
+                    info_buf!(msgs, "  fee: {:.8} {quote}", res.usd_fee);
                 }
                 balance.btc_balance -= res.executed_amount + res.btc_fee.abs();
                 balance.usd_balance += res.executed_amount * res.executed_price - res.usd_fee.abs();
+                return Ok(Some(res.to_order(order::Side::Sell, amount, Some(price))));
             }
-            Err(e) => {
-                error!("Failed to place SELL order: {e}");
-            }
-        },
+        }
         Signal::Hold => {}
     }
-    Ok(())
+    Ok(None)
 }
 
 // eof
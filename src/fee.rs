@@ -3,6 +3,7 @@ use log::info;
 use crate::balance::Balance;
 use crate::config::{Config, PerfFeeMode};
 use crate::info_buf;
+use crate::instrument::quote_decimals;
 
 //const DEFAULT_HWM_WINDOW: usize = 10; // počet cyklov na sledovanie HWM v okne
 
@@ -43,7 +44,9 @@ impl PerfFeeTracker {
         }
 
         let perf_fee = cfg.perf_fee_rate * 100.0;
-        let symbol = cfg.coin.symbol();
+        let pair = cfg.pair();
+        let (symbol, quote) = (&pair.base, &pair.quote);
+        let dp = quote_decimals(balance._btc_price);
 
         info_buf!(msgs, "Výplata odmeny:");
 
@@ -83,9 +86,9 @@ impl PerfFeeTracker {
                         balance.usd_balance -= fee_usd;
                     }
                     self.total_fee_usd += fee_usd;
-                    info_buf!(msgs, "  - vyplatiť {fee_usd:.2} USD ({perf_fee:.2}%)");
+                    info_buf!(msgs, "  - vyplatiť {fee_usd:.dp$} {quote} ({perf_fee:.2}%)");
                 } else {
-                    info_buf!(msgs, "  - žiadna odmena USD");
+                    info_buf!(msgs, "  - žiadna odmena {quote}");
                 }
 
                 self.high_water_mark_btc = balance.btc_balance;
@@ -114,16 +117,19 @@ impl PerfFeeTracker {
                         balance.usd_balance -= fee_usd;
                     }
                     self.total_fee_usd += fee_usd;
-                    info_buf!(msgs, "  - vyplatiť {fee_usd:.2} USD (kumulatívne, {perf_fee:.2}%)");
+                    info_buf!(
+                        msgs,
+                        "  - vyplatiť {fee_usd:.dp$} {quote} (kumulatívne, {perf_fee:.2}%)"
+                    );
                 } else {
-                    info_buf!(msgs, "  - žiadna odmena USD");
+                    info_buf!(msgs, "  - žiadna odmena {quote}");
                 }
             }
         }
 
         if cfg.perf_fee_rate > 0.0 && cfg.is_simulation() {
             info!(
-                "Celková odmena: {:.8} {symbol}, {:.2} USD",
+                "Celková odmena: {:.8} {symbol}, {:.dp$} {quote}",
                 self.total_fee_btc, self.total_fee_usd
             );
         }
@@ -0,0 +1,159 @@
+/// Trading rules of the pair on the exchange. Zero means no constraint.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InstrumentInfo {
+    /// Minimum order size in the base currency
+    pub min_size: f64,
+    /// Order size has to be a multiple of it
+    pub size_increment: f64,
+    /// Limit price has to be a multiple of it
+    pub price_tick: f64,
+    /// Minimum order value in the quote currency
+    pub min_notional: f64,
+}
+
+// tolerance for values which are a multiple of the step, but not exactly because of the floating point
+const STEP_EPSILON: f64 = 1e-9;
+
+impl InstrumentInfo {
+    /// Rounds the size down to the size increment
+    pub fn round_size(&self, amount: f64) -> f64 {
+        if self.size_increment > 0.0 {
+            (amount / self.size_increment + STEP_EPSILON).floor() * self.size_increment
+        } else {
+            amount
+        }
+    }
+
+    /// Rounds the limit price to the price tick, down for buy and up for sell so that the order is not worse
+    /// than requested
+    pub fn round_price(&self, price: f64, round_up: bool) -> f64 {
+        if self.price_tick <= 0.0 {
+            return price;
+        }
+        let ticks = price / self.price_tick;
+        let ticks = if round_up {
+            (ticks - STEP_EPSILON).ceil()
+        } else {
+            (ticks + STEP_EPSILON).floor()
+        };
+        ticks * self.price_tick
+    }
+
+    /// Returns the reason why the order is too small to be placed
+    pub fn dust_reason(&self, amount: f64, price: f64) -> Option<String> {
+        if amount <= 0.0 {
+            Some("nothing left after rounding to the size increment".to_string())
+        } else if amount < self.min_size {
+            Some(format!("below the minimum size {}", format_decimal(self.min_size)))
+        } else if amount * price < self.min_notional {
+            let dp = quote_decimals(price);
+            Some(format!(
+                "value {:.dp$} is below the minimum {:.dp$}",
+                amount * price,
+                self.min_notional
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+/// Decimals of the prices and the amounts in the quote currency: 2 like for USD, more for the prices below 10, so
+/// that they keep 4 significant digits (0.03450 instead of 0.03 for ETH-BTC)
+pub fn quote_decimals(price: f64) -> usize {
+    if !price.is_finite() || price <= 0.0 {
+        return 2;
+    }
+    (3 - price.log10().floor() as i64).clamp(2, 10) as usize
+}
+
+/// Formats the amount or price for the exchange API, without the exponent and the floating point noise
+pub fn format_decimal(value: f64) -> String {
+    let s = format!("{value:.10}");
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    if s.is_empty() || s == "-" {
+        "0".to_string()
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-12, "{actual} != {expected}");
+    }
+
+    fn info() -> InstrumentInfo {
+        InstrumentInfo {
+            min_size: 0.001,
+            size_increment: 0.0001,
+            price_tick: 0.1,
+            min_notional: 10.0,
+        }
+    }
+
+    #[test]
+    fn round_size_down_to_the_increment() {
+        let info = info();
+        assert_close(info.round_size(0.123456), 0.1234);
+        assert_close(info.round_size(0.00009), 0.0);
+        assert_close(InstrumentInfo::default().round_size(0.123456), 0.123456);
+    }
+
+    #[test]
+    fn round_size_keeps_multiples_despite_the_floating_point() {
+        let info = InstrumentInfo {
+            size_increment: 0.1,
+            ..Default::default()
+        };
+        // 0.1 + 0.2 = 0.30000000000000004 and 0.7 / 0.1 = 6.999999999999999
+        assert_eq!(format_decimal(info.round_size(0.1 + 0.2)), "0.3");
+        assert_eq!(format_decimal(info.round_size(0.3)), "0.3");
+        assert_eq!(format_decimal(info.round_size(0.7)), "0.7");
+    }
+
+    #[test]
+    fn round_price_to_the_tick() {
+        let info = info();
+        assert_close(info.round_price(100.05, false), 100.0);
+        assert_close(info.round_price(100.05, true), 100.1);
+        // a multiple of the tick stays, in both directions
+        assert_eq!(format_decimal(info.round_price(0.1 + 0.2, true)), "0.3");
+        assert_eq!(format_decimal(info.round_price(0.3, false)), "0.3");
+        assert_close(InstrumentInfo::default().round_price(100.05, true), 100.05);
+    }
+
+    #[test]
+    fn dust_reason_rejects_small_orders() {
+        let info = info();
+        let reason = |amount, price| info.dust_reason(amount, price).unwrap_or_default();
+        assert!(reason(0.0, 20000.0).contains("nothing left"));
+        assert!(reason(0.0005, 100000.0).contains("minimum size 0.001"));
+        assert!(reason(0.002, 4000.0).contains("value 8.00 is below the minimum 10.00"));
+        assert_eq!(info.dust_reason(0.002, 5000.0), None);
+        assert_eq!(InstrumentInfo::default().dust_reason(0.00000001, 1.0), None);
+    }
+
+    #[test]
+    fn quote_decimals_keep_small_prices_readable() {
+        assert_eq!(quote_decimals(30000.0), 2);
+        assert_eq!(quote_decimals(12.5), 2);
+        assert_eq!(quote_decimals(1.5), 3);
+        assert_eq!(format!("{:.*}", quote_decimals(0.0345), 0.0345), "0.03450");
+        assert_eq!(quote_decimals(0.0), 2);
+        assert_eq!(quote_decimals(f64::NAN), 2);
+    }
+
+    #[test]
+    fn format_decimal_without_exponent_and_noise() {
+        assert_eq!(format_decimal(0.1 + 0.2), "0.3");
+        assert_eq!(format_decimal(0.00000001), "0.00000001");
+        assert_eq!(format_decimal(100.0), "100");
+        assert_eq!(format_decimal(0.0), "0");
+    }
+}
+
+// eof
@@ -1,13 +1,24 @@
+pub mod backtest;
 pub mod balance;
+pub mod benchmark;
+pub mod candle;
 pub mod cex;
 pub mod coin;
 pub mod config;
+pub mod engine;
 pub mod executor;
 pub mod fear_greed;
 pub mod fee;
+pub mod instrument;
 pub mod logger;
+pub mod metrics;
+pub mod mock;
+pub mod optimize;
 pub mod order;
+pub mod pair;
+pub mod report;
 pub mod signal;
+pub mod state;
 pub mod strategy;
 pub mod telegram;
 
@@ -0,0 +1,371 @@
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::{
+    balance::Balance,
+    candle::Timeframe,
+    order::Side,
+    report::{EarlierTrades, EquityPoint, Trade},
+};
+
+/// Risk and performance of the run, computed from the equity curve in USD (balances and set aside amounts) and the
+/// trades. The ratios are annualized from the cycle returns, with zero risk-free rate.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Metrics {
+    /// Change of the total portfolio value
+    pub total_return_pct: f64,
+    pub cagr_pct: f64,
+    pub max_drawdown_pct: f64,
+    /// Longest time under a previous peak, until it is recovered or until the end
+    pub max_drawdown_days: f64,
+    pub sharpe: f64,
+    pub sortino: f64,
+    /// Annualized standard deviation of the cycle returns
+    pub volatility_pct: f64,
+    /// Share of the sells above the average cost of the coin, after fees
+    pub win_rate_pct: f64,
+    /// Gross profit of the sells divided by their gross loss, infinite without a loss
+    #[serde(serialize_with = "serialize_ratio")]
+    pub profit_factor: f64,
+    /// Share of the cycles with the coin held
+    pub exposure_pct: f64,
+    pub trades: usize,
+}
+
+impl Metrics {
+    /// Metrics of the equity curve which starts from the initial balance, one point per cycle. The `earlier`
+    /// trades were rolled up before the `trades`.
+    pub fn compute(
+        initial: &Balance,
+        equity: &[EquityPoint],
+        trades: &[Trade],
+        earlier: Option<&EarlierTrades>,
+        timeframe: Timeframe,
+    ) -> Self {
+        let initial_value = initial.btc_balance * initial._btc_price + initial.usd_balance;
+        let values: Vec<f64> = std::iter::once(initial_value)
+            .chain(equity.iter().map(|point| point.equity_usd))
+            .collect();
+        let returns: Vec<f64> = values
+            .windows(2)
+            .filter(|pair| pair[0] > 0.0)
+            .map(|pair| pair[1] / pair[0] - 1.0)
+            .collect();
+
+        let bar_days = timeframe.minutes() as f64 / (24.0 * 60.0);
+        let periods_per_year = 365.0 / bar_days;
+        // an empty portfolio at the start has no return
+        let growth = if initial_value > 0.0 {
+            values.last().copied().unwrap_or(initial_value) / initial_value
+        } else {
+            1.0
+        };
+        let years = equity.len() as f64 / periods_per_year;
+        let cagr = if years > 0.0 && growth > 0.0 {
+            growth.powf(1.0 / years) - 1.0
+        } else {
+            0.0
+        };
+
+        let mean = average(&returns);
+        let std_dev = average(&returns.iter().map(|r| (r - mean).powi(2)).collect::<Vec<_>>()).sqrt();
+        let downside_dev = average(&returns.iter().map(|r| r.min(0.0).powi(2)).collect::<Vec<_>>()).sqrt();
+        let annualize = periods_per_year.sqrt();
+        let ratio = |dev: f64| if dev > 0.0 { mean / dev * annualize } else { 0.0 };
+
+        let (max_drawdown, drawdown_bars) = max_drawdown(&values);
+        let mut sells = earlier.map_or_else(|| SellTally::new(initial), |earlier| earlier.sells);
+        trades.iter().for_each(|trade| sells.add(trade));
+        let (win_rate, profit_factor) = sells.results();
+        let exposed = equity.iter().filter(|point| point.btc_balance > 0.0).count();
+
+        Metrics {
+            total_return_pct: (growth - 1.0) * 100.0,
+            cagr_pct: cagr * 100.0,
+            max_drawdown_pct: max_drawdown * 100.0,
+            max_drawdown_days: drawdown_bars as f64 * bar_days,
+            sharpe: ratio(std_dev),
+            sortino: ratio(downside_dev),
+            volatility_pct: std_dev * annualize * 100.0,
+            win_rate_pct: win_rate * 100.0,
+            profit_factor,
+            exposure_pct: if equity.is_empty() {
+                0.0
+            } else {
+                exposed as f64 / equity.len() as f64 * 100.0
+            },
+            trades: trades.len() + earlier.map_or(0, |earlier| earlier.count),
+        }
+    }
+
+    /// CAGR divided by the max drawdown, infinite without a drawdown
+    pub fn calmar(&self) -> f64 {
+        if self.max_drawdown_pct > 0.0 {
+            self.cagr_pct / self.max_drawdown_pct
+        } else if self.cagr_pct > 0.0 {
+            f64::INFINITY
+        } else {
+            0.0
+        }
+    }
+
+    /// Lines of the evaluation for the logs and Telegram
+    pub fn lines(&self) -> [String; 3] {
+        [
+            format!(
+                "Return {:.2}%, CAGR {:.2}%, max drawdown {:.2}% ({:.0} days)",
+                self.total_return_pct, self.cagr_pct, self.max_drawdown_pct, self.max_drawdown_days
+            ),
+            format!(
+                "Sharpe {:.2}, Sortino {:.2}, volatility {:.2}%",
+                self.sharpe, self.sortino, self.volatility_pct
+            ),
+            format!(
+                "Win rate {:.2}%, profit factor {:.2}, exposure {:.2}%, {} trades",
+                self.win_rate_pct, self.profit_factor, self.exposure_pct, self.trades
+            ),
+        ]
+    }
+}
+
+fn average(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// Largest fall from a peak (fraction) and the longest time under a peak (bars)
+pub(crate) fn max_drawdown(values: &[f64]) -> (f64, usize) {
+    let mut peak = f64::MIN;
+    let mut peak_index = 0;
+    let mut max_drawdown: f64 = 0.0;
+    let mut max_duration = 0;
+    for (i, &value) in values.iter().enumerate() {
+        if value >= peak {
+            peak = value;
+            peak_index = i;
+        } else {
+            max_drawdown = max_drawdown.max(1.0 - value / peak);
+        }
+        max_duration = max_duration.max(i - peak_index);
+    }
+    (max_drawdown, max_duration)
+}
+
+/// Writes an infinite ratio as the string "inf" (or "-inf"), JSON has no number for it and would write null
+pub fn serialize_ratio<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    match *value {
+        f64::INFINITY => serializer.serialize_str("inf"),
+        f64::NEG_INFINITY => serializer.serialize_str("-inf"),
+        value => serializer.serialize_f64(value),
+    }
+}
+
+/// Sells against the average cost of the coin, for the win rate and the profit factor. The initial coin costs the
+/// initial price, the buys add to the cost with their fees.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct SellTally {
+    amount: f64,
+    cost: f64,
+    wins: usize,
+    sells: usize,
+    profit: f64,
+    loss: f64,
+}
+
+impl SellTally {
+    pub fn new(initial: &Balance) -> Self {
+        SellTally {
+            amount: initial.btc_balance,
+            cost: initial.btc_balance * initial._btc_price,
+            ..Default::default()
+        }
+    }
+
+    pub fn add(&mut self, trade: &Trade) {
+        match trade.side {
+            Side::Buy => {
+                self.amount += trade.amount - trade.btc_fee;
+                self.cost += trade.amount * trade.price + trade.usd_fee;
+            }
+            Side::Sell => {
+                let avg_cost = if self.amount > 0.0 {
+                    self.cost / self.amount
+                } else {
+                    trade.price
+                };
+                let pnl = trade.amount * (trade.price - avg_cost) - trade.usd_fee - trade.btc_fee * trade.price;
+                let sold = (trade.amount + trade.btc_fee).min(self.amount);
+                self.cost -= sold * avg_cost;
+                self.amount -= sold;
+                self.sells += 1;
+                if pnl > 0.0 {
+                    self.wins += 1;
+                    self.profit += pnl;
+                } else {
+                    self.loss -= pnl;
+                }
+            }
+        }
+    }
+
+    /// Win rate and profit factor
+    fn results(&self) -> (f64, f64) {
+        let win_rate = if self.sells > 0 {
+            self.wins as f64 / self.sells as f64
+        } else {
+            0.0
+        };
+        let profit_factor = if self.loss > 0.0 {
+            self.profit / self.loss
+        } else if self.profit > 0.0 {
+            f64::INFINITY
+        } else {
+            0.0
+        };
+        (win_rate, profit_factor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+    }
+
+    /// Equity curve of a portfolio held in USD only
+    fn curve(values: &[f64]) -> Vec<EquityPoint> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, &usd)| EquityPoint::new(String::new(), i as u32 + 1, 1.0, 0.0, usd, (0.0, 0.0), (0.0, 0.0)))
+            .collect()
+    }
+
+    fn compute(initial_usd: f64, values: &[f64]) -> Metrics {
+        Metrics::compute(
+            &Balance::new(0.0, initial_usd, 1.0),
+            &curve(values),
+            &[],
+            None,
+            Timeframe::Day1,
+        )
+    }
+
+    fn assert_no_nan(metrics: &Metrics) {
+        let values = [
+            metrics.total_return_pct,
+            metrics.cagr_pct,
+            metrics.max_drawdown_pct,
+            metrics.max_drawdown_days,
+            metrics.sharpe,
+            metrics.sortino,
+            metrics.volatility_pct,
+            metrics.win_rate_pct,
+            metrics.profit_factor,
+            metrics.exposure_pct,
+            metrics.calmar(),
+        ];
+        // infinite profit factor and Calmar ratio mean no loss and no drawdown
+        assert!(!values.iter().any(|value| value.is_nan()), "{metrics:?}");
+    }
+
+    #[test]
+    fn drawdown_from_the_highest_peak() {
+        // 120 -> 90 is 25 %, 130 -> 100 only 23 %, the longest time under a peak is 2 bars after 120
+        let (drawdown, bars) = max_drawdown(&[100.0, 120.0, 90.0, 110.0, 130.0, 100.0]);
+        assert_close(drawdown, 0.25);
+        assert_eq!(bars, 2);
+
+        let metrics = compute(100.0, &[120.0, 90.0, 110.0, 130.0, 100.0]);
+        assert_close(metrics.max_drawdown_pct, 25.0);
+        assert_close(metrics.max_drawdown_days, 2.0);
+        assert_close(metrics.total_return_pct, 0.0);
+    }
+
+    #[test]
+    fn sharpe_and_sortino_annualized_from_the_daily_returns() {
+        // returns 10 % and 0 %: mean 5 %, standard deviation 5 %, no downside
+        let metrics = compute(100.0, &[110.0, 110.0]);
+        assert_close(metrics.sharpe, 365f64.sqrt());
+        assert_close(metrics.volatility_pct, 5.0 * 365f64.sqrt());
+        assert_close(metrics.sortino, 0.0);
+
+        // returns 10 % and -10 %: mean 0, downside deviation sqrt(0.01 / 2)
+        let metrics = compute(100.0, &[110.0, 99.0]);
+        assert_close(metrics.sharpe, 0.0);
+        assert_close(metrics.sortino, 0.0);
+        assert_close(metrics.total_return_pct, -1.0);
+    }
+
+    #[test]
+    fn cagr_over_whole_years() {
+        // 21 % in two years of daily cycles is 10 % a year
+        let mut values = vec![100.0; 729];
+        values.push(121.0);
+        let metrics = compute(100.0, &values);
+        assert_close(metrics.total_return_pct, 21.0);
+        assert_close(metrics.cagr_pct, 10.0);
+        assert_eq!(metrics.calmar(), f64::INFINITY);
+    }
+
+    #[test]
+    fn flat_series_has_zero_metrics() {
+        let metrics = compute(100.0, &[100.0, 100.0, 100.0]);
+        assert_no_nan(&metrics);
+        assert_close(metrics.total_return_pct, 0.0);
+        assert_close(metrics.cagr_pct, 0.0);
+        assert_close(metrics.max_drawdown_pct, 0.0);
+        assert_close(metrics.sharpe, 0.0);
+        assert_close(metrics.volatility_pct, 0.0);
+        assert_close(metrics.calmar(), 0.0);
+
+        assert_no_nan(&compute(0.0, &[0.0, 0.0]));
+    }
+
+    #[test]
+    fn single_point_and_empty_series() {
+        let metrics = compute(100.0, &[100.0]);
+        assert_no_nan(&metrics);
+        assert_close(metrics.sharpe, 0.0);
+
+        let metrics = compute(100.0, &[101.0]);
+        assert_no_nan(&metrics);
+        assert_close(metrics.total_return_pct, 1.0);
+        assert_close(metrics.sharpe, 0.0);
+
+        let metrics = compute(100.0, &[]);
+        assert_no_nan(&metrics);
+        assert_close(metrics.total_return_pct, 0.0);
+        assert_close(metrics.exposure_pct, 0.0);
+    }
+
+    #[test]
+    fn win_rate_against_the_average_cost() {
+        let trade = |side, amount, price| Trade {
+            date: String::new(),
+            cycle: 1,
+            order_id: String::new(),
+            side,
+            amount,
+            price,
+            btc_fee: 0.0,
+            usd_fee: 0.0,
+        };
+        // 1 coin bought at 100 on start, half sold at 120 (+10), half at 80 (-10)
+        let mut sells = SellTally::new(&Balance::new(1.0, 0.0, 100.0));
+        sells.add(&trade(Side::Sell, 0.5, 120.0));
+        let (win_rate, profit_factor) = sells.results();
+        assert_close(win_rate, 1.0);
+        assert_eq!(profit_factor, f64::INFINITY);
+
+        sells.add(&trade(Side::Sell, 0.5, 80.0));
+        let (win_rate, profit_factor) = sells.results();
+        assert_close(win_rate, 0.5);
+        assert_close(profit_factor, 1.0);
+    }
+}
+
+// eof
@@ -0,0 +1,303 @@
+use anyhow::Result;
+use axum::http::{Method, StatusCode};
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+
+use crate::{
+    candle::{Candle, Timeframe},
+    cex::kucoin::parse_candle,
+    instrument::format_decimal,
+    mock::{MockExchange, MockOrder, MockRequest, Reply, sign},
+    order::{OrderStatus, OrderType, Side},
+};
+
+pub(super) const FIXTURES: &[(&str, &str)] = &[
+    ("level1", include_str!("../../data/mock/kucoin/level1.json")),
+    ("candles", include_str!("../../data/mock/kucoin/candles.json")),
+    ("symbol", include_str!("../../data/mock/kucoin/symbol.json")),
+    ("accounts", include_str!("../../data/mock/kucoin/accounts.json")),
+];
+
+// requests which differ from the server time by more are rejected
+const TIMESTAMP_WINDOW_MS: i64 = 5_000;
+// maximum candles per request
+const CANDLES_LIMIT: usize = 1500;
+
+/// Recorded candles, the last price and the balances of the account from the fixtures
+pub(super) fn load(fixtures: &HashMap<&str, Value>) -> Result<(Vec<Candle>, f64, HashMap<String, f64>)> {
+    let rows: Vec<Vec<String>> = serde_json::from_value(fixtures["candles"]["data"].clone())?;
+    let mut candles = rows.iter().map(|row| parse_candle(row)).collect::<Result<Vec<_>>>()?;
+    candles.sort_by_key(|candle| candle.ts);
+
+    let last_price = fixtures["level1"]["data"]["price"]
+        .as_str()
+        .ok_or_else(|| anyhow::anyhow!("No price in the KuCoin level1 fixture"))?
+        .parse()?;
+
+    let accounts: Vec<Account> = serde_json::from_value(fixtures["accounts"]["data"].clone())?;
+    let mut balances = HashMap::new();
+    for account in accounts {
+        *balances.entry(account.currency).or_default() += account.available.parse::<f64>()?;
+    }
+    Ok((candles, last_price, balances))
+}
+
+pub(super) fn handle(mock: &MockExchange, req: &MockRequest) -> Reply {
+    let public = req.path == "/api/v1/timestamp"
+        || req.path.starts_with("/api/v1/market/")
+        || req.path.starts_with("/api/v2/symbols/");
+    if !public && let Err(reply) = verify(mock, req) {
+        return reply;
+    }
+
+    let path: Vec<&str> = req.path.trim_start_matches('/').split('/').collect();
+    match (&req.method, path.as_slice()) {
+        (&Method::GET, ["api", "v1", "timestamp"]) => ok(json!(Utc::now().timestamp_millis())),
+        (&Method::GET, ["api", "v1", "market", "orderbook", "level1"]) => (StatusCode::OK, mock.fixture("level1")),
+        (&Method::GET, ["api", "v1", "market", "candles"]) => candles(mock, req),
+        (&Method::GET, ["api", "v2", "symbols", _]) => (StatusCode::OK, mock.fixture("symbol")),
+        (&Method::GET, ["api", "v1", "accounts"]) => accounts(mock, req),
+        (&Method::POST, ["api", "v1", "orders"]) => place_order(mock, req),
+        (&Method::GET, ["api", "v1", "orders"]) => {
+            let account = mock.account.lock().unwrap();
+            let items: Vec<Value> = account
+                .open_orders(req.param("symbol").unwrap_or_default())
+                .map(order_details)
+                .collect();
+            ok(json!({
+                "currentPage": 1,
+                "pageSize": 500,
+                "totalNum": items.len(),
+                "totalPage": 1,
+                "items": items,
+            }))
+        }
+        (&Method::GET, ["api", "v1", "orders", id]) => {
+            let account = mock.account.lock().unwrap();
+            match account.order(id) {
+                Some(order) => ok(order_details(order)),
+                None => error(StatusCode::NOT_FOUND, "400100", "order not exist."),
+            }
+        }
+        (&Method::DELETE, ["api", "v1", "orders", id]) => {
+            if mock.account.lock().unwrap().cancel(id) {
+                ok(json!({ "cancelledOrderIds": [id] }))
+            } else {
+                error(
+                    StatusCode::BAD_REQUEST,
+                    "400100",
+                    "order_not_exist_or_not_allow_to_cancel",
+                )
+            }
+        }
+        (&Method::GET, ["api", "v1", "fills"]) => fills(mock, req),
+        _ => error(StatusCode::NOT_FOUND, "404", "Not Found"),
+    }
+}
+
+/// Checks the API key, passphrase, timestamp and signature headers of the private endpoints. Only API keys of
+/// version 2 are supported, their passphrase is signed by the secret.
+fn verify(mock: &MockExchange, req: &MockRequest) -> Result<(), Reply> {
+    if req.header("KC-API-KEY") != mock.api_key {
+        return Err(error(StatusCode::UNAUTHORIZED, "400003", "KC-API-KEY not exists"));
+    }
+    if req.header("KC-API-KEY-VERSION") != "2"
+        || req.header("KC-API-PASSPHRASE") != sign(&mock.secret, &mock.passphrase)
+    {
+        return Err(error(StatusCode::UNAUTHORIZED, "400004", "Invalid KC-API-PASSPHRASE"));
+    }
+    let ts = req.header("KC-API-TIMESTAMP");
+    match ts.parse::<i64>() {
+        Ok(ts) if (Utc::now().timestamp_millis() - ts).abs() <= TIMESTAMP_WINDOW_MS => {}
+        _ => {
+            return Err(error(
+                StatusCode::UNAUTHORIZED,
+                "400002",
+                "KC-API-TIMESTAMP Invalid -- Time differs from server time by more than 5 seconds",
+            ));
+        }
+    }
+    if req.header("KC-API-SIGN") != sign(&mock.secret, &req.prehash(ts)) {
+        return Err(error(StatusCode::UNAUTHORIZED, "400005", "Signature error"));
+    }
+    Ok(())
+}
+
+fn ok(data: Value) -> Reply {
+    (StatusCode::OK, json!({ "code": "200000", "data": data }))
+}
+
+fn error(status: StatusCode, code: &str, msg: &str) -> Reply {
+    (status, json!({ "code": code, "msg": msg }))
+}
+
+fn timeframe(candle_type: &str) -> Option<Timeframe> {
+    match candle_type {
+        "1min" => Some(Timeframe::Min1),
+        "5min" => Some(Timeframe::Min5),
+        "1hour" => Some(Timeframe::Hour1),
+        "4hour" => Some(Timeframe::Hour4),
+        "1day" => Some(Timeframe::Day1),
+        "1week" => Some(Timeframe::Week1),
+        _ => None,
+    }
+}
+
+/// Candles starting between `startAt` and `endAt` (seconds, both inclusive), newest first
+fn candles(mock: &MockExchange, req: &MockRequest) -> Reply {
+    let Some(timeframe) = req.param("type").and_then(timeframe) else {
+        return error(StatusCode::BAD_REQUEST, "400100", "type invalid");
+    };
+    let start_at: i64 = req.param("startAt").and_then(|v| v.parse().ok()).unwrap_or(0);
+    let end_at: i64 = req
+        .param("endAt")
+        .and_then(|v| v.parse().ok())
+        .filter(|end_at| *end_at > 0)
+        .unwrap_or(i64::MAX / 1000 - 1);
+    let rows: Vec<Value> = mock
+        .candles(timeframe, start_at * 1000 - 1, end_at * 1000 + 1, CANDLES_LIMIT)
+        .iter()
+        .map(|c| {
+            json!([
+                (c.ts / 1000).to_string(),
+                format_decimal(c.open),
+                format_decimal(c.close),
+                format_decimal(c.high),
+                format_decimal(c.low),
+                format_decimal(c.volume),
+                format_decimal(c.volume * c.close),
+            ])
+        })
+        .collect();
+    ok(json!(rows))
+}
+
+fn accounts(mock: &MockExchange, req: &MockRequest) -> Reply {
+    let account = mock.account.lock().unwrap();
+    let items: Vec<Value> = account
+        .balances
+        .iter()
+        .filter(|(ccy, _)| req.param("currency").is_none_or(|wanted| wanted == ccy.as_str()))
+        .map(|(ccy, balance)| {
+            json!({
+                "id": format!("mock-{ccy}"),
+                "currency": ccy,
+                "type": "trade",
+                "balance": format_decimal(*balance),
+                "available": format_decimal(*balance),
+                "holds": "0",
+            })
+        })
+        .collect();
+    ok(json!(items))
+}
+
+fn place_order(mock: &MockExchange, req: &MockRequest) -> Reply {
+    let Ok(request) = serde_json::from_str::<PlaceOrderRequest>(&req.body) else {
+        return error(StatusCode::BAD_REQUEST, "400100", "Invalid request body");
+    };
+    let side = match request.side.as_str() {
+        "buy" => Side::Buy,
+        "sell" => Side::Sell,
+        _ => return error(StatusCode::BAD_REQUEST, "400100", "side invalid"),
+    };
+    let Ok(size) = request.size.parse::<f64>() else {
+        return error(StatusCode::BAD_REQUEST, "400100", "size invalid");
+    };
+    let price = request.price.as_deref().and_then(|price| price.parse::<f64>().ok());
+    let order_type = match (request.type_.as_str(), request.time_in_force.as_deref()) {
+        ("market", _) => OrderType::Market,
+        ("limit", _) if price.is_none() => return error(StatusCode::BAD_REQUEST, "400100", "price invalid"),
+        ("limit", _) if request.post_only => OrderType::PostOnly,
+        ("limit", Some("IOC")) => OrderType::Ioc,
+        ("limit", _) => OrderType::Limit,
+        _ => return error(StatusCode::BAD_REQUEST, "400100", "type invalid"),
+    };
+
+    let mut account = mock.account.lock().unwrap();
+    match account.place(&request.symbol, side, order_type, size, price, mock.last_price) {
+        Some(order) => ok(json!({ "orderId": order.id })),
+        None => error(StatusCode::OK, "200004", "Balance insufficient!"),
+    }
+}
+
+/// Orders are filled at once at a single price, so there is at most one fill
+fn fills(mock: &MockExchange, req: &MockRequest) -> Reply {
+    let account = mock.account.lock().unwrap();
+    let items: Vec<Value> = req
+        .param("orderId")
+        .and_then(|id| account.order(id))
+        .filter(|order| order.filled > 0.0)
+        .map(|order| {
+            json!({
+                "orderId": order.id,
+                "symbol": order.symbol,
+                "side": side(order.side),
+                "price": format_decimal(order.avg_price),
+                "size": format_decimal(order.filled),
+                "funds": format_decimal(order.filled * order.avg_price),
+                "fee": format_decimal(order.fee),
+                "feeCurrency": order.fee_ccy,
+                "liquidity": "taker",
+                "createdAt": order.created,
+            })
+        })
+        .into_iter()
+        .collect();
+    ok(json!({
+        "currentPage": 1,
+        "pageSize": 500,
+        "totalNum": items.len(),
+        "totalPage": 1,
+        "items": items,
+    }))
+}
+
+fn side(side: Side) -> &'static str {
+    match side {
+        Side::Buy => "buy",
+        Side::Sell => "sell",
+    }
+}
+
+fn order_details(order: &MockOrder) -> Value {
+    json!({
+        "id": order.id,
+        "symbol": order.symbol,
+        "type": if order.order_type == OrderType::Market { "market" } else { "limit" },
+        "side": side(order.side),
+        "price": format_decimal(order.price.unwrap_or_default()),
+        "size": format_decimal(order.size),
+        "dealSize": format_decimal(order.filled),
+        "dealFunds": format_decimal(order.filled * order.avg_price),
+        "fee": format_decimal(order.fee),
+        "feeCurrency": order.fee_ccy,
+        "isActive": order.status.is_open(),
+        "cancelExist": order.status == OrderStatus::Cancelled,
+        "createdAt": order.created,
+    })
+}
+
+#[derive(Deserialize)]
+struct Account {
+    currency: String,
+    available: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PlaceOrderRequest {
+    symbol: String,
+    side: String,
+    #[serde(rename = "type")]
+    type_: String,
+    size: String,
+    price: Option<String>,
+    time_in_force: Option<String>,
+    #[serde(default)]
+    post_only: bool,
+}
+
+// eof
@@ -0,0 +1,337 @@
+use anyhow::Result;
+use axum::{
+    Json, Router,
+    extract::State,
+    http::{HeaderMap, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use base64::{Engine as _, engine::general_purpose};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use hmac::{Hmac, Mac};
+use log::{debug, info};
+use serde_json::Value;
+use sha2::Sha256;
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    candle::{Candle, Timeframe},
+    config::Config,
+    order::{OrderStatus, OrderType, Side},
+};
+
+mod kucoin;
+mod okx;
+
+// fee of the filled orders, charged in the received currency
+const FEE_RATE: f64 = 0.001;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Exchange API imitated by the mock server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Flavor {
+    Okx,
+    Kucoin,
+}
+
+/// Response of the mock, the status and the JSON body
+type Reply = (StatusCode, Value);
+
+/// Local HTTP server which imitates the REST API of the exchange over recorded fixtures. The signed requests are
+/// verified with the API key, secret and passphrase of the config, orders are matched against the ticker price and
+/// settled on the mock account.
+pub async fn run(cfg: &Config, flavor: Flavor, listen: &str, fixtures: Option<&str>) -> Result<()> {
+    let mock = Arc::new(MockExchange::new(cfg, flavor, fixtures.map(Path::new))?);
+    let app = Router::new().fallback(handle).with_state(mock);
+    let listener = tokio::net::TcpListener::bind(listen).await?;
+    info!(
+        "Mock {flavor:?} exchange listening on http://{}",
+        listener.local_addr()?
+    );
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+async fn handle(
+    State(mock): State<Arc<MockExchange>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    let req = MockRequest::new(method, &uri, headers, body);
+    let (status, body) = match mock.flavor {
+        Flavor::Okx => okx::handle(&mock, &req),
+        Flavor::Kucoin => kucoin::handle(&mock, &req),
+    };
+    debug!("{} {} -> {status}", req.method, req.path_and_query);
+    (status, Json(body)).into_response()
+}
+
+/// Request as received by the mock, the signature is verified over the raw path with the query and the body
+struct MockRequest {
+    method: Method,
+    path: String,
+    path_and_query: String,
+    query: HashMap<String, String>,
+    headers: HeaderMap,
+    body: String,
+}
+
+impl MockRequest {
+    fn new(method: Method, uri: &Uri, headers: HeaderMap, body: String) -> Self {
+        let query = uri
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        MockRequest {
+            method,
+            path: uri.path().to_string(),
+            path_and_query: uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/").to_string(),
+            query,
+            headers,
+            body,
+        }
+    }
+
+    fn param(&self, name: &str) -> Option<&str> {
+        self.query.get(name).map(String::as_str)
+    }
+
+    /// Value of the header, empty when it is missing
+    fn header(&self, name: &str) -> &str {
+        self.headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+    }
+
+    /// Prehash string of OKX and KuCoin: timestamp + method + requestPath + body
+    fn prehash(&self, timestamp: &str) -> String {
+        format!("{timestamp}{}{}{}", self.method, self.path_and_query, self.body)
+    }
+}
+
+/// Base64 encoded HMAC-SHA256, the signature of OKX and KuCoin
+fn sign(secret: &str, message: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(message.as_bytes());
+    general_purpose::STANDARD.encode(mac.finalize().into_bytes())
+}
+
+/// Fixture file of the flavor, from the directory when it is given, the built-in one otherwise
+fn fixture(dir: Option<&Path>, name: &str, builtin: &str) -> Result<Value> {
+    let content = match dir {
+        Some(dir) => std::fs::read_to_string(dir.join(format!("{name}.json")))
+            .map_err(|e| anyhow::format_err!("Failed to read fixture {name}: {e}"))?,
+        None => builtin.to_string(),
+    };
+    serde_json::from_str(&content).map_err(|e| anyhow::format_err!("Invalid fixture {name}: {e}"))
+}
+
+/// Splits the instrument like "BTC-USDT" into the base and quote currency
+fn split_symbol(symbol: &str) -> (&str, &str) {
+    symbol.split_once('-').unwrap_or((symbol, "USDT"))
+}
+
+struct MockExchange {
+    flavor: Flavor,
+    api_key: String,
+    secret: String,
+    passphrase: String,
+    fixtures: HashMap<&'static str, Value>,
+    /// Recorded candles which are replayed for any time window
+    recorded: Vec<Candle>,
+    /// Last price of the ticker fixture, the orders are matched against it
+    last_price: f64,
+    account: Mutex<Account>,
+}
+
+impl MockExchange {
+    fn new(cfg: &Config, flavor: Flavor, dir: Option<&Path>) -> Result<Self> {
+        let builtin = match flavor {
+            Flavor::Okx => okx::FIXTURES,
+            Flavor::Kucoin => kucoin::FIXTURES,
+        };
+        let fixtures = builtin
+            .iter()
+            .map(|(name, content)| Ok((*name, fixture(dir, name, content)?)))
+            .collect::<Result<HashMap<_, _>>>()?;
+        let (recorded, last_price, balances) = match flavor {
+            Flavor::Okx => okx::load(&fixtures)?,
+            Flavor::Kucoin => kucoin::load(&fixtures)?,
+        };
+        if recorded.is_empty() {
+            anyhow::bail!("No recorded candles in the fixtures");
+        }
+        Ok(MockExchange {
+            flavor,
+            api_key: cfg.cex_api_key.clone(),
+            secret: cfg.cex_api_secret.clone(),
+            passphrase: cfg.cex_api_passphrase.clone(),
+            fixtures,
+            recorded,
+            last_price,
+            account: Mutex::new(Account {
+                balances,
+                orders: Vec::new(),
+            }),
+        })
+    }
+
+    fn fixture(&self, name: &str) -> Value {
+        self.fixtures[name].clone()
+    }
+
+    /// Candles newer than `from` and older than `to` (milliseconds, both exclusive), newest first, at most
+    /// `limit`. The current bar is the newest one. The recorded candles are replayed in a loop, every bar takes the
+    /// recorded one by its position in time, so the same bar is the same on every page.
+    fn candles(&self, timeframe: Timeframe, from: i64, to: i64, limit: usize) -> Vec<Candle> {
+        let bar_ms = timeframe.duration().num_milliseconds();
+        let newest = to.min(Utc::now().timestamp_millis() + 1) - 1;
+        let mut ts = timeframe
+            .bar_start(DateTime::from_timestamp_millis(newest).unwrap_or_default())
+            .timestamp_millis();
+        let mut candles = Vec::new();
+        while ts > from && candles.len() < limit {
+            let index = (ts / bar_ms).rem_euclid(self.recorded.len() as i64) as usize;
+            candles.push(Candle {
+                ts,
+                ..self.recorded[index]
+            });
+            ts -= bar_ms;
+        }
+        candles
+    }
+}
+
+/// Balances and orders of the mock account
+struct Account {
+    balances: HashMap<String, f64>,
+    orders: Vec<MockOrder>,
+}
+
+struct MockOrder {
+    id: String,
+    symbol: String,
+    side: Side,
+    order_type: OrderType,
+    size: f64,
+    price: Option<f64>,
+    filled: f64,
+    avg_price: f64,
+    fee: f64,
+    fee_ccy: String,
+    status: OrderStatus,
+    created: i64,
+}
+
+impl Account {
+    fn balance(&self, ccy: &str) -> f64 {
+        self.balances.get(ccy).copied().unwrap_or(0.0)
+    }
+
+    /// Places the order against the last price with unlimited liquidity. Market orders and limit orders which
+    /// cross the price are filled at once, post-only orders which would cross and IOC orders which do not are
+    /// cancelled, the other limit orders rest on the book (without holding the funds). Returns `None` when the
+    /// balance is not sufficient.
+    fn place(
+        &mut self,
+        symbol: &str,
+        side: Side,
+        order_type: OrderType,
+        size: f64,
+        price: Option<f64>,
+        last_price: f64,
+    ) -> Option<&MockOrder> {
+        let (base, quote) = split_symbol(symbol);
+        let crosses = match (order_type, price) {
+            (OrderType::Market, _) | (_, None) => true,
+            (_, Some(price)) => match side {
+                Side::Buy => price >= last_price,
+                Side::Sell => price <= last_price,
+            },
+        };
+        let sufficient = match side {
+            Side::Buy => self.balance(quote) >= size * price.unwrap_or(last_price),
+            Side::Sell => self.balance(base) >= size,
+        };
+        if !sufficient {
+            return None;
+        }
+
+        let mut order = MockOrder {
+            id: (self.orders.len() as u64 + 1_000_001).to_string(),
+            symbol: symbol.to_string(),
+            side,
+            order_type,
+            size,
+            price,
+            filled: 0.0,
+            avg_price: 0.0,
+            fee: 0.0,
+            fee_ccy: match side {
+                Side::Buy => base,
+                Side::Sell => quote,
+            }
+            .to_string(),
+            status: OrderStatus::New,
+            created: Utc::now().timestamp_millis(),
+        };
+        match (order_type, crosses) {
+            (OrderType::PostOnly, true) | (OrderType::Ioc, false) => order.status = OrderStatus::Cancelled,
+            (_, true) => {
+                let value = size * last_price;
+                match side {
+                    Side::Buy => {
+                        order.fee = size * FEE_RATE;
+                        *self.balances.entry(base.to_string()).or_default() += size - order.fee;
+                        *self.balances.entry(quote.to_string()).or_default() -= value;
+                    }
+                    Side::Sell => {
+                        order.fee = value * FEE_RATE;
+                        *self.balances.entry(base.to_string()).or_default() -= size;
+                        *self.balances.entry(quote.to_string()).or_default() += value - order.fee;
+                    }
+                }
+                order.filled = size;
+                order.avg_price = last_price;
+                order.status = OrderStatus::Filled;
+            }
+            (_, false) => {}
+        }
+        self.orders.push(order);
+        self.orders.last()
+    }
+
+    fn order(&self, id: &str) -> Option<&MockOrder> {
+        self.orders.iter().find(|order| order.id == id)
+    }
+
+    /// Cancels the open order, returns `false` when there is no such open order
+    fn cancel(&mut self, id: &str) -> bool {
+        match self.orders.iter_mut().find(|order| order.id == id) {
+            Some(order) if order.status.is_open() => {
+                order.status = OrderStatus::Cancelled;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn open_orders(&self, symbol: &str) -> impl Iterator<Item = &MockOrder> {
+        self.orders
+            .iter()
+            .filter(move |order| order.symbol == symbol && order.status.is_open())
+    }
+}
+
+// eof
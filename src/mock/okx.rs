@@ -0,0 +1,312 @@
+use anyhow::Result;
+use axum::http::{Method, StatusCode};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+
+use crate::{
+    candle::{Candle, Timeframe},
+    cex::okx::parse_candle,
+    instrument::format_decimal,
+    mock::{MockExchange, MockOrder, MockRequest, Reply, sign},
+    order::{OrderStatus, OrderType, Side},
+};
+
+pub(super) const FIXTURES: &[(&str, &str)] = &[
+    ("ticker", include_str!("../../data/mock/okx/ticker.json")),
+    ("candles", include_str!("../../data/mock/okx/candles.json")),
+    ("instruments", include_str!("../../data/mock/okx/instruments.json")),
+    ("balance", include_str!("../../data/mock/okx/balance.json")),
+];
+
+// requests older than this are rejected
+const TIMESTAMP_WINDOW_MS: i64 = 30_000;
+
+/// Recorded candles, the last price and the balances of the account from the fixtures
+pub(super) fn load(fixtures: &HashMap<&str, Value>) -> Result<(Vec<Candle>, f64, HashMap<String, f64>)> {
+    let rows: Vec<Vec<String>> = serde_json::from_value(fixtures["candles"]["data"].clone())?;
+    let mut candles = rows.iter().map(|row| parse_candle(row)).collect::<Result<Vec<_>>>()?;
+    candles.sort_by_key(|candle| candle.ts);
+
+    let last_price = fixtures["ticker"]["data"][0]["last"]
+        .as_str()
+        .ok_or_else(|| anyhow::anyhow!("No last price in the OKX ticker fixture"))?
+        .parse()?;
+
+    let details: Vec<BalanceDetail> = serde_json::from_value(fixtures["balance"]["data"][0]["details"].clone())?;
+    let balances = details
+        .into_iter()
+        .map(|detail| Ok((detail.ccy, detail.avail_bal.parse()?)))
+        .collect::<Result<_>>()?;
+    Ok((candles, last_price, balances))
+}
+
+pub(super) fn handle(mock: &MockExchange, req: &MockRequest) -> Reply {
+    let public = matches!(
+        req.path.as_str(),
+        "/api/v5/public/time"
+            | "/api/v5/public/instruments"
+            | "/api/v5/market/ticker"
+            | "/api/v5/market/candles"
+            | "/api/v5/market/history-candles"
+    );
+    if !public && let Err(reply) = verify(mock, req) {
+        return reply;
+    }
+
+    match (&req.method, req.path.as_str()) {
+        (&Method::GET, "/api/v5/public/time") => ok(json!([{ "ts": Utc::now().timestamp_millis().to_string() }])),
+        (&Method::GET, "/api/v5/public/instruments") => (StatusCode::OK, mock.fixture("instruments")),
+        (&Method::GET, "/api/v5/market/ticker") => (StatusCode::OK, mock.fixture("ticker")),
+        (&Method::GET, "/api/v5/market/candles") => candles(mock, req, 300),
+        (&Method::GET, "/api/v5/market/history-candles") => candles(mock, req, 100),
+        (&Method::GET, "/api/v5/account/balance") => balance(mock, req),
+        (&Method::POST, "/api/v5/trade/order") => place_order(mock, req),
+        (&Method::GET, "/api/v5/trade/order") => {
+            let account = mock.account.lock().unwrap();
+            match req.param("ordId").and_then(|id| account.order(id)) {
+                Some(order) => ok(json!([order_details(order)])),
+                None => error(StatusCode::OK, "51603", "Order does not exist"),
+            }
+        }
+        (&Method::POST, "/api/v5/trade/cancel-order") => cancel_order(mock, req),
+        (&Method::GET, "/api/v5/trade/orders-pending") => {
+            let account = mock.account.lock().unwrap();
+            let orders: Vec<Value> = account
+                .open_orders(req.param("instId").unwrap_or_default())
+                .map(order_details)
+                .collect();
+            ok(json!(orders))
+        }
+        _ => error(StatusCode::NOT_FOUND, "404", "Not Found"),
+    }
+}
+
+/// Checks the API key, passphrase, timestamp and signature headers of the private endpoints
+fn verify(mock: &MockExchange, req: &MockRequest) -> Result<(), Reply> {
+    if req.header("OK-ACCESS-KEY") != mock.api_key {
+        return Err(error(StatusCode::UNAUTHORIZED, "50111", "Invalid OK-ACCESS-KEY"));
+    }
+    if req.header("OK-ACCESS-PASSPHRASE") != mock.passphrase {
+        return Err(error(StatusCode::UNAUTHORIZED, "50105", "Invalid OK-ACCESS-PASSPHRASE"));
+    }
+    let ts = req.header("OK-ACCESS-TIMESTAMP");
+    match DateTime::parse_from_rfc3339(ts) {
+        Ok(time) if (Utc::now().timestamp_millis() - time.timestamp_millis()).abs() <= TIMESTAMP_WINDOW_MS => {}
+        Ok(_) => return Err(error(StatusCode::UNAUTHORIZED, "50102", "Timestamp request expired")),
+        Err(_) => return Err(error(StatusCode::UNAUTHORIZED, "50112", "Invalid OK-ACCESS-TIMESTAMP")),
+    }
+    if req.header("OK-ACCESS-SIGN") != sign(&mock.secret, &req.prehash(ts)) {
+        return Err(error(StatusCode::UNAUTHORIZED, "50113", "Invalid Sign"));
+    }
+    Ok(())
+}
+
+fn ok(data: Value) -> Reply {
+    (StatusCode::OK, json!({ "code": "0", "msg": "", "data": data }))
+}
+
+fn error(status: StatusCode, code: &str, msg: &str) -> Reply {
+    (status, json!({ "code": code, "msg": msg, "data": [] }))
+}
+
+fn timeframe(bar: &str) -> Option<Timeframe> {
+    match bar {
+        "1m" => Some(Timeframe::Min1),
+        "5m" => Some(Timeframe::Min5),
+        "1H" => Some(Timeframe::Hour1),
+        "4H" => Some(Timeframe::Hour4),
+        "1D" | "1Dutc" => Some(Timeframe::Day1),
+        "1W" | "1Wutc" => Some(Timeframe::Week1),
+        _ => None,
+    }
+}
+
+/// Candles older than `after` and newer than `before`, newest first
+fn candles(mock: &MockExchange, req: &MockRequest, max_limit: usize) -> Reply {
+    let Some(timeframe) = timeframe(req.param("bar").unwrap_or("1m")) else {
+        return error(StatusCode::BAD_REQUEST, "51000", "Parameter bar error");
+    };
+    let after = req.param("after").and_then(|v| v.parse().ok()).unwrap_or(i64::MAX);
+    let before = req.param("before").and_then(|v| v.parse().ok()).unwrap_or(0);
+    let limit = req
+        .param("limit")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(100)
+        .min(max_limit);
+    let rows: Vec<Value> = mock
+        .candles(timeframe, before, after, limit)
+        .iter()
+        .map(|c| {
+            json!([
+                c.ts.to_string(),
+                format_decimal(c.open),
+                format_decimal(c.high),
+                format_decimal(c.low),
+                format_decimal(c.close),
+                format_decimal(c.volume),
+                format_decimal(c.volume * c.close),
+                format_decimal(c.volume * c.close),
+                "1"
+            ])
+        })
+        .collect();
+    ok(json!(rows))
+}
+
+fn balance(mock: &MockExchange, req: &MockRequest) -> Reply {
+    let account = mock.account.lock().unwrap();
+    let wanted: Vec<&str> = req.param("ccy").map(|ccy| ccy.split(',').collect()).unwrap_or_default();
+    let details: Vec<Value> = account
+        .balances
+        .iter()
+        .filter(|(ccy, _)| wanted.is_empty() || wanted.contains(&ccy.as_str()))
+        .map(|(ccy, balance)| {
+            json!({
+                "ccy": ccy,
+                "availBal": format_decimal(*balance),
+                "cashBal": format_decimal(*balance),
+                "frozenBal": "0",
+            })
+        })
+        .collect();
+    ok(json!([{ "uTime": Utc::now().timestamp_millis().to_string(), "details": details }]))
+}
+
+fn place_order(mock: &MockExchange, req: &MockRequest) -> Reply {
+    let Ok(request) = serde_json::from_str::<PlaceOrderRequest>(&req.body) else {
+        return error(StatusCode::BAD_REQUEST, "50002", "JSON syntax error");
+    };
+    let side = match request.side.as_str() {
+        "buy" => Side::Buy,
+        "sell" => Side::Sell,
+        _ => return error(StatusCode::OK, "51000", "Parameter side error"),
+    };
+    let order_type = match request.ord_type.as_str() {
+        "market" => OrderType::Market,
+        "limit" => OrderType::Limit,
+        "post_only" => OrderType::PostOnly,
+        "ioc" => OrderType::Ioc,
+        _ => return error(StatusCode::OK, "51000", "Parameter ordType error"),
+    };
+    let Ok(mut size) = request.sz.parse::<f64>() else {
+        return error(StatusCode::OK, "51000", "Parameter sz error");
+    };
+    let price = request.px.as_deref().and_then(|px| px.parse::<f64>().ok());
+    if order_type != OrderType::Market && price.is_none() {
+        return error(StatusCode::OK, "51000", "Parameter px error");
+    }
+    // size of spot market buy orders is in the quote currency by default
+    if order_type == OrderType::Market && side == Side::Buy && request.tgt_ccy.as_deref() != Some("base_ccy") {
+        size /= mock.last_price;
+    }
+
+    let mut account = mock.account.lock().unwrap();
+    match account.place(&request.inst_id, side, order_type, size, price, mock.last_price) {
+        Some(order) => ok(json!([{
+            "ordId": order.id,
+            "clOrdId": "",
+            "tag": "",
+            "sCode": "0",
+            "sMsg": "Order placed",
+        }])),
+        None => (
+            StatusCode::OK,
+            json!({
+                "code": "1",
+                "msg": "All operations failed",
+                "data": [{ "ordId": "", "clOrdId": "", "tag": "", "sCode": "51008", "sMsg": "Order failed. Insufficient balance." }],
+            }),
+        ),
+    }
+}
+
+fn cancel_order(mock: &MockExchange, req: &MockRequest) -> Reply {
+    let Ok(request) = serde_json::from_str::<CancelOrderRequest>(&req.body) else {
+        return error(StatusCode::BAD_REQUEST, "50002", "JSON syntax error");
+    };
+    let mut account = mock.account.lock().unwrap();
+    if account.cancel(&request.ord_id) {
+        ok(json!([{ "ordId": request.ord_id, "clOrdId": "", "sCode": "0", "sMsg": "" }]))
+    } else {
+        (
+            StatusCode::OK,
+            json!({
+                "code": "1",
+                "msg": "",
+                "data": [{
+                    "ordId": request.ord_id,
+                    "clOrdId": "",
+                    "sCode": "51400",
+                    "sMsg": "Order cancellation failed as the order has been filled, canceled or does not exist",
+                }],
+            }),
+        )
+    }
+}
+
+fn order_details(order: &MockOrder) -> Value {
+    json!({
+        "instId": order.symbol,
+        "ordId": order.id,
+        "side": match order.side {
+            Side::Buy => "buy",
+            Side::Sell => "sell",
+        },
+        "ordType": match order.order_type {
+            OrderType::Market => "market",
+            OrderType::Limit => "limit",
+            OrderType::PostOnly => "post_only",
+            OrderType::Ioc => "ioc",
+        },
+        "state": match order.status {
+            OrderStatus::New => "live",
+            OrderStatus::PartiallyFilled => "partially_filled",
+            OrderStatus::Filled => "filled",
+            OrderStatus::Cancelled | OrderStatus::Rejected => "canceled",
+        },
+        "sz": format_decimal(order.size),
+        "px": order.price.map(format_decimal).unwrap_or_default(),
+        "avgPx": if order.filled > 0.0 { format_decimal(order.avg_price) } else { String::new() },
+        "accFillSz": format_decimal(order.filled),
+        // charged fee is reported as a negative number
+        "fee": format_decimal(-order.fee),
+        "feeCcy": order.fee_ccy,
+        "cTime": order.created.to_string(),
+    })
+}
+
+#[derive(Deserialize)]
+struct BalanceDetail {
+    ccy: String,
+
+    #[serde(rename = "availBal")]
+    avail_bal: String,
+}
+
+#[derive(Deserialize)]
+struct PlaceOrderRequest {
+    #[serde(rename = "instId")]
+    inst_id: String,
+
+    side: String,
+
+    #[serde(rename = "ordType")]
+    ord_type: String,
+
+    #[serde(rename = "tgtCcy")]
+    tgt_ccy: Option<String>,
+
+    sz: String,
+
+    px: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CancelOrderRequest {
+    #[serde(rename = "ordId")]
+    ord_id: String,
+}
+
+// eof
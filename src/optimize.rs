@@ -0,0 +1,394 @@
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use clap::{Args, ValueEnum};
+use log::LevelFilter;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use std::{
+    collections::HashSet,
+    fs,
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+};
+
+use crate::{backtest, config::Config, metrics::Metrics};
+
+#[derive(Args, Debug, Clone)]
+pub struct OptimizeArgs {
+    /// TOML file with the `[params]` table, a list of values or a `{ from, to, step }` range per parameter
+    #[arg(long)]
+    pub params: String,
+    /// First simulated day, like 2021-01-01
+    #[arg(long)]
+    pub from: NaiveDate,
+    /// Last simulated day
+    #[arg(long)]
+    pub to: NaiveDate,
+    #[arg(long, default_value = "return")]
+    pub objective: Objective,
+    #[arg(long, default_value = "grid")]
+    pub search: Search,
+    /// Number of the combinations tried by the random search
+    #[arg(long, default_value_t = 100)]
+    pub samples: usize,
+    /// Seed of the random search, the same seed tries the same combinations
+    #[arg(long, default_value_t = 1)]
+    pub seed: u64,
+    /// Number of the best results printed
+    #[arg(long, default_value_t = 10)]
+    pub top: usize,
+}
+
+/// Metric the results are ranked by, the higher the better
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Objective {
+    Return,
+    Sharpe,
+    Calmar,
+}
+
+impl Objective {
+    fn score(&self, metrics: &Metrics) -> f64 {
+        let score = match self {
+            Objective::Return => metrics.total_return_pct,
+            Objective::Sharpe => metrics.sharpe,
+            Objective::Calmar => metrics.calmar(),
+        };
+        if score.is_nan() { f64::NEG_INFINITY } else { score }
+    }
+}
+
+/// How the combinations of the parameter values are chosen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Search {
+    /// Every combination
+    Grid,
+    /// `samples` distinct combinations of randomly picked values, at most every combination of the grid
+    Random,
+}
+
+/// Config parameter and the values it is tried with
+struct Param {
+    name: String,
+    values: Vec<toml::Value>,
+}
+
+/// Result of one combination, the indices of its values in the parameters
+struct Outcome {
+    combination: Vec<usize>,
+    metrics: Metrics,
+    score: f64,
+}
+
+/// Backtests the combinations of the parameter values over the days between `from` and `to`, every CPU core runs
+/// its own backtests. The other settings come from the config, the results are printed ranked by the objective.
+pub fn run(mut cfg: Config, args: OptimizeArgs) -> Result<()> {
+    let params = load_params(&args.params)?;
+    // every value is checked up front, so that a typo does not surface only after the whole run
+    for param in &params {
+        for value in &param.values {
+            cfg.clone().set_param(&param.name, value)?;
+        }
+    }
+    cfg.telegram_channel_id = 0;
+    cfg.report_file.clear();
+
+    let combinations = combinations(&params, args.search, args.samples, args.seed);
+    let threads = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(combinations.len().max(1));
+    println!(
+        "Optimize {} - {}: {} combinations of {} parameters on {threads} threads, objective {:?}",
+        args.from,
+        args.to,
+        combinations.len(),
+        params.len(),
+        args.objective
+    );
+
+    // the runs would flood the log, only their errors are kept
+    let log_level = log::max_level();
+    log::set_max_level(log_level.min(LevelFilter::Error));
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(combinations.len()));
+    thread::scope(|scope| -> Result<()> {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| -> Result<()> {
+                    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(combination) = combinations.get(index) else {
+                            return Ok(());
+                        };
+                        let mut run_cfg = cfg.clone();
+                        for (param, &value) in params.iter().zip(combination) {
+                            run_cfg.set_param(&param.name, &param.values[value])?;
+                        }
+                        let result = runtime.block_on(backtest::evaluate(run_cfg, args.from, args.to));
+
+                        let mut results = results.lock().unwrap();
+                        results.push((combination.clone(), result));
+                        eprint!("\r{}/{}", results.len(), combinations.len());
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap()?;
+        }
+        Ok(())
+    })?;
+    eprintln!();
+    log::set_max_level(log_level);
+
+    let mut outcomes = Vec::new();
+    let mut errors = Vec::new();
+    for (combination, result) in results.into_inner().unwrap() {
+        match result {
+            Ok(metrics) => outcomes.push(Outcome {
+                score: args.objective.score(&metrics),
+                combination,
+                metrics,
+            }),
+            Err(e) => errors.push(e),
+        }
+    }
+    if let Some(e) = errors.first() {
+        if outcomes.is_empty() {
+            anyhow::bail!("All combinations failed: {e:#}");
+        }
+        println!("{} combinations failed, the first one with: {e:#}", errors.len());
+    }
+
+    // the same order for the same scores, whichever thread finished first
+    outcomes.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| a.combination.cmp(&b.combination))
+    });
+    print_ranking(&params, &outcomes, args.top);
+    Ok(())
+}
+
+/// Reads the `[params]` table, parameters are the keys of the config with a list of values, an inclusive
+/// `{ from, to, step }` range or a single value
+fn load_params(path: &str) -> Result<Vec<Param>> {
+    let content = fs::read_to_string(path).with_context(|| format!("Failed to read {path}"))?;
+    let mut table: toml::Table = toml::from_str(&content).with_context(|| format!("Failed to parse {path}"))?;
+    let Some(toml::Value::Table(params)) = table.remove("params") else {
+        anyhow::bail!("Missing [params] table in {path}");
+    };
+    if params.is_empty() {
+        anyhow::bail!("No parameters in {path}");
+    }
+
+    params
+        .into_iter()
+        .map(|(name, value)| {
+            let values = param_values(&name, value)?;
+            if values.is_empty() {
+                anyhow::bail!("No values of {name}");
+            }
+            Ok(Param { name, values })
+        })
+        .collect()
+}
+
+fn param_values(name: &str, value: toml::Value) -> Result<Vec<toml::Value>> {
+    let range = match value {
+        toml::Value::Array(values) => return Ok(values),
+        toml::Value::Table(range) => range,
+        value => return Ok(vec![value]),
+    };
+    let bound = |key: &str| {
+        range
+            .get(key)
+            .with_context(|| format!("Missing {key} in the range of {name}"))
+    };
+    let (from, to, step) = (bound("from")?, bound("to")?, bound("step")?);
+
+    // integer bounds give integer values, which fit both the integer and the float parameters
+    if let (Some(from), Some(to), Some(step)) = (from.as_integer(), to.as_integer(), step.as_integer()) {
+        if step <= 0 {
+            anyhow::bail!("Step of {name} must be positive");
+        }
+        if to < from {
+            anyhow::bail!("Range of {name}: `to` {to} is below `from` {from}");
+        }
+        return Ok((from..=to).step_by(step as usize).map(toml::Value::Integer).collect());
+    }
+
+    let number = |value: &toml::Value| {
+        value
+            .as_float()
+            .or(value.as_integer().map(|i| i as f64))
+            .with_context(|| format!("Range of {name} is not numeric"))
+    };
+    let (from, to, step) = (number(from)?, number(to)?, number(step)?);
+    if step <= 0.0 {
+        anyhow::bail!("Step of {name} must be positive");
+    }
+    if to < from {
+        anyhow::bail!("Range of {name}: `to` {to} is below `from` {from}");
+    }
+    // every value is computed from the start and rounded, so that 0.1 steps do not drift
+    let count = ((to - from) / step + 1e-9).floor() as usize + 1;
+    Ok((0..count)
+        .map(|i| toml::Value::Float(((from + i as f64 * step) * 1e9).round() / 1e9))
+        .collect())
+}
+
+/// Combinations as the indices of the values, one per parameter
+fn combinations(params: &[Param], search: Search, samples: usize, seed: u64) -> Vec<Vec<usize>> {
+    match search {
+        Search::Grid => {
+            let total: usize = params.iter().map(|param| param.values.len()).product();
+            (0..total)
+                .map(|mut index| {
+                    params
+                        .iter()
+                        .map(|param| {
+                            let value = index % param.values.len();
+                            index /= param.values.len();
+                            value
+                        })
+                        .collect()
+                })
+                .collect()
+        }
+        Search::Random => {
+            // repeated draws are skipped, there cannot be more distinct combinations than in the grid
+            let total = params
+                .iter()
+                .fold(1usize, |total, param| total.saturating_mul(param.values.len()));
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut seen = HashSet::new();
+            let mut combinations = Vec::new();
+            while combinations.len() < samples.min(total) {
+                let combination: Vec<usize> = params
+                    .iter()
+                    .map(|param| rng.random_range(0..param.values.len()))
+                    .collect();
+                if seen.insert(combination.clone()) {
+                    combinations.push(combination);
+                }
+            }
+            combinations
+        }
+    }
+}
+
+fn print_ranking(params: &[Param], outcomes: &[Outcome], top: usize) {
+    println!(
+        "{:>4} {:>10} {:>9} {:>9} {:>7} {:>7} {:>6}  Parameters",
+        "Rank", "Return", "CAGR", "Max DD", "Sharpe", "Calmar", "Trades"
+    );
+    for (rank, outcome) in outcomes.iter().take(top).enumerate() {
+        let metrics = &outcome.metrics;
+        let values: Vec<String> = params
+            .iter()
+            .zip(&outcome.combination)
+            .map(|(param, &value)| format!("{}={}", param.name, param.values[value]))
+            .collect();
+        println!(
+            "{:>4} {:>9.2}% {:>8.2}% {:>8.2}% {:>7.2} {:>7.2} {:>6}  {}",
+            rank + 1,
+            metrics.total_return_pct,
+            metrics.cagr_pct,
+            metrics.max_drawdown_pct,
+            metrics.sharpe,
+            metrics.calmar(),
+            metrics.trades,
+            values.join(" ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(value: &str) -> Result<Vec<toml::Value>> {
+        let table: toml::Table = toml::from_str(&format!("p = {value}")).unwrap();
+        param_values("p", table["p"].clone())
+    }
+
+    fn floats(value: &str) -> Vec<f64> {
+        values(value).unwrap().iter().map(|v| v.as_float().unwrap()).collect()
+    }
+
+    fn params(counts: &[usize]) -> Vec<Param> {
+        counts
+            .iter()
+            .map(|&count| Param {
+                name: String::new(),
+                values: (0..count as i64).map(toml::Value::Integer).collect(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn integer_range() {
+        let ints = |value| -> Vec<i64> { values(value).unwrap().iter().map(|v| v.as_integer().unwrap()).collect() };
+        assert_eq!(ints("{ from = 10, to = 30, step = 10 }"), vec![10, 20, 30]);
+        assert_eq!(ints("{ from = 1, to = 6, step = 2 }"), vec![1, 3, 5]);
+        assert_eq!(ints("{ from = 5, to = 5, step = 1 }"), vec![5]);
+        assert_eq!(ints("[3, 1]"), vec![3, 1]);
+        assert_eq!(ints("7"), vec![7]);
+    }
+
+    #[test]
+    fn float_range_is_rounded() {
+        assert_eq!(
+            floats("{ from = 0.1, to = 0.5, step = 0.1 }"),
+            vec![0.1, 0.2, 0.3, 0.4, 0.5]
+        );
+        assert_eq!(
+            floats("{ from = 0, to = 1, step = 0.25 }"),
+            vec![0.0, 0.25, 0.5, 0.75, 1.0]
+        );
+        assert_eq!(floats("{ from = 1.0, to = 1.25, step = 0.1 }"), vec![1.0, 1.1, 1.2]);
+    }
+
+    #[test]
+    fn invalid_ranges() {
+        let error = |value| values(value).unwrap_err().to_string();
+        assert!(error("{ from = 5, to = 1, step = 1 }").contains("`to` 1 is below `from` 5"));
+        assert!(error("{ from = 0.5, to = 0.1, step = 0.1 }").contains("`to` 0.1 is below `from` 0.5"));
+        assert!(error("{ from = 1, to = 5, step = 0 }").contains("must be positive"));
+        assert!(error("{ from = 0.1, to = 0.5, step = -0.1 }").contains("must be positive"));
+        assert!(error("{ from = 1, to = 5 }").contains("Missing step"));
+        assert!(error("{ from = 1, to = 5, step = \"x\" }").contains("not numeric"));
+    }
+
+    #[test]
+    fn grid_has_every_combination_once() {
+        let grid = combinations(&params(&[2, 3]), Search::Grid, 0, 0);
+        assert_eq!(
+            grid,
+            vec![vec![0, 0], vec![1, 0], vec![0, 1], vec![1, 1], vec![0, 2], vec![1, 2]]
+        );
+    }
+
+    #[test]
+    fn random_combinations_are_distinct() {
+        let params = params(&[3, 4, 5]);
+        let random = combinations(&params, Search::Random, 20, 7);
+        assert_eq!(random.len(), 20);
+        assert_eq!(random.iter().collect::<HashSet<_>>().len(), 20);
+        assert!(random.iter().all(|c| c[0] < 3 && c[1] < 4 && c[2] < 5));
+        assert_eq!(random, combinations(&params, Search::Random, 20, 7));
+    }
+
+    #[test]
+    fn random_combinations_capped_at_the_grid() {
+        let random = combinations(&params(&[2, 3]), Search::Random, 100, 1);
+        assert_eq!(random.len(), 6);
+        assert_eq!(random.iter().collect::<HashSet<_>>().len(), 6);
+    }
+}
+
+// eof
@@ -1,12 +1,104 @@
-#[derive(Debug)]
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::instrument::format_decimal;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Side {
     Buy,
     Sell,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, ValueEnum)]
+pub enum OrderType {
+    /// Executed immediately at the best available price
+    #[default]
+    Market,
+    /// Rests on the order book until it is filled at the price or better
+    Limit,
+    /// Limit order which is rejected instead of taking liquidity
+    PostOnly,
+    /// Immediate-or-cancel limit order, the unfilled part is cancelled
+    Ioc,
+}
+
+impl OrderType {
+    /// Returns the limit price, which is required for all but market orders
+    pub fn limit_price(&self, price: Option<f64>) -> Result<Option<f64>> {
+        match self {
+            OrderType::Market => Ok(None),
+            _ => match price {
+                Some(price) if price > 0.0 => Ok(Some(price)),
+                _ => Err(anyhow::format_err!("{self:?} order requires a price")),
+            },
+        }
+    }
+}
+
+/// State of an order on the exchange
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrderStatus {
+    /// Accepted and resting on the order book, nothing is filled yet
+    New,
+    /// Resting on the order book, part of it is filled
+    PartiallyFilled,
+    Filled,
+    /// Cancelled by the user or expired, it may have been partially filled before
+    Cancelled,
+    Rejected,
+}
+
+impl OrderStatus {
+    /// Order can still be filled
+    pub fn is_open(&self) -> bool {
+        matches!(self, OrderStatus::New | OrderStatus::PartiallyFilled)
+    }
+}
+
+/// Order as reported by the exchange
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Order {
+    pub id: String,
+    pub side: Side,
+    pub status: OrderStatus,
+    /// Ordered amount of the coin
+    pub amount: f64,
+    /// Limit price, `None` for market orders
+    pub price: Option<f64>,
+    pub filled_amount: f64,
+    /// Average price of the fills, 0 when nothing is filled
+    pub avg_price: f64,
+    pub btc_fee: f64,
+    pub usd_fee: f64,
+}
+
+impl Order {
+    /// Status derived from the filled amount of an order which is still on the book
+    pub fn open_status(amount: f64, filled_amount: f64) -> OrderStatus {
+        if filled_amount <= 0.0 {
+            OrderStatus::New
+        } else if filled_amount < amount {
+            OrderStatus::PartiallyFilled
+        } else {
+            OrderStatus::Filled
+        }
+    }
+
+    pub fn response(&self) -> OrderResponse {
+        OrderResponse {
+            order_id: self.id.clone(),
+            executed_price: self.avg_price,
+            executed_amount: self.filled_amount,
+            btc_fee: self.btc_fee,
+            usd_fee: self.usd_fee,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct OrderResponse {
-    //pub order_id: String,
+    pub order_id: String,
     pub executed_price: f64,
     pub executed_amount: f64,
     //pub amount: f64,
@@ -31,12 +123,31 @@ pub async fn put_order(
 }
 */
 
+impl OrderResponse {
+    /// Order which was placed with this response, used to track the part which is still resting on the book
+    pub fn to_order(&self, side: Side, amount: f64, price: Option<f64>) -> Order {
+        Order {
+            id: self.order_id.clone(),
+            side,
+            status: Order::open_status(amount, self.executed_amount),
+            amount,
+            price,
+            filled_amount: self.executed_amount,
+            avg_price: self.executed_price,
+            btc_fee: self.btc_fee,
+            usd_fee: self.usd_fee,
+        }
+    }
+}
+
 impl std::fmt::Display for OrderResponse {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "{:.2} USD. Fees: fee: {:.8}, {:.2} USD",
-            self.executed_price, self.btc_fee, self.usd_fee
+            "{}. Fees: {:.8} base, {:.8} quote",
+            format_decimal(self.executed_price),
+            self.btc_fee,
+            self.usd_fee
         )
     }
 }
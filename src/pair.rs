@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+use crate::coin::Coin;
+
+/// Traded pair, the base currency is bought and sold for the quote currency. Written like "ETH-BTC" in the config,
+/// every exchange client maps it to its own symbol format.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Pair {
+    pub base: String,
+    pub quote: String,
+}
+
+impl Pair {
+    pub fn new(base: &str, quote: &str) -> Self {
+        Pair {
+            base: base.to_uppercase(),
+            quote: quote.to_uppercase(),
+        }
+    }
+
+    /// Symbol of the pair with the separator between the currencies, e.g. "ETH-BTC" or "ETHBTC"
+    pub fn join(&self, separator: &str) -> String {
+        format!("{}{separator}{}", self.base, self.quote)
+    }
+}
+
+/// The coin traded against USDT
+impl From<&Coin> for Pair {
+    fn from(coin: &Coin) -> Self {
+        Pair::new(coin.symbol(), "USDT")
+    }
+}
+
+impl FromStr for Pair {
+    type Err = String;
+
+    /// Parses "BASE-QUOTE", "/" and "_" are accepted as the separator too
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (base, quote) = s
+            .trim()
+            .split_once(['-', '/', '_'])
+            .ok_or_else(|| format!("Invalid pair {s}, expected BASE-QUOTE like ETH-BTC"))?;
+        let valid = |currency: &str| !currency.is_empty() && currency.chars().all(|c| c.is_ascii_alphanumeric());
+        if !valid(base) || !valid(quote) {
+            return Err(format!("Invalid pair {s}, expected BASE-QUOTE like ETH-BTC"));
+        }
+        Ok(Pair::new(base, quote))
+    }
+}
+
+impl TryFrom<String> for Pair {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Pair> for String {
+    fn from(pair: Pair) -> Self {
+        pair.to_string()
+    }
+}
+
+impl fmt::Display for Pair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.base, self.quote)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_with_any_separator() {
+        let expected = Pair::new("BTC", "USDT");
+        for s in ["BTC-USDT", "BTC/USDT", "BTC_USDT", "btc-usdt", " BTC-USDT "] {
+            assert_eq!(s.parse::<Pair>(), Ok(expected.clone()), "{s}");
+        }
+        assert_eq!(Pair::from(&Coin::Ethereum), Pair::new("ETH", "USDT"));
+    }
+
+    #[test]
+    fn parse_rejects_invalid_pairs() {
+        for s in ["", "BTCUSDT", "BTC-", "-USDT", "BTC-US DT", "BTC-USDT-EUR", "BTC--USDT"] {
+            assert!(s.parse::<Pair>().is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn symbol_formats() {
+        let pair = Pair::new("btc", "usdt");
+        assert_eq!(pair.to_string(), "BTC-USDT");
+        assert_eq!(pair.join("-"), "BTC-USDT");
+        assert_eq!(pair.join(""), "BTCUSDT");
+    }
+
+    #[test]
+    fn serialized_as_string() {
+        let pair = Pair::new("ETH", "BTC");
+        assert_eq!(serde_json::to_string(&pair).unwrap(), "\"ETH-BTC\"");
+        assert_eq!(serde_json::from_str::<Pair>("\"eth/btc\"").unwrap(), pair);
+        assert!(serde_json::from_str::<Pair>("\"ETHBTC\"").is_err());
+    }
+}
+
+// eof
@@ -0,0 +1,387 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{fmt::Write as _, fs, path::Path};
+
+use crate::{
+    balance::Balance,
+    benchmark::Benchmark,
+    candle::Timeframe,
+    metrics::{Metrics, SellTally, serialize_ratio},
+    order::{Order, Side},
+};
+
+/// Most trades listed in the journal, the older ones are only summed up in [`EarlierTrades`]
+const TRADES_LIMIT: usize = 10_000;
+
+/// Order filled during the run, or the part of a resting order filled since the last cycle
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Trade {
+    /// Day of the cycle, yyyy-mm-dd
+    pub date: String,
+    pub cycle: u32,
+    pub order_id: String,
+    pub side: Side,
+    pub amount: f64,
+    pub price: f64,
+    pub btc_fee: f64,
+    pub usd_fee: f64,
+}
+
+impl Trade {
+    /// Filled part of the order
+    pub fn from_order(cycle: u32, order: &Order) -> Self {
+        Trade {
+            date: String::new(),
+            cycle,
+            order_id: order.id.clone(),
+            side: order.side,
+            amount: order.filled_amount,
+            price: order.avg_price,
+            btc_fee: order.btc_fee.abs(),
+            usd_fee: order.usd_fee.abs(),
+        }
+    }
+}
+
+/// Portfolio at the end of a cycle. The set aside and performance fee amounts are the totals up to the cycle, the
+/// equity is the value of the balances and the set aside amounts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EquityPoint {
+    pub date: String,
+    pub cycle: u32,
+    pub price: f64,
+    pub btc_balance: f64,
+    pub usd_balance: f64,
+    pub set_aside_btc: f64,
+    pub set_aside_usd: f64,
+    pub perf_fee_btc: f64,
+    pub perf_fee_usd: f64,
+    pub equity_btc: f64,
+    pub equity_usd: f64,
+}
+
+impl EquityPoint {
+    pub fn new(
+        date: String,
+        cycle: u32,
+        price: f64,
+        btc_balance: f64,
+        usd_balance: f64,
+        set_aside: (f64, f64),
+        perf_fee: (f64, f64),
+    ) -> Self {
+        let equity_btc = btc_balance + set_aside.0 + (usd_balance + set_aside.1) / price;
+        EquityPoint {
+            date,
+            cycle,
+            price,
+            btc_balance,
+            usd_balance,
+            set_aside_btc: set_aside.0,
+            set_aside_usd: set_aside.1,
+            perf_fee_btc: perf_fee.0,
+            perf_fee_usd: perf_fee.1,
+            equity_btc,
+            equity_usd: equity_btc * price,
+        }
+    }
+}
+
+/// Trades dropped from the journal over the limit, summed up so that the metrics and the summary still cover the
+/// whole run
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct EarlierTrades {
+    pub count: usize,
+    pub btc_fee: f64,
+    pub usd_fee: f64,
+    /// Cost basis and sell results after the dropped trades
+    pub sells: SellTally,
+}
+
+/// Trades and the equity curve collected while the engine runs, kept in the state file so that the metrics and the
+/// report survive a restart. Only the last trades are listed, the equity curve is complete.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Journal {
+    trades: Vec<Trade>,
+    equity: Vec<EquityPoint>,
+    /// Missing in older state files and until the trades reach the limit
+    #[serde(default)]
+    earlier: Option<EarlierTrades>,
+}
+
+impl Journal {
+    pub fn record_trade(&mut self, trade: Trade) {
+        self.trades.push(trade);
+    }
+
+    /// Closes the cycle, the trades recorded in it get its date. The trades over the limit are rolled up, their
+    /// cost basis starts from the `initial` balance.
+    pub fn record_day(&mut self, point: EquityPoint, initial: &Balance) {
+        for trade in self.trades.iter_mut().filter(|trade| trade.cycle == point.cycle) {
+            trade.date = point.date.clone();
+        }
+        self.equity.push(point);
+
+        if self.trades.len() > TRADES_LIMIT {
+            let earlier = self.earlier.get_or_insert_with(|| EarlierTrades {
+                count: 0,
+                btc_fee: 0.0,
+                usd_fee: 0.0,
+                sells: SellTally::new(initial),
+            });
+            for trade in self.trades.drain(..self.trades.len() - TRADES_LIMIT) {
+                earlier.count += 1;
+                earlier.btc_fee += trade.btc_fee;
+                earlier.usd_fee += trade.usd_fee;
+                earlier.sells.add(&trade);
+            }
+        }
+    }
+
+    /// Replaces the last point, used for the final settlement after the last cycle
+    pub fn amend_last_day(&mut self, point: EquityPoint) {
+        self.equity.pop();
+        self.equity.push(point);
+    }
+
+    pub fn last_day(&self) -> Option<&EquityPoint> {
+        self.equity.last()
+    }
+
+    pub fn trades(&self) -> &[Trade] {
+        &self.trades
+    }
+
+    pub fn equity(&self) -> &[EquityPoint] {
+        &self.equity
+    }
+
+    /// Metrics of the whole run, the rolled up trades included
+    pub fn metrics(&self, initial: &Balance, timeframe: Timeframe) -> Metrics {
+        Metrics::compute(initial, &self.equity, &self.trades, self.earlier.as_ref(), timeframe)
+    }
+
+    /// Report of the run, `None` when no cycle was recorded
+    pub fn into_report(
+        self,
+        pair: String,
+        cex: String,
+        initial: &Balance,
+        metrics: Metrics,
+        benchmarks: Vec<Benchmark>,
+    ) -> Option<Report> {
+        let last = self.equity.last()?;
+        let summary = Summary::new(initial, last, &self.trades, self.earlier.as_ref(), metrics);
+        Some(Report {
+            pair,
+            cex,
+            summary,
+            benchmarks,
+            trades: self.trades,
+            equity: self.equity,
+        })
+    }
+}
+
+/// Totals and metrics of the run. The final balances include the set aside amounts like the overall evaluation.
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    pub initial_btc: f64,
+    pub initial_usd: f64,
+    pub initial_price: f64,
+    pub final_btc: f64,
+    pub final_usd: f64,
+    pub final_price: f64,
+    pub return_btc_pct: f64,
+    pub return_usd_pct: f64,
+    pub initial_equity_usd: f64,
+    pub final_equity_usd: f64,
+    pub fees_btc: f64,
+    pub fees_usd: f64,
+    pub perf_fee_btc: f64,
+    pub perf_fee_usd: f64,
+    pub set_aside_btc: f64,
+    pub set_aside_usd: f64,
+    #[serde(flatten)]
+    pub metrics: Metrics,
+    /// CAGR divided by the max drawdown, "inf" without a drawdown
+    #[serde(serialize_with = "serialize_ratio")]
+    pub calmar: f64,
+}
+
+impl Summary {
+    fn new(
+        initial: &Balance,
+        last: &EquityPoint,
+        trades: &[Trade],
+        earlier: Option<&EarlierTrades>,
+        metrics: Metrics,
+    ) -> Self {
+        let initial_price = initial._btc_price;
+        let final_btc = last.btc_balance + last.set_aside_btc;
+        let final_usd = last.usd_balance + last.set_aside_usd;
+        let initial_equity_usd = initial.btc_balance * initial_price + initial.usd_balance;
+        Summary {
+            initial_btc: initial.btc_balance,
+            initial_usd: initial.usd_balance,
+            initial_price,
+            final_btc,
+            final_usd,
+            final_price: last.price,
+            return_btc_pct: pct(initial.btc_balance, final_btc),
+            return_usd_pct: pct(initial.usd_balance, final_usd),
+            initial_equity_usd,
+            final_equity_usd: last.equity_usd,
+            fees_btc: trades.iter().map(|trade| trade.btc_fee).sum::<f64>() + earlier.map_or(0.0, |e| e.btc_fee),
+            fees_usd: trades.iter().map(|trade| trade.usd_fee).sum::<f64>() + earlier.map_or(0.0, |e| e.usd_fee),
+            perf_fee_btc: last.perf_fee_btc,
+            perf_fee_usd: last.perf_fee_usd,
+            set_aside_btc: last.set_aside_btc,
+            set_aside_usd: last.set_aside_usd,
+            calmar: metrics.calmar(),
+            metrics,
+        }
+    }
+}
+
+fn pct(initial: f64, current: f64) -> f64 {
+    (current - initial) / initial * 100.0
+}
+
+/// Report of the simulation run, written as JSON or CSV
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub pair: String,
+    pub cex: String,
+    pub summary: Summary,
+    pub benchmarks: Vec<Benchmark>,
+    pub trades: Vec<Trade>,
+    pub equity: Vec<EquityPoint>,
+}
+
+impl Report {
+    /// Writes JSON, or CSV when the path ends with `.csv`. The CSV file holds the equity curve, the trades, the
+    /// summary and the benchmarks are written next to it as `<name>_trades.csv`, `<name>_summary.csv` and
+    /// `<name>_benchmarks.csv`.
+    pub fn write(&self, path: &str) -> Result<()> {
+        let path = Path::new(path);
+        if !path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("csv")) {
+            let content = serde_json::to_string_pretty(self)?;
+            return fs::write(path, content).with_context(|| format!("Failed to write report {}", path.display()));
+        }
+
+        let sibling = |suffix: &str| {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            path.with_file_name(format!("{stem}_{suffix}.csv"))
+        };
+        write_csv(path, &self.equity)?;
+        write_csv(&sibling("trades"), &self.trades)?;
+        write_csv(&sibling("summary"), std::slice::from_ref(&self.summary))?;
+        write_csv(&sibling("benchmarks"), &self.benchmarks)?;
+        Ok(())
+    }
+}
+
+/// Writes the rows with the field names as the header, the rows must serialize to flat JSON objects
+fn write_csv<T: Serialize>(path: &Path, rows: &[T]) -> Result<()> {
+    let mut content = String::new();
+    for (i, row) in rows.iter().enumerate() {
+        let serde_json::Value::Object(fields) = serde_json::to_value(row)? else {
+            anyhow::bail!("CSV row is not an object");
+        };
+        if i == 0 {
+            let header: Vec<String> = fields.keys().map(|key| csv_field(key)).collect();
+            writeln!(content, "{}", header.join(","))?;
+        }
+        let values: Vec<String> = fields
+            .values()
+            .map(|value| match value {
+                serde_json::Value::String(s) => csv_field(s),
+                other => other.to_string(),
+            })
+            .collect();
+        writeln!(content, "{}", values.join(","))?;
+    }
+    fs::write(path, content).with_context(|| format!("Failed to write report {}", path.display()))
+}
+
+/// Field quoted when it contains a separator, a quote or a line break, the quotes inside are doubled (RFC 4180)
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rolled_up_trades_keep_the_metrics() {
+        let initial = Balance::new(1.0, 1000.0, 100.0);
+        let mut journal = Journal::default();
+        let (mut equity, mut trades) = (Vec::new(), Vec::new());
+        let days = TRADES_LIMIT as u32 + 50;
+        for cycle in 1..=days {
+            let price = 100.0 + (cycle % 30) as f64 - (cycle / 1000) as f64;
+            let trade = Trade {
+                date: String::new(),
+                cycle,
+                order_id: cycle.to_string(),
+                side: if cycle % 2 == 0 { Side::Sell } else { Side::Buy },
+                amount: 0.01,
+                price,
+                btc_fee: 0.0,
+                usd_fee: 0.01,
+            };
+            journal.record_trade(trade.clone());
+            trades.push(trade);
+            let point = EquityPoint::new(String::new(), cycle, price, 1.0, 1000.0, (0.0, 0.0), (0.0, 0.0));
+            journal.record_day(point.clone(), &initial);
+            equity.push(point);
+        }
+        assert_eq!(journal.trades().len(), TRADES_LIMIT);
+        assert_eq!(journal.equity().len(), days as usize);
+
+        let expected = Metrics::compute(&initial, &equity, &trades, None, Timeframe::Day1);
+        let metrics = journal.metrics(&initial, Timeframe::Day1);
+        assert_eq!(metrics.total_return_pct, expected.total_return_pct);
+        assert_eq!(metrics.cagr_pct, expected.cagr_pct);
+        assert_eq!(metrics.max_drawdown_pct, expected.max_drawdown_pct);
+        assert_eq!(metrics.win_rate_pct, expected.win_rate_pct);
+        assert_eq!(metrics.profit_factor, expected.profit_factor);
+        assert_eq!(metrics.trades, days as usize);
+
+        let report = journal.into_report(String::new(), String::new(), &initial, metrics, Vec::new());
+        let summary = report.unwrap().summary;
+        assert!((summary.fees_usd - 0.01 * days as f64).abs() < 1e-6);
+    }
+
+    #[test]
+    fn infinite_ratios_written_as_inf() {
+        let initial = Balance::new(0.0, 1000.0, 100.0);
+        let last = EquityPoint::new(String::new(), 1, 100.0, 0.0, 1100.0, (0.0, 0.0), (0.0, 0.0));
+        let metrics = Metrics {
+            cagr_pct: 10.0,
+            profit_factor: f64::INFINITY,
+            ..Default::default()
+        };
+        let summary = serde_json::to_value(Summary::new(&initial, &last, &[], None, metrics)).unwrap();
+        assert_eq!(summary["profit_factor"], "inf");
+        assert_eq!(summary["calmar"], "inf");
+
+        let metrics = Metrics {
+            profit_factor: 1.5,
+            max_drawdown_pct: 20.0,
+            cagr_pct: 10.0,
+            ..Default::default()
+        };
+        let summary = serde_json::to_value(Summary::new(&initial, &last, &[], None, metrics)).unwrap();
+        assert_eq!(summary["profit_factor"], 1.5);
+        assert_eq!(summary["calmar"], 0.5);
+    }
+}
+
+// eof
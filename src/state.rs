@@ -0,0 +1,162 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{fs, io::Write, path::Path};
+
+use crate::{
+    balance::Balance,
+    coin::Coin,
+    config::Config,
+    fee::PerfFeeTracker,
+    order::{Order, Side},
+    pair::Pair,
+    report::Journal,
+};
+
+/// Version of the state file format, bump it on incompatible changes
+pub const STATE_VERSION: u32 = 1;
+
+/// Bot state persisted between restarts
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BotState {
+    pub version: u32,
+    /// Traded pair, missing in older state files
+    #[serde(default)]
+    pub pair: Option<Pair>,
+    /// Coin traded against USDT, written by the older versions instead of the pair
+    #[serde(default, skip_serializing)]
+    pub coin: Option<Coin>,
+    pub cex: String,
+
+    pub cycle_count: u32,
+
+    pub initial_balance: BalanceState,
+    pub balance: BalanceState,
+
+    pub total_take_profit_btc: f64,
+    pub total_take_profit_usd: f64,
+
+    pub perf_fee: PerfFeeState,
+
+    /// Limit orders resting on the order book, missing in older state files
+    #[serde(default)]
+    pub pending_orders: Vec<Order>,
+
+    /// Last entry watched by the price feed, missing in older state files
+    #[serde(default)]
+    pub position: Option<Position>,
+
+    /// Trades and equity curve of the run so far, missing in older state files
+    #[serde(default)]
+    pub journal: Journal,
+}
+
+/// Last filled trade, the streamed stop-loss and take-profit levels are measured from its price and closing it
+/// sells what was bought or buys back what was sold
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub side: Side,
+    pub price: f64,
+    pub amount: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BalanceState {
+    pub btc: f64,
+    pub usd: f64,
+    pub price: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PerfFeeState {
+    pub high_water_mark_btc: f64,
+    pub high_water_mark_usd: f64,
+    pub total_fee_btc: f64,
+    pub total_fee_usd: f64,
+}
+
+impl From<&Balance> for BalanceState {
+    fn from(balance: &Balance) -> Self {
+        BalanceState {
+            btc: balance.btc_balance,
+            usd: balance.usd_balance,
+            price: balance._btc_price,
+        }
+    }
+}
+
+impl From<&BalanceState> for Balance {
+    fn from(state: &BalanceState) -> Self {
+        Balance::new(state.btc, state.usd, state.price)
+    }
+}
+
+impl From<&PerfFeeTracker> for PerfFeeState {
+    fn from(tracker: &PerfFeeTracker) -> Self {
+        PerfFeeState {
+            high_water_mark_btc: tracker.high_water_mark_btc,
+            high_water_mark_usd: tracker.high_water_mark_usd,
+            total_fee_btc: tracker.total_fee_btc,
+            total_fee_usd: tracker.total_fee_usd,
+        }
+    }
+}
+
+impl PerfFeeState {
+    pub fn restore(&self, tracker: &mut PerfFeeTracker) {
+        tracker.high_water_mark_btc = self.high_water_mark_btc;
+        tracker.high_water_mark_usd = self.high_water_mark_usd;
+        tracker.total_fee_btc = self.total_fee_btc;
+        tracker.total_fee_usd = self.total_fee_usd;
+    }
+}
+
+impl BotState {
+    /// Loads the state from `path`. Returns `None` when the file does not exist yet.
+    pub fn load(path: &str) -> Result<Option<Self>> {
+        if !Path::new(path).exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(path).with_context(|| format!("Failed to read state file {path}"))?;
+        let state: BotState =
+            serde_json::from_str(&content).with_context(|| format!("Failed to parse state file {path}"))?;
+        if state.version != STATE_VERSION {
+            anyhow::bail!(
+                "Unsupported state file version {} in {path} (expected {STATE_VERSION})",
+                state.version
+            );
+        }
+        Ok(Some(state))
+    }
+
+    /// Checks that the state was written for the same pair and exchange as configured
+    pub fn validate(&self, cfg: &Config) -> Result<()> {
+        let pair = self
+            .pair
+            .clone()
+            .or_else(|| self.coin.as_ref().map(Pair::from))
+            .ok_or_else(|| anyhow::anyhow!("State file {} has no traded pair", cfg.state_file))?;
+        if pair != cfg.pair() || !self.cex.eq_ignore_ascii_case(&cfg.cex) {
+            anyhow::bail!(
+                "State file {} was written for {pair} on {}, but the config uses {} on {}. Remove the file or fix the config.",
+                cfg.state_file,
+                self.cex,
+                cfg.pair(),
+                cfg.cex
+            );
+        }
+        Ok(())
+    }
+
+    /// Writes the state atomically: into a temporary file next to `path` which then replaces it
+    pub fn save(&self, path: &str) -> Result<()> {
+        let tmp = format!("{path}.tmp");
+        let content = serde_json::to_string_pretty(self)?;
+        let mut file = fs::File::create(&tmp).with_context(|| format!("Failed to create state file {tmp}"))?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, path).with_context(|| format!("Failed to replace state file {path}"))?;
+        Ok(())
+    }
+}
+
+// eof
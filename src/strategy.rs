@@ -3,6 +3,7 @@ use clap::ValueEnum;
 use serde::Deserialize;
 
 use crate::{
+    candle::{self, Candle},
     config::Config,
     signal::{Signal, Signals},
 };
@@ -217,10 +218,11 @@ pub fn partial_take_profit_strategy(prices: &[f64], tp_pct: f64, buyback_pct: f6
     signals.last().cloned().unwrap_or(Signal::Hold)
 }
 
-pub fn generate_signals(cfg: &Config, historical: &[f64], weights: StrategyWeights) -> Result<Signals> {
-    if historical.is_empty() {
+pub fn generate_signals(cfg: &Config, candles: &[Candle], weights: StrategyWeights) -> Result<Signals> {
+    if candles.is_empty() {
         anyhow::bail!("No historical data provided");
     }
+    let historical = &candle::prices(candles, cfg.price_field);
 
     let ema_signal: Option<Signal> = if cfg.strategy == Strategy::Ema || weights.ema > 0.0 {
         Some(ema_crossover_strategy(historical, cfg.ema_short, cfg.ema_long))